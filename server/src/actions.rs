@@ -0,0 +1,73 @@
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    mqtta::{
+        message::{qos_from_u8, PublishMessage},
+        MqttHandle,
+    },
+    scenes::{Scene, Scenes},
+};
+
+/// Something the server does on behalf of a schedule, rule or timer
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub(crate) enum Action {
+    Publish {
+        topic: String,
+        value: String,
+        #[serde(default)]
+        qos: u8,
+        #[serde(default)]
+        retain: bool,
+    },
+    Scene {
+        scene: String,
+    },
+}
+
+impl Action {
+    pub(crate) fn validate(&self, scenes: &Scenes) -> Result<(), String> {
+        match self {
+            Action::Publish { topic, .. } if !rumqttc::valid_topic(topic) || topic.is_empty() => {
+                Err(format!("Invalid topic {topic}"))
+            }
+            Action::Scene { scene } if scenes.get(scene).is_none() => {
+                Err(format!("Unknown scene {scene}"))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Topics the action publishes to
+    pub(crate) fn topics<'a>(&'a self, scenes: &'a Scenes) -> Vec<&'a str> {
+        match self {
+            Action::Publish { topic, .. } => vec![topic.as_str()],
+            Action::Scene { scene } => scenes.get(scene).map(Scene::topics).unwrap_or_default(),
+        }
+    }
+
+    /// Execute the action, returns `OK` on success
+    pub(crate) async fn run(&self, mqtt: &MqttHandle, scenes: &Scenes) -> String {
+        match self {
+            Action::Publish {
+                topic,
+                value,
+                qos,
+                retain,
+            } => {
+                let payload = PublishMessage::builder()
+                    .topic(topic.clone())
+                    .value(value.clone().into_bytes())
+                    .qos(qos_from_u8(*qos))
+                    .retain(*retain)
+                    .build();
+                mqtt.publish(payload).await
+            }
+            Action::Scene { scene } => match scenes.activate(mqtt, scene).await {
+                Some(steps) if steps.iter().all(|s| s.ok()) => String::from("OK"),
+                Some(_) => String::from("Error"),
+                None => String::from("Unknown scene"),
+            },
+        }
+    }
+}
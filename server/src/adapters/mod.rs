@@ -0,0 +1,233 @@
+mod shelly;
+mod tasmota;
+
+use std::{
+    collections::{BTreeMap, HashSet},
+    env,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+};
+
+use color_eyre::eyre::{eyre, Context, Result};
+use rumqttc::QoS;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use tokio::{
+    sync::{mpsc, oneshot, RwLock},
+    task::JoinHandle,
+};
+use tracing::{debug, info, warn};
+
+use crate::mqtta::{
+    message::{ActorMessage, IncomingMessage, PublishMessage},
+    MqttHandle,
+};
+
+#[derive(Clone, Copy, Debug, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum Vendor {
+    /// `cmnd/`, `stat/` and `tele/` topics
+    Tasmota,
+    /// Shelly Gen2 RPC over MQTT
+    Shelly,
+}
+
+#[derive(Clone, Deserialize)]
+struct AdapterConfig {
+    id: String,
+    name: Option<String>,
+    vendor: Vendor,
+    /// Tasmota topic or Shelly topic prefix
+    topic: String,
+}
+
+/// Vendor independent state of a device
+#[derive(Clone, Debug, Serialize)]
+pub(crate) struct AdapterState {
+    id: String,
+    name: Option<String>,
+    vendor: Vendor,
+    online: Option<bool>,
+    /// Relay states, numbered from 1
+    relays: BTreeMap<u8, bool>,
+    /// Numeric readings like `ENERGY.Power` or `switch:0.apower`
+    sensors: BTreeMap<String, f64>,
+    /// Time of the last state message in milliseconds since the unix epoch
+    updated: Option<u64>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum RelayAction {
+    On,
+    Off,
+    Toggle,
+}
+
+fn default_relay() -> u8 {
+    1
+}
+
+/// Vendor independent command, e.g. `{"action":"toggle","relay":1}`
+#[derive(Debug, Deserialize)]
+pub(crate) struct RelayCommand {
+    action: RelayAction,
+    #[serde(default = "default_relay")]
+    relay: u8,
+}
+
+/// Collect all numeric leaves of a JSON document with dotted keys
+fn flatten_numbers(prefix: &str, value: &Value, out: &mut BTreeMap<String, f64>) {
+    match value {
+        Value::Number(n) => {
+            if let Some(n) = n.as_f64() {
+                out.insert(prefix.to_string(), n);
+            }
+        }
+        Value::Object(map) => {
+            for (key, value) in map {
+                let key = if prefix.is_empty() {
+                    key.clone()
+                } else {
+                    format!("{prefix}.{key}")
+                };
+                flatten_numbers(&key, value, out);
+            }
+        }
+        _ => {}
+    }
+}
+
+/// Devices handled through vendor adapters
+#[derive(Clone, Default)]
+pub(crate) struct AdapterRegistry {
+    configs: Arc<Vec<AdapterConfig>>,
+    states: Arc<RwLock<BTreeMap<String, AdapterState>>>,
+    rpc_id: Arc<AtomicU64>,
+}
+
+impl AdapterRegistry {
+    pub(crate) async fn list(&self) -> Vec<AdapterState> {
+        self.states.read().await.values().cloned().collect()
+    }
+
+    pub(crate) async fn get(&self, id: &str) -> Option<AdapterState> {
+        self.states.read().await.get(id).cloned()
+    }
+
+    /// Build the vendor specific message for a command.
+    pub(crate) fn command(&self, id: &str, command: &RelayCommand) -> Option<PublishMessage> {
+        let config = self.configs.iter().find(|c| c.id == id)?;
+        let relay = command.relay.max(1);
+        let (topic, value) = match config.vendor {
+            Vendor::Tasmota => tasmota::command(&config.topic, relay, &command.action),
+            Vendor::Shelly => shelly::command(
+                &config.topic,
+                relay,
+                &command.action,
+                self.rpc_id.fetch_add(1, Ordering::Relaxed) + 1,
+            ),
+        };
+        Some(
+            PublishMessage::builder()
+                .topic(topic)
+                .value(value.into_bytes())
+                .qos(QoS::AtLeastOnce)
+                .retain(false)
+                .build(),
+        )
+    }
+
+    async fn apply(&self, config: &AdapterConfig, message: &IncomingMessage) {
+        let mut states = self.states.write().await;
+        let Some(state) = states.get_mut(&config.id) else {
+            return;
+        };
+        match config.vendor {
+            Vendor::Tasmota => tasmota::apply(&config.topic, state, message),
+            Vendor::Shelly => shelly::apply(&config.topic, state, message),
+        }
+    }
+}
+
+fn adapter_configs_from_env() -> Result<Vec<AdapterConfig>> {
+    let Ok(path) = env::var("HCS_ADAPTERS_CONFIG") else {
+        return Ok(Vec::new());
+    };
+    if path.is_empty() {
+        return Ok(Vec::new());
+    }
+    let content = std::fs::read_to_string(&path)
+        .wrap_err_with(|| format!("Cannot read adapter configuration {path}"))?;
+    let configs = serde_json::from_str::<Vec<AdapterConfig>>(&content)
+        .wrap_err_with(|| format!("Invalid adapter configuration {path}"))?;
+    let mut ids = HashSet::new();
+    for config in &configs {
+        if !ids.insert(config.id.as_str()) {
+            return Err(eyre!("Duplicate adapter device id {}", config.id));
+        }
+    }
+    Ok(configs)
+}
+
+/// Start following the topics of all devices from `HCS_ADAPTERS_CONFIG`.
+pub(crate) fn run_adapters(mqtt: MqttHandle) -> Result<(AdapterRegistry, Vec<JoinHandle<()>>)> {
+    let configs = adapter_configs_from_env()?;
+    let states = configs
+        .iter()
+        .map(|c| {
+            (
+                c.id.clone(),
+                AdapterState {
+                    id: c.id.clone(),
+                    name: c.name.clone(),
+                    vendor: c.vendor,
+                    online: None,
+                    relays: BTreeMap::new(),
+                    sensors: BTreeMap::new(),
+                    updated: None,
+                },
+            )
+        })
+        .collect();
+    let registry = AdapterRegistry {
+        configs: Arc::new(configs),
+        states: Arc::new(RwLock::new(states)),
+        rpc_id: Default::default(),
+    };
+    if !registry.configs.is_empty() {
+        info!(devices = registry.configs.len(), "Device adapters enabled");
+    }
+
+    let mut tasks = Vec::new();
+    for config in registry.configs.iter() {
+        let filters = match config.vendor {
+            Vendor::Tasmota => tasmota::filters(&config.topic),
+            Vendor::Shelly => shelly::filters(&config.topic),
+        };
+        for filter in filters {
+            let mqtt = mqtt.clone();
+            let registry = registry.clone();
+            let config = config.clone();
+            tasks.push(tokio::spawn(async move {
+                let (tx, rx) = oneshot::channel::<mpsc::Receiver<Arc<IncomingMessage>>>();
+                mqtt.send(ActorMessage::Stream {
+                    filter: filter.clone(),
+                    respond_to: tx,
+                })
+                .await;
+                let Ok(mut messages) = rx.await else {
+                    warn!(filter, "Could not subscribe adapter topics");
+                    return;
+                };
+                while let Some(message) = messages.recv().await {
+                    registry.apply(&config, &message).await;
+                }
+                debug!(id = config.id, "Adapter stopped");
+            }));
+        }
+    }
+    Ok((registry, tasks))
+}
@@ -0,0 +1,207 @@
+use serde_json::{json, Value};
+
+use super::{flatten_numbers, AdapterState, RelayAction};
+use crate::mqtta::message::IncomingMessage;
+
+/// Source announced in RPC requests, responses would go to `<src>/rpc`
+const RPC_SOURCE: &str = "homecontrol";
+
+pub(super) fn filters(prefix: &str) -> Vec<String> {
+    vec![format!("{prefix}/#")]
+}
+
+/// Relay number of a `switch:<id>` component, ids start at 0
+fn relay_of(component: &str) -> Option<u8> {
+    component
+        .strip_prefix("switch:")?
+        .parse::<u8>()
+        .ok()?
+        .checked_add(1)
+}
+
+fn apply_component(state: &mut AdapterState, component: &str, status: &Value) {
+    if let Some(relay) = relay_of(component) {
+        if let Some(on) = status.get("output").and_then(Value::as_bool) {
+            state.relays.insert(relay, on);
+        }
+    }
+    flatten_numbers(component, status, &mut state.sensors);
+}
+
+pub(super) fn apply(prefix: &str, state: &mut AdapterState, message: &IncomingMessage) {
+    let Some(key) = message
+        .topic
+        .strip_prefix(prefix)
+        .and_then(|r| r.strip_prefix('/'))
+    else {
+        return;
+    };
+    let text = String::from_utf8_lossy(&message.payload);
+
+    if key == "online" {
+        state.online = Some(text.trim() == "true");
+    } else if let Some(component) = key.strip_prefix("status/") {
+        let Ok(status) = serde_json::from_str::<Value>(&text) else {
+            return;
+        };
+        apply_component(state, component, &status);
+    } else if key == "events/rpc" {
+        let Ok(event) = serde_json::from_str::<Value>(&text) else {
+            return;
+        };
+        let method = event.get("method").and_then(Value::as_str);
+        if !matches!(method, Some("NotifyStatus") | Some("NotifyFullStatus")) {
+            return;
+        }
+        if let Some(Value::Object(params)) = event.get("params") {
+            for (component, status) in params {
+                if status.is_object() {
+                    apply_component(state, component, status);
+                }
+            }
+        }
+    } else {
+        return;
+    }
+    state.updated = Some(message.ts);
+}
+
+pub(super) fn command(prefix: &str, relay: u8, action: &RelayAction, id: u64) -> (String, String) {
+    let switch_id = relay.saturating_sub(1);
+    let (method, params) = match action {
+        RelayAction::On => ("Switch.Set", json!({ "id": switch_id, "on": true })),
+        RelayAction::Off => ("Switch.Set", json!({ "id": switch_id, "on": false })),
+        RelayAction::Toggle => ("Switch.Toggle", json!({ "id": switch_id })),
+    };
+    (
+        format!("{prefix}/rpc"),
+        json!({
+            "id": id,
+            "src": RPC_SOURCE,
+            "method": method,
+            "params": params,
+        })
+        .to_string(),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use super::*;
+    use crate::adapters::Vendor;
+
+    fn state() -> AdapterState {
+        AdapterState {
+            id: String::from("relay"),
+            name: None,
+            vendor: Vendor::Shelly,
+            online: None,
+            relays: BTreeMap::new(),
+            sensors: BTreeMap::new(),
+            updated: None,
+        }
+    }
+
+    fn message(topic: &str, payload: &str) -> IncomingMessage {
+        IncomingMessage {
+            topic: topic.to_string(),
+            payload: payload.as_bytes().to_vec(),
+            ts: 42,
+        }
+    }
+
+    #[test]
+    fn relay_numbers() {
+        assert_eq!(relay_of("switch:0"), Some(1));
+        assert_eq!(relay_of("switch:3"), Some(4));
+        assert_eq!(relay_of("switch:255"), None);
+        assert_eq!(relay_of("switch:x"), None);
+        assert_eq!(relay_of("input:0"), None);
+    }
+
+    #[test]
+    fn status_payloads() {
+        let mut state = state();
+        let status = r#"{"id":0,"output":true,"apower":8.5}"#;
+        apply(
+            "shelly",
+            &mut state,
+            &message("shelly/status/switch:0", status),
+        );
+        apply("shelly", &mut state, &message("shelly/online", "true"));
+        assert_eq!(state.relays, BTreeMap::from([(1, true)]));
+        assert_eq!(state.sensors.get("switch:0.apower"), Some(&8.5));
+        assert_eq!(state.online, Some(true));
+        assert_eq!(state.updated, Some(42));
+
+        // readings of other components are kept, they are no relays
+        apply(
+            "shelly",
+            &mut state,
+            &message("shelly/status/input:0", r#"{"state":true}"#),
+        );
+        apply(
+            "shelly",
+            &mut state,
+            &message("shelly/status/switch:x", r#"{"output":false}"#),
+        );
+        assert_eq!(state.relays, BTreeMap::from([(1, true)]));
+    }
+
+    #[test]
+    fn rpc_events() {
+        let mut state = state();
+        let event = r#"{"method":"NotifyStatus","params":{"ts":1.5,"switch:1":{"output":true}}}"#;
+        apply("shelly", &mut state, &message("shelly/events/rpc", event));
+        assert_eq!(state.relays, BTreeMap::from([(2, true)]));
+
+        let other = r#"{"method":"NotifyEvent","params":{"switch:1":{"output":false}}}"#;
+        apply("shelly", &mut state, &message("shelly/events/rpc", other));
+        assert_eq!(state.relays.get(&2), Some(&true));
+    }
+
+    #[test]
+    fn malformed_payloads() {
+        let mut state = state();
+        apply(
+            "shelly",
+            &mut state,
+            &message("shelly/status/switch:0", "{\"output\":"),
+        );
+        apply(
+            "shelly",
+            &mut state,
+            &message("shelly/events/rpc", "NotifyStatus"),
+        );
+        apply(
+            "shelly",
+            &mut state,
+            &message("other/status/switch:0", r#"{"output":true}"#),
+        );
+        assert!(state.relays.is_empty());
+        assert_eq!(state.updated, None);
+    }
+
+    #[test]
+    fn commands() {
+        let (topic, payload) = command("shelly", 1, &RelayAction::On, 7);
+        assert_eq!(topic, "shelly/rpc");
+        let payload: Value = serde_json::from_str(&payload).unwrap();
+        assert_eq!(
+            payload,
+            json!({
+                "id": 7,
+                "src": RPC_SOURCE,
+                "method": "Switch.Set",
+                "params": { "id": 0, "on": true },
+            })
+        );
+
+        let (_, payload) = command("shelly", 2, &RelayAction::Toggle, 8);
+        let payload: Value = serde_json::from_str(&payload).unwrap();
+        assert_eq!(payload["method"], "Switch.Toggle");
+        assert_eq!(payload["params"], json!({ "id": 1 }));
+    }
+}
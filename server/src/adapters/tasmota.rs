@@ -0,0 +1,183 @@
+use serde_json::Value;
+
+use super::{flatten_numbers, AdapterState, RelayAction};
+use crate::mqtta::message::IncomingMessage;
+
+pub(super) fn filters(topic: &str) -> Vec<String> {
+    vec![format!("stat/{topic}/#"), format!("tele/{topic}/#")]
+}
+
+/// Relay number of a `POWER` or `POWER<n>` key, `POWER` is relay 1
+fn relay_of(key: &str) -> Option<u8> {
+    let n = key.strip_prefix("POWER")?;
+    if n.is_empty() {
+        Some(1)
+    } else {
+        n.parse().ok()
+    }
+}
+
+fn power_state(value: &str) -> Option<bool> {
+    match value.to_uppercase().as_str() {
+        "ON" | "1" => Some(true),
+        "OFF" | "0" => Some(false),
+        _ => None,
+    }
+}
+
+fn apply_power_keys(state: &mut AdapterState, payload: &Value) {
+    if let Value::Object(map) = payload {
+        for (key, value) in map {
+            if let (Some(relay), Some(on)) = (relay_of(key), value.as_str().and_then(power_state)) {
+                state.relays.insert(relay, on);
+            }
+        }
+    }
+}
+
+pub(super) fn apply(topic: &str, state: &mut AdapterState, message: &IncomingMessage) {
+    let text = String::from_utf8_lossy(&message.payload);
+    let (prefix, rest) = match message.topic.split_once('/') {
+        Some(parts) => parts,
+        None => return,
+    };
+    let Some(key) = rest.strip_prefix(topic).and_then(|r| r.strip_prefix('/')) else {
+        return;
+    };
+
+    match (prefix, key) {
+        ("tele", "LWT") => state.online = Some(text.eq_ignore_ascii_case("online")),
+        ("tele", "STATE") | ("stat", "RESULT") | ("stat", "STATUS11") => {
+            if let Ok(payload) = serde_json::from_str::<Value>(&text) {
+                let payload = payload.get("StatusSTS").unwrap_or(&payload);
+                apply_power_keys(state, payload);
+            }
+        }
+        ("tele", "SENSOR") | ("stat", "STATUS8") | ("stat", "STATUS10") => {
+            if let Ok(payload) = serde_json::from_str::<Value>(&text) {
+                let payload = payload.get("StatusSNS").unwrap_or(&payload);
+                flatten_numbers("", payload, &mut state.sensors);
+            }
+        }
+        ("stat", key) => {
+            if let (Some(relay), Some(on)) = (relay_of(key), power_state(&text)) {
+                state.relays.insert(relay, on);
+            }
+        }
+        _ => return,
+    }
+    state.updated = Some(message.ts);
+}
+
+pub(super) fn command(topic: &str, relay: u8, action: &RelayAction) -> (String, String) {
+    let payload = match action {
+        RelayAction::On => "ON",
+        RelayAction::Off => "OFF",
+        RelayAction::Toggle => "TOGGLE",
+    };
+    (format!("cmnd/{topic}/POWER{relay}"), payload.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use super::*;
+    use crate::adapters::Vendor;
+
+    fn state() -> AdapterState {
+        AdapterState {
+            id: String::from("plug"),
+            name: None,
+            vendor: Vendor::Tasmota,
+            online: None,
+            relays: BTreeMap::new(),
+            sensors: BTreeMap::new(),
+            updated: None,
+        }
+    }
+
+    fn message(topic: &str, payload: &str) -> IncomingMessage {
+        IncomingMessage {
+            topic: topic.to_string(),
+            payload: payload.as_bytes().to_vec(),
+            ts: 42,
+        }
+    }
+
+    #[test]
+    fn relay_numbers() {
+        assert_eq!(relay_of("POWER"), Some(1));
+        assert_eq!(relay_of("POWER2"), Some(2));
+        assert_eq!(relay_of("POWERX"), None);
+        assert_eq!(relay_of("POWER256"), None);
+        assert_eq!(relay_of("Dimmer"), None);
+    }
+
+    #[test]
+    fn power_topics() {
+        let mut state = state();
+        apply("plug", &mut state, &message("stat/plug/POWER", "ON"));
+        apply("plug", &mut state, &message("stat/plug/POWER2", "off"));
+        assert_eq!(state.relays, BTreeMap::from([(1, true), (2, false)]));
+        assert_eq!(state.updated, Some(42));
+
+        let mut state = self::state();
+        apply("plug", &mut state, &message("stat/plug/POWER3", "BLINK"));
+        apply("plug", &mut state, &message("stat/other/POWER", "ON"));
+        assert!(state.relays.is_empty());
+    }
+
+    #[test]
+    fn state_payloads() {
+        let mut state = state();
+        let payload = r#"{"Time":"2024-01-01T00:00:00","POWER1":"ON","POWER2":"OFF","Dimmer":40}"#;
+        apply("plug", &mut state, &message("tele/plug/STATE", payload));
+        assert_eq!(state.relays, BTreeMap::from([(1, true), (2, false)]));
+
+        let status = r#"{"StatusSTS":{"POWER":"OFF"}}"#;
+        apply("plug", &mut state, &message("stat/plug/STATUS11", status));
+        assert_eq!(state.relays.get(&1), Some(&false));
+
+        apply(
+            "plug",
+            &mut state,
+            &message("tele/plug/SENSOR", r#"{"ENERGY":{"Power":12.5}}"#),
+        );
+        assert_eq!(state.sensors.get("ENERGY.Power"), Some(&12.5));
+    }
+
+    #[test]
+    fn malformed_payloads() {
+        let mut state = state();
+        apply(
+            "plug",
+            &mut state,
+            &message("tele/plug/STATE", "{\"POWER\":"),
+        );
+        apply(
+            "plug",
+            &mut state,
+            &message("tele/plug/STATE", r#"["POWER","ON"]"#),
+        );
+        apply("plug", &mut state, &message("tele/plug/SENSOR", "not json"));
+        assert!(state.relays.is_empty());
+        assert!(state.sensors.is_empty());
+
+        apply("plug", &mut state, &message("tele/plug/LWT", "Offline"));
+        assert_eq!(state.online, Some(false));
+    }
+
+    #[test]
+    fn commands() {
+        assert_eq!(
+            command("plug", 1, &RelayAction::On),
+            (String::from("cmnd/plug/POWER1"), String::from("ON"))
+        );
+        assert_eq!(
+            command("plug", 3, &RelayAction::Toggle),
+            (String::from("cmnd/plug/POWER3"), String::from("TOGGLE"))
+        );
+        assert_eq!(command("plug", 2, &RelayAction::Off).1, "OFF");
+    }
+}
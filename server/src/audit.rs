@@ -0,0 +1,596 @@
+use std::{
+    convert::Infallible,
+    env,
+    fs::{self, File, OpenOptions},
+    future::Future,
+    io::{self, BufWriter, Read, Seek, SeekFrom, Write},
+    net::IpAddr,
+    path::{Path, PathBuf},
+    sync::Arc,
+    thread,
+};
+
+use axum::{
+    async_trait,
+    extract::{FromRef, FromRequestParts},
+    http::{request::Parts, StatusCode},
+};
+use color_eyre::eyre::{eyre, Context, Result};
+use jwt_authorizer::JwtClaims;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use tokio::sync::mpsc;
+use tracing::{debug, warn};
+
+use crate::{
+    datadir::{data_dir, now_millis},
+    http::{claims::Claims, client::ClientInfo},
+    mqtta::message::PublishMessage,
+};
+
+const AUDIT_FILE: &str = "audit.jsonl";
+const REDACTED: &str = "[redacted]";
+/// Size of `audit.jsonl` in megabytes before it is rotated
+const DEFAULT_ROTATE_SIZE: u64 = 10;
+/// Rotated files kept as `audit.1.jsonl` (newest) to `audit.N.jsonl`
+const DEFAULT_KEEP_FILES: usize = 5;
+/// Bytes read at a time when reading a file from the end
+const CHUNK: u64 = 64 * 1024;
+
+/// One control action taken through the API
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub(crate) struct AuditRecord {
+    /// Milliseconds since the unix epoch
+    pub(crate) ts: u64,
+    /// `sub` claim of the token
+    pub(crate) user: Option<String>,
+    /// `name` or `preferred_username` claim of the token
+    pub(crate) name: Option<String>,
+    pub(crate) ip: IpAddr,
+    /// `http` or `https` as seen by the client, missing in older records
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) scheme: Option<String>,
+    /// e.g. `publish`, `scene.activate` or `rule.delete`
+    pub(crate) action: String,
+    /// Id of the scene, rule, device, ... acted on
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) target: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) topic: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) payload: Option<String>,
+    /// `OK` or what went wrong
+    pub(crate) result: String,
+}
+
+/// Filters and page of [`AuditLog::query`], all optional
+#[derive(Debug, Default, Deserialize)]
+pub(crate) struct AuditQuery {
+    /// `sub` or name of the user
+    pub(crate) user: Option<String>,
+    pub(crate) action: Option<String>,
+    pub(crate) target: Option<String>,
+    /// Topic filter, wildcards allowed
+    pub(crate) topic: Option<String>,
+    pub(crate) ip: Option<IpAddr>,
+    pub(crate) result: Option<String>,
+    /// Start of the range in milliseconds since the unix epoch
+    pub(crate) from: Option<u64>,
+    /// End of the range in milliseconds since the unix epoch
+    pub(crate) to: Option<u64>,
+    /// Matching records to skip, newest first
+    pub(crate) offset: Option<usize>,
+    pub(crate) limit: Option<usize>,
+}
+
+impl AuditQuery {
+    fn matches(&self, record: &AuditRecord) -> bool {
+        let user = |u: &String| record.user.as_ref() == Some(u) || record.name.as_ref() == Some(u);
+        self.user.as_ref().is_none_or(user)
+            && self.action.as_ref().is_none_or(|a| &record.action == a)
+            && self
+                .target
+                .as_ref()
+                .is_none_or(|t| record.target.as_ref() == Some(t))
+            && self.topic.as_ref().is_none_or(|f| {
+                record
+                    .topic
+                    .as_ref()
+                    .is_some_and(|t| rumqttc::matches(t, f))
+            })
+            && self.ip.is_none_or(|ip| record.ip == ip)
+            && self.result.as_ref().is_none_or(|r| &record.result == r)
+            && self.from.is_none_or(|from| record.ts >= from)
+            && self.to.is_none_or(|to| record.ts < to)
+    }
+}
+
+/// Path of the current file for `index` 0, rotated files count up from 1
+fn audit_file(dir: &Path, index: usize) -> PathBuf {
+    match index {
+        0 => dir.join(AUDIT_FILE),
+        n => dir.join(format!("audit.{n}.jsonl")),
+    }
+}
+
+fn number_from_env<T: std::str::FromStr>(name: &str, default: T) -> Result<T> {
+    match env::var(name) {
+        Ok(value) if !value.is_empty() => value
+            .parse()
+            .map_err(|_| eyre!("Invalid number {value} in {name}")),
+        _ => Ok(default),
+    }
+}
+
+/// Owner of the current file, runs on its own thread so writes never block
+/// the runtime
+struct Writer {
+    dir: PathBuf,
+    file: BufWriter<File>,
+    size: u64,
+    rotate_size: u64,
+    keep: usize,
+}
+
+impl Writer {
+    fn open(dir: &Path) -> io::Result<(File, u64)> {
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(audit_file(dir, 0))?;
+        let size = file.metadata()?.len();
+        Ok((file, size))
+    }
+
+    fn run(mut self, mut records: mpsc::UnboundedReceiver<AuditRecord>) {
+        debug!("Audit writer started");
+        while let Some(record) = records.blocking_recv() {
+            self.write(&record);
+            // flush once nothing else is waiting
+            while let Ok(record) = records.try_recv() {
+                self.write(&record);
+            }
+            if let Err(e) = self.file.flush() {
+                warn!("Cannot write audit log: {:?}", e);
+            }
+        }
+        debug!("Audit writer stopped");
+    }
+
+    fn write(&mut self, record: &AuditRecord) {
+        let mut line = match serde_json::to_vec(record) {
+            Ok(line) => line,
+            Err(e) => {
+                warn!("Cannot serialize audit record: {:?}", e);
+                return;
+            }
+        };
+        line.push(b'\n');
+        if let Err(e) = self.file.write_all(&line) {
+            warn!("Cannot write audit log: {:?}", e);
+            return;
+        }
+        self.size += line.len() as u64;
+        if self.size >= self.rotate_size {
+            if let Err(e) = self.rotate() {
+                warn!("Cannot rotate audit log: {:?}", e);
+            }
+        }
+    }
+
+    /// Shift `audit.N.jsonl` to `audit.N+1.jsonl`, dropping the oldest, and
+    /// start a new current file
+    fn rotate(&mut self) -> io::Result<()> {
+        self.file.flush()?;
+        if self.keep == 0 {
+            fs::remove_file(audit_file(&self.dir, 0))?;
+        } else {
+            for index in (0..self.keep).rev() {
+                let from = audit_file(&self.dir, index);
+                if from.exists() {
+                    fs::rename(&from, audit_file(&self.dir, index + 1))?;
+                }
+            }
+        }
+        let (file, size) = Self::open(&self.dir)?;
+        self.file = BufWriter::new(file);
+        self.size = size;
+        debug!("Audit log rotated");
+        Ok(())
+    }
+}
+
+/// Lines of a file from the last to the first
+struct ReverseLines {
+    file: File,
+    /// Start of the part of the file not read yet
+    pos: u64,
+    /// Read but not returned, ends before the lines already returned
+    buf: Vec<u8>,
+}
+
+impl ReverseLines {
+    fn new(file: File) -> io::Result<Self> {
+        let pos = file.metadata()?.len();
+        Ok(Self {
+            file,
+            pos,
+            buf: Vec::new(),
+        })
+    }
+}
+
+impl Iterator for ReverseLines {
+    type Item = io::Result<Vec<u8>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(i) = self.buf.iter().rposition(|b| *b == b'\n') {
+                let line = self.buf.split_off(i + 1);
+                self.buf.truncate(i);
+                if line.is_empty() {
+                    continue;
+                }
+                return Some(Ok(line));
+            }
+            if self.pos == 0 {
+                return (!self.buf.is_empty()).then(|| Ok(std::mem::take(&mut self.buf)));
+            }
+            let read = CHUNK.min(self.pos);
+            self.pos -= read;
+            let mut chunk = vec![0; read as usize];
+            if let Err(e) = self
+                .file
+                .seek(SeekFrom::Start(self.pos))
+                .and_then(|_| self.file.read_exact(&mut chunk))
+            {
+                return Some(Err(e));
+            }
+            chunk.append(&mut self.buf);
+            self.buf = chunk;
+        }
+    }
+}
+
+/// Append-only log of the control actions in `audit.jsonl` in the data
+/// directory, one JSON record per line. The file is rotated by size, older
+/// records are in `audit.1.jsonl` and up.
+#[derive(Clone)]
+pub(crate) struct AuditLog {
+    dir: PathBuf,
+    keep: usize,
+    records: mpsc::UnboundedSender<AuditRecord>,
+    /// Topic filters from `HCS_AUDIT_REDACT_TOPICS` whose payloads are not
+    /// written
+    redact: Arc<Vec<String>>,
+}
+
+impl AuditLog {
+    /// Read `HCS_AUDIT_REDACT_TOPICS`, `HCS_AUDIT_ROTATE_SIZE` and
+    /// `HCS_AUDIT_KEEP_FILES` and start the writer.
+    pub(crate) fn from_env() -> Result<Self> {
+        let redact: Vec<String> = env::var("HCS_AUDIT_REDACT_TOPICS")
+            .unwrap_or_default()
+            .split(',')
+            .map(|f| f.trim().to_string())
+            .filter(|f| !f.is_empty())
+            .collect();
+        if let Some(f) = redact.iter().find(|f| !rumqttc::valid_filter(f)) {
+            return Err(eyre!("Invalid topic filter {f} in HCS_AUDIT_REDACT_TOPICS"));
+        }
+        let rotate_size = number_from_env("HCS_AUDIT_ROTATE_SIZE", DEFAULT_ROTATE_SIZE)?;
+        if rotate_size == 0 {
+            return Err(eyre!("HCS_AUDIT_ROTATE_SIZE must be at least 1"));
+        }
+        let keep = number_from_env("HCS_AUDIT_KEEP_FILES", DEFAULT_KEEP_FILES)?;
+        let dir = data_dir()?;
+        let (file, size) = Writer::open(&dir)
+            .wrap_err_with(|| format!("Cannot open audit log {}", audit_file(&dir, 0).display()))?;
+        let (records, rx) = mpsc::unbounded_channel();
+        let writer = Writer {
+            dir: dir.clone(),
+            file: BufWriter::new(file),
+            size,
+            rotate_size: rotate_size * 1024 * 1024,
+            keep,
+        };
+        thread::Builder::new()
+            .name("audit-writer".to_string())
+            .spawn(move || writer.run(rx))
+            .wrap_err("Cannot start audit writer")?;
+        Ok(Self {
+            dir,
+            keep,
+            records,
+            redact: Arc::new(redact),
+        })
+    }
+
+    fn redacted(&self, topic: &str) -> bool {
+        self.redact.iter().any(|f| rumqttc::matches(topic, f))
+    }
+
+    /// Replace the `value` of every object in `definition` whose `topic` is
+    /// redacted, e.g. the publish actions of timers, schedules and rules
+    fn redact_values(&self, definition: &mut Value) {
+        match definition {
+            Value::Object(object) => {
+                let redacted = object
+                    .get("topic")
+                    .and_then(Value::as_str)
+                    .is_some_and(|topic| self.redacted(topic));
+                if redacted && object.contains_key("value") {
+                    object.insert("value".to_string(), REDACTED.into());
+                }
+                object.values_mut().for_each(|v| self.redact_values(v));
+            }
+            Value::Array(array) => array.iter_mut().for_each(|v| self.redact_values(v)),
+            _ => {}
+        }
+    }
+
+    fn append(&self, mut record: AuditRecord) {
+        if record.topic.as_deref().is_some_and(|t| self.redacted(t)) {
+            record.payload = record.payload.map(|_| REDACTED.to_string());
+        }
+        if self.records.send(record).is_err() {
+            warn!("Audit writer stopped, record lost");
+        }
+    }
+
+    /// Up to `limit` records matching `query`, newest first, and whether
+    /// more follow. Files are read from the end and only as far as needed.
+    pub(crate) async fn query(
+        &self,
+        query: AuditQuery,
+        limit: usize,
+    ) -> Result<(Vec<AuditRecord>, bool)> {
+        let (dir, keep) = (self.dir.clone(), self.keep);
+        tokio::task::spawn_blocking(move || {
+            let mut skip = query.offset.unwrap_or(0);
+            let mut records = Vec::new();
+            for index in 0..=keep {
+                let path = audit_file(&dir, index);
+                let file = match File::open(&path) {
+                    Ok(file) => file,
+                    Err(e) if e.kind() == io::ErrorKind::NotFound => continue,
+                    Err(e) => {
+                        return Err(e)
+                            .wrap_err_with(|| format!("Cannot read audit log {}", path.display()))
+                    }
+                };
+                for line in ReverseLines::new(file)? {
+                    let line =
+                        line.wrap_err_with(|| format!("Cannot read audit log {}", path.display()))?;
+                    // a line cut short by a crash or a write in progress is skipped
+                    let Ok(record) = serde_json::from_slice::<AuditRecord>(&line) else {
+                        continue;
+                    };
+                    if !query.matches(&record) {
+                        continue;
+                    }
+                    if skip > 0 {
+                        skip -= 1;
+                    } else if records.len() == limit {
+                        return Ok((records, true));
+                    } else {
+                        records.push(record);
+                    }
+                }
+            }
+            Ok((records, false))
+        })
+        .await?
+    }
+}
+
+/// Who acts, for handlers that write to the audit log
+pub(crate) struct Audit {
+    log: AuditLog,
+    user: Option<String>,
+    name: Option<String>,
+    ip: IpAddr,
+    scheme: String,
+}
+
+#[async_trait]
+impl<S> FromRequestParts<S> for Audit
+where
+    S: Send + Sync,
+    AuditLog: FromRef<S>,
+{
+    type Rejection = Infallible;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        let client = ClientInfo::from_request_parts(parts, state).await?;
+        let claims = JwtClaims::<Claims>::from_request_parts(parts, state)
+            .await
+            .ok()
+            .map(|JwtClaims(claims)| claims);
+        Ok(Self {
+            log: AuditLog::from_ref(state),
+            user: claims.as_ref().and_then(|c| c.sub()).map(String::from),
+            name: claims
+                .as_ref()
+                .and_then(|c| c.display_name())
+                .map(String::from),
+            ip: client.ip,
+            scheme: client.scheme,
+        })
+    }
+}
+
+impl Audit {
+    /// Start a record of `action`, written by [`AuditEntry::write`]
+    pub(crate) fn entry(&self, action: &str) -> AuditEntry {
+        AuditEntry {
+            log: self.log.clone(),
+            record: AuditRecord {
+                ts: now_millis(),
+                user: self.user.clone(),
+                name: self.name.clone(),
+                ip: self.ip,
+                scheme: Some(self.scheme.clone()),
+                action: action.to_string(),
+                target: None,
+                topic: None,
+                payload: None,
+                result: String::new(),
+            },
+        }
+    }
+}
+
+pub(crate) struct AuditEntry {
+    log: AuditLog,
+    record: AuditRecord,
+}
+
+impl AuditEntry {
+    pub(crate) fn target(mut self, target: impl Into<String>) -> Self {
+        self.record.target = Some(target.into());
+        self
+    }
+
+    pub(crate) fn topic(mut self, topic: impl Into<String>) -> Self {
+        self.record.topic = Some(topic.into());
+        self
+    }
+
+    pub(crate) fn payload(mut self, payload: impl Into<String>) -> Self {
+        self.record.payload = Some(payload.into());
+        self
+    }
+
+    /// Definition of a timer, schedule or rule as JSON, values published to
+    /// redacted topics are left out
+    pub(crate) fn definition(self, definition: &impl Serialize) -> Self {
+        let mut definition = serde_json::to_value(definition).unwrap_or_default();
+        self.log.redact_values(&mut definition);
+        self.payload(definition.to_string())
+    }
+
+    /// Topic and payload of a message about to be published
+    pub(crate) fn message(self, message: &PublishMessage) -> Self {
+        self.topic(&message.topic)
+            .payload(String::from_utf8_lossy(&message.value))
+    }
+
+    /// Run `action` in its own task and write its result. The task is not
+    /// cancelled with the request, an action interrupted by the request
+    /// timeout or a client that went away still completes and is recorded.
+    pub(crate) async fn record<T, F>(self, action: F, result: fn(&T) -> String) -> T
+    where
+        F: Future<Output = T> + Send + 'static,
+        T: Send + 'static,
+    {
+        let task = tokio::spawn(async move {
+            let value = action.await;
+            self.write(result(&value));
+            value
+        });
+        task.await
+            .unwrap_or_else(|e| std::panic::resume_unwind(e.into_panic()))
+    }
+
+    pub(crate) fn write(&self, result: impl Into<String>) {
+        let mut record = self.record.clone();
+        record.result = result.into();
+        self.log.append(record);
+    }
+}
+
+/// Result of a handler as written to the audit log
+pub(crate) fn outcome<T>(result: &Result<T, (StatusCode, String)>) -> String {
+    match result {
+        Ok(_) => String::from("OK"),
+        Err((_, message)) => message.clone(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = env::temp_dir().join(format!("hcs-audit-{name}-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn reverse_lines(path: &Path) -> Vec<String> {
+        ReverseLines::new(File::open(path).unwrap())
+            .unwrap()
+            .map(|line| String::from_utf8(line.unwrap()).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn reverse_lines_across_chunks() {
+        let dir = temp_dir("reverse");
+        let path = dir.join("lines");
+        // longer than one chunk, with lines split at the chunk boundaries
+        let lines: Vec<String> = (0..5000).map(|i| format!("{i:>20}")).collect();
+        fs::write(&path, lines.join("\n") + "\n").unwrap();
+        let mut expected = lines.clone();
+        expected.reverse();
+        assert_eq!(reverse_lines(&path), expected);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn reverse_lines_without_final_newline() {
+        let dir = temp_dir("partial");
+        let path = dir.join("lines");
+        fs::write(&path, "a\n\nb\nc").unwrap();
+        assert_eq!(reverse_lines(&path), ["c", "b", "a"]);
+        fs::write(&path, "").unwrap();
+        assert!(reverse_lines(&path).is_empty());
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn rotate_keeps_files() {
+        let dir = temp_dir("rotate");
+        let (file, size) = Writer::open(&dir).unwrap();
+        let mut writer = Writer {
+            dir: dir.clone(),
+            file: BufWriter::new(file),
+            size,
+            rotate_size: 1,
+            keep: 2,
+        };
+        for i in 0..4 {
+            writeln!(writer.file, "{i}").unwrap();
+            writer.rotate().unwrap();
+        }
+        let read = |index| fs::read_to_string(audit_file(&dir, index)).unwrap();
+        assert_eq!(read(0), "");
+        assert_eq!(read(1), "3\n");
+        assert_eq!(read(2), "2\n");
+        assert!(!audit_file(&dir, 3).exists());
+        assert_eq!(writer.size, 0);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn redact_definition_values() {
+        let (records, _rx) = mpsc::unbounded_channel();
+        let log = AuditLog {
+            dir: PathBuf::new(),
+            keep: 0,
+            records,
+            redact: Arc::new(vec!["cmnd/+/PASSWORD".to_string()]),
+        };
+        let mut definition = serde_json::json!({
+            "actions": [
+                {"topic": "cmnd/lock/PASSWORD", "value": "secret"},
+                {"topic": "cmnd/lamp/POWER", "value": "ON"},
+            ],
+        });
+        log.redact_values(&mut definition);
+        assert_eq!(definition["actions"][0]["value"], REDACTED);
+        assert_eq!(definition["actions"][1]["value"], "ON");
+    }
+}
@@ -0,0 +1,41 @@
+use std::{env, path::PathBuf};
+
+use color_eyre::eyre::{Context, Result};
+use serde::{de::DeserializeOwned, Serialize};
+
+/// Directory for locally persisted state, created on first use.
+pub(crate) fn data_dir() -> Result<PathBuf> {
+    let dir = PathBuf::from(env::var("HCS_DATA_DIR").unwrap_or_else(|_| "data".to_string()));
+    std::fs::create_dir_all(&dir)
+        .wrap_err_with(|| format!("Cannot create data directory {}", dir.display()))?;
+    Ok(dir)
+}
+
+/// Milliseconds since the unix epoch.
+pub(crate) fn now_millis() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or_default()
+}
+
+/// Read a JSON file from the data directory, a missing file yields the default.
+pub(crate) fn load_json<T: DeserializeOwned + Default>(name: &str) -> Result<T> {
+    let path = data_dir()?.join(name);
+    if !path.exists() {
+        return Ok(T::default());
+    }
+    let content = std::fs::read_to_string(&path)
+        .wrap_err_with(|| format!("Cannot read {}", path.display()))?;
+    serde_json::from_str(&content)
+        .wrap_err_with(|| format!("Invalid content in {}", path.display()))
+}
+
+/// Replace a JSON file in the data directory.
+pub(crate) fn save_json<T: Serialize>(name: &str, value: &T) -> Result<()> {
+    let path = data_dir()?.join(name);
+    let tmp = path.with_extension("tmp");
+    std::fs::write(&tmp, serde_json::to_vec_pretty(value)?)
+        .wrap_err_with(|| format!("Cannot write {}", tmp.display()))?;
+    std::fs::rename(&tmp, &path).wrap_err_with(|| format!("Cannot replace {}", path.display()))
+}
@@ -0,0 +1,93 @@
+mod model;
+
+use std::{collections::HashSet, env, sync::Arc};
+
+use color_eyre::eyre::{eyre, Context, Result};
+use tracing::info;
+
+pub(crate) use model::{Device, DeviceCommand};
+
+use crate::mqtta::message::{qos_from_u8, PublishMessage};
+
+/// Error while translating a device command
+pub(crate) enum DeviceError {
+    NotFound(String),
+    Unsupported(String),
+}
+
+/// Devices and their capabilities as defined in the configuration.
+#[derive(Clone, Default)]
+pub(crate) struct DeviceRegistry {
+    devices: Arc<Vec<Device>>,
+}
+
+impl DeviceRegistry {
+    /// Load the devices from the file named by `HCS_DEVICES_CONFIG`.
+    pub(crate) fn from_env() -> Result<Self> {
+        let Ok(path) = env::var("HCS_DEVICES_CONFIG") else {
+            return Ok(Self::default());
+        };
+        if path.is_empty() {
+            return Ok(Self::default());
+        }
+        let content = std::fs::read_to_string(&path)
+            .wrap_err_with(|| format!("Cannot read device configuration {path}"))?;
+        let devices = serde_json::from_str::<Vec<Device>>(&content)
+            .wrap_err_with(|| format!("Invalid device configuration {path}"))?;
+
+        let mut ids = HashSet::new();
+        for device in &devices {
+            if !ids.insert(device.id.as_str()) {
+                return Err(eyre!("Duplicate device id {}", device.id));
+            }
+            let mut capability_ids = HashSet::new();
+            for capability in &device.capabilities {
+                if !capability_ids.insert(capability.id.as_str()) {
+                    return Err(eyre!(
+                        "Duplicate capability id {} in device {}",
+                        capability.id,
+                        device.id
+                    ));
+                }
+            }
+        }
+        info!(devices = devices.len(), "Device registry loaded");
+        Ok(Self {
+            devices: Arc::new(devices),
+        })
+    }
+
+    pub(crate) fn list(&self) -> &[Device] {
+        &self.devices
+    }
+
+    pub(crate) fn get(&self, id: &str) -> Option<&Device> {
+        self.devices.iter().find(|d| d.id == id)
+    }
+
+    /// Build the message that executes a command on a device capability.
+    pub(crate) fn command(
+        &self,
+        device_id: &str,
+        capability_id: &str,
+        command: &DeviceCommand,
+    ) -> Result<PublishMessage, DeviceError> {
+        let device = self
+            .get(device_id)
+            .ok_or_else(|| DeviceError::NotFound(format!("Unknown device {device_id}")))?;
+        let capability = device
+            .capabilities
+            .iter()
+            .find(|c| c.id == capability_id)
+            .ok_or_else(|| DeviceError::NotFound(format!("Unknown capability {capability_id}")))?;
+        let (topic, value) = capability
+            .command(command)
+            .map_err(DeviceError::Unsupported)?;
+        Ok(PublishMessage::builder()
+            .topic(topic)
+            .value(value.into_bytes())
+            .qos(qos_from_u8(capability.qos))
+            .retain(capability.retain)
+            .build())
+    }
+}
@@ -0,0 +1,339 @@
+use serde::{Deserialize, Serialize};
+
+fn default_on() -> String {
+    "ON".to_string()
+}
+
+fn default_off() -> String {
+    "OFF".to_string()
+}
+
+fn default_open() -> String {
+    "OPEN".to_string()
+}
+
+fn default_close() -> String {
+    "CLOSE".to_string()
+}
+
+fn default_stop() -> String {
+    "STOP".to_string()
+}
+
+fn default_max() -> f64 {
+    100.0
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub(crate) struct Device {
+    pub(crate) id: String,
+    pub(crate) name: String,
+    #[serde(default)]
+    pub(crate) room: Option<String>,
+    #[serde(default)]
+    pub(crate) capabilities: Vec<Capability>,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub(crate) struct Capability {
+    pub(crate) id: String,
+    #[serde(default)]
+    pub(crate) qos: u8,
+    #[serde(default)]
+    pub(crate) retain: bool,
+    #[serde(flatten)]
+    pub(crate) kind: CapabilityKind,
+}
+
+/// How a capability maps to topics and payloads
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub(crate) enum CapabilityKind {
+    Switch {
+        state_topic: Option<String>,
+        command_topic: String,
+        #[serde(default = "default_on")]
+        payload_on: String,
+        #[serde(default = "default_off")]
+        payload_off: String,
+        /// Path into a JSON state payload
+        value_path: Option<String>,
+    },
+    Dimmer {
+        state_topic: Option<String>,
+        command_topic: String,
+        #[serde(default)]
+        min: f64,
+        #[serde(default = "default_max")]
+        max: f64,
+        /// Wrap the brightness into a JSON object with this key
+        command_key: Option<String>,
+        value_path: Option<String>,
+    },
+    Sensor {
+        state_topic: String,
+        unit: Option<String>,
+        device_class: Option<String>,
+        value_path: Option<String>,
+    },
+    Cover {
+        state_topic: Option<String>,
+        command_topic: String,
+        position_topic: Option<String>,
+        set_position_topic: Option<String>,
+        #[serde(default = "default_open")]
+        payload_open: String,
+        #[serde(default = "default_close")]
+        payload_close: String,
+        #[serde(default = "default_stop")]
+        payload_stop: String,
+    },
+    Thermostat {
+        current_temperature_topic: Option<String>,
+        target_temperature_topic: Option<String>,
+        command_topic: String,
+        unit: Option<String>,
+        command_key: Option<String>,
+        value_path: Option<String>,
+    },
+}
+
+/// A command addressed to a single capability
+#[derive(Debug, Deserialize)]
+#[serde(tag = "action", rename_all = "snake_case")]
+pub(crate) enum DeviceCommand {
+    On,
+    Off,
+    Set { value: f64 },
+    Open,
+    Close,
+    Stop,
+    SetPosition { position: f64 },
+    SetTemperature { temperature: f64 },
+}
+
+fn number_payload(value: f64, key: &Option<String>) -> String {
+    let value = serde_json::json!(value);
+    match key {
+        Some(key) => serde_json::json!({ key: value }).to_string(),
+        None => value.to_string(),
+    }
+}
+
+impl Capability {
+    /// Translate a command into the topic and payload to publish.
+    pub(crate) fn command(&self, command: &DeviceCommand) -> Result<(String, String), String> {
+        match (&self.kind, command) {
+            (
+                CapabilityKind::Switch {
+                    command_topic,
+                    payload_on,
+                    ..
+                },
+                DeviceCommand::On,
+            ) => Ok((command_topic.clone(), payload_on.clone())),
+            (
+                CapabilityKind::Switch {
+                    command_topic,
+                    payload_off,
+                    ..
+                },
+                DeviceCommand::Off,
+            ) => Ok((command_topic.clone(), payload_off.clone())),
+            (
+                CapabilityKind::Dimmer {
+                    command_topic,
+                    min,
+                    max,
+                    command_key,
+                    ..
+                },
+                DeviceCommand::Set { value },
+            ) => Ok((
+                command_topic.clone(),
+                number_payload(value.clamp(*min, *max), command_key),
+            )),
+            (
+                CapabilityKind::Dimmer {
+                    command_topic,
+                    max,
+                    command_key,
+                    ..
+                },
+                DeviceCommand::On,
+            ) => Ok((command_topic.clone(), number_payload(*max, command_key))),
+            (
+                CapabilityKind::Dimmer {
+                    command_topic,
+                    min,
+                    command_key,
+                    ..
+                },
+                DeviceCommand::Off,
+            ) => Ok((command_topic.clone(), number_payload(*min, command_key))),
+            (
+                CapabilityKind::Cover {
+                    command_topic,
+                    payload_open,
+                    ..
+                },
+                DeviceCommand::Open,
+            ) => Ok((command_topic.clone(), payload_open.clone())),
+            (
+                CapabilityKind::Cover {
+                    command_topic,
+                    payload_close,
+                    ..
+                },
+                DeviceCommand::Close,
+            ) => Ok((command_topic.clone(), payload_close.clone())),
+            (
+                CapabilityKind::Cover {
+                    command_topic,
+                    payload_stop,
+                    ..
+                },
+                DeviceCommand::Stop,
+            ) => Ok((command_topic.clone(), payload_stop.clone())),
+            (
+                CapabilityKind::Cover {
+                    command_topic,
+                    set_position_topic,
+                    ..
+                },
+                DeviceCommand::SetPosition { position },
+            ) => Ok((
+                set_position_topic
+                    .clone()
+                    .unwrap_or_else(|| command_topic.clone()),
+                number_payload(position.clamp(0.0, 100.0), &None),
+            )),
+            (
+                CapabilityKind::Thermostat {
+                    command_topic,
+                    command_key,
+                    ..
+                },
+                DeviceCommand::SetTemperature { temperature },
+            ) => Ok((
+                command_topic.clone(),
+                number_payload(*temperature, command_key),
+            )),
+            (CapabilityKind::Sensor { .. }, _) => Err("Sensors cannot be controlled".to_string()),
+            (_, command) => Err(format!("Command {command:?} not supported")),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    fn capability(kind: serde_json::Value) -> Capability {
+        serde_json::from_value(kind).unwrap()
+    }
+
+    fn command(
+        capability: &Capability,
+        command: serde_json::Value,
+    ) -> Result<(String, String), String> {
+        capability.command(&serde_json::from_value(command).unwrap())
+    }
+
+    #[test]
+    fn dimmer_levels_are_clamped() {
+        let dimmer = capability(json!({
+            "id": "light",
+            "type": "dimmer",
+            "command_topic": "light/set",
+            "min": 10.0,
+            "max": 80.0,
+        }));
+        let set = |value: f64| command(&dimmer, json!({ "action": "set", "value": value }));
+        assert_eq!(set(50.0), Ok(("light/set".to_string(), "50.0".to_string())));
+        assert_eq!(
+            set(120.0),
+            Ok(("light/set".to_string(), "80.0".to_string()))
+        );
+        assert_eq!(set(-5.0), Ok(("light/set".to_string(), "10.0".to_string())));
+        assert_eq!(
+            command(&dimmer, json!({ "action": "on" })).unwrap().1,
+            "80.0"
+        );
+        assert_eq!(
+            command(&dimmer, json!({ "action": "off" })).unwrap().1,
+            "10.0"
+        );
+
+        let cover = capability(json!({
+            "id": "blind",
+            "type": "cover",
+            "command_topic": "blind/set",
+            "set_position_topic": "blind/position/set",
+        }));
+        assert_eq!(
+            command(
+                &cover,
+                json!({ "action": "set_position", "position": 150.0 })
+            ),
+            Ok(("blind/position/set".to_string(), "100.0".to_string()))
+        );
+    }
+
+    #[test]
+    fn command_key_wraps_values() {
+        let dimmer = capability(json!({
+            "id": "light",
+            "type": "dimmer",
+            "command_topic": "zigbee2mqtt/lamp/set",
+            "max": 254.0,
+            "command_key": "brightness",
+        }));
+        let (topic, payload) =
+            command(&dimmer, json!({ "action": "set", "value": 300.0 })).unwrap();
+        assert_eq!(topic, "zigbee2mqtt/lamp/set");
+        assert_eq!(payload, r#"{"brightness":254.0}"#);
+
+        let thermostat = capability(json!({
+            "id": "heating",
+            "type": "thermostat",
+            "command_topic": "trv/set",
+            "command_key": "current_heating_setpoint",
+        }));
+        let (_, payload) = command(
+            &thermostat,
+            json!({ "action": "set_temperature", "temperature": 21.5 }),
+        )
+        .unwrap();
+        assert_eq!(payload, r#"{"current_heating_setpoint":21.5}"#);
+
+        let switch =
+            capability(json!({ "id": "power", "type": "switch", "command_topic": "plug/set" }));
+        assert_eq!(
+            command(&switch, json!({ "action": "on" })),
+            Ok(("plug/set".to_string(), "ON".to_string()))
+        );
+    }
+
+    #[test]
+    fn unsupported_commands() {
+        let sensor = capability(
+            json!({ "id": "temperature", "type": "sensor", "state_topic": "room/temp" }),
+        );
+        for action in [
+            json!({ "action": "on" }),
+            json!({ "action": "set", "value": 1.0 }),
+        ] {
+            assert_eq!(
+                command(&sensor, action),
+                Err("Sensors cannot be controlled".to_string())
+            );
+        }
+
+        let switch =
+            capability(json!({ "id": "power", "type": "switch", "command_topic": "plug/set" }));
+        assert!(command(&switch, json!({ "action": "open" })).is_err());
+    }
+}
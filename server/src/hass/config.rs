@@ -0,0 +1,270 @@
+use std::collections::BTreeMap;
+
+use serde::Serialize;
+use serde_json::{Map, Value};
+
+/// Abbreviations allowed in discovery payloads
+const ABBREVIATIONS: &[(&str, &str)] = &[
+    ("avty_t", "availability_topic"),
+    ("bri_cmd_t", "brightness_command_topic"),
+    ("bri_stat_t", "brightness_state_topic"),
+    ("cmd_t", "command_topic"),
+    ("curr_temp_t", "current_temperature_topic"),
+    ("dev", "device"),
+    ("dev_cla", "device_class"),
+    ("ic", "icon"),
+    ("json_attr_t", "json_attributes_topic"),
+    ("mode_cmd_t", "mode_command_topic"),
+    ("mode_stat_t", "mode_state_topic"),
+    ("pl_avail", "payload_available"),
+    ("pl_not_avail", "payload_not_available"),
+    ("pl_off", "payload_off"),
+    ("pl_on", "payload_on"),
+    ("pos_t", "position_topic"),
+    ("set_pos_t", "set_position_topic"),
+    ("stat_cla", "state_class"),
+    ("stat_t", "state_topic"),
+    ("temp_cmd_t", "temperature_command_topic"),
+    ("temp_stat_t", "temperature_state_topic"),
+    ("uniq_id", "unique_id"),
+    ("unit_of_meas", "unit_of_measurement"),
+    ("val_tpl", "value_template"),
+];
+
+const DEVICE_ABBREVIATIONS: &[(&str, &str)] = &[
+    ("ids", "identifiers"),
+    ("mdl", "model"),
+    ("mf", "manufacturer"),
+    ("sw", "sw_version"),
+];
+
+#[derive(Clone, Debug, Serialize)]
+pub(crate) struct HassDevice {
+    pub(crate) identifiers: Vec<String>,
+    pub(crate) name: Option<String>,
+    pub(crate) manufacturer: Option<String>,
+    pub(crate) model: Option<String>,
+    pub(crate) sw_version: Option<String>,
+}
+
+/// An entity announced through MQTT discovery
+#[derive(Clone, Debug, Serialize)]
+pub(crate) struct HassEntity {
+    pub(crate) id: String,
+    pub(crate) component: String,
+    pub(crate) name: Option<String>,
+    pub(crate) unique_id: Option<String>,
+    pub(crate) state_topic: Option<String>,
+    pub(crate) command_topic: Option<String>,
+    pub(crate) availability_topic: Option<String>,
+    pub(crate) unit: Option<String>,
+    pub(crate) device_class: Option<String>,
+    pub(crate) value_template: Option<String>,
+    pub(crate) payload_on: Option<String>,
+    pub(crate) payload_off: Option<String>,
+    /// All other topics of the entity, e.g. `brightness_command_topic`
+    pub(crate) topics: BTreeMap<String, String>,
+    pub(crate) device: Option<HassDevice>,
+    /// Time of the last discovery message in milliseconds since the unix epoch
+    pub(crate) updated: u64,
+}
+
+/// Split `<prefix>/<component>/[<node_id>/]<object_id>/config` into the
+/// component and the entity id.
+pub(crate) fn parse_discovery_topic(prefix: &str, topic: &str) -> Option<(String, String)> {
+    let rest = topic.strip_prefix(prefix)?.strip_prefix('/')?;
+    let rest = rest.strip_suffix("/config")?;
+    let parts: Vec<&str> = rest.split('/').collect();
+    match parts.as_slice() {
+        [component, _object_id] | [component, _, _object_id] => {
+            Some((component.to_string(), rest.to_string()))
+        }
+        _ => None,
+    }
+}
+
+fn expand(map: &Map<String, Value>, abbreviations: &[(&str, &str)]) -> Map<String, Value> {
+    map.iter()
+        .map(|(key, value)| {
+            let key = abbreviations
+                .iter()
+                .find(|(short, _)| short == key)
+                .map(|(_, long)| long.to_string())
+                .unwrap_or_else(|| key.clone());
+            (key, value.clone())
+        })
+        .collect()
+}
+
+fn as_text(value: &Value) -> Option<String> {
+    match value {
+        Value::String(s) => Some(s.clone()),
+        Value::Number(n) => Some(n.to_string()),
+        Value::Bool(b) => Some(b.to_string()),
+        _ => None,
+    }
+}
+
+fn parse_device(value: &Value) -> Option<HassDevice> {
+    let map = expand(value.as_object()?, DEVICE_ABBREVIATIONS);
+    let identifiers = match map.get("identifiers") {
+        Some(Value::Array(ids)) => ids.iter().filter_map(as_text).collect(),
+        Some(v) => as_text(v).into_iter().collect(),
+        None => Vec::new(),
+    };
+    let text = |key: &str| map.get(key).and_then(as_text);
+    Some(HassDevice {
+        identifiers,
+        name: text("name"),
+        manufacturer: text("manufacturer"),
+        model: text("model"),
+        sw_version: text("sw_version"),
+    })
+}
+
+/// Build an entity from a discovery payload, expanding abbreviations and
+/// the `~` base topic.
+pub(crate) fn parse_entity(
+    id: String,
+    component: String,
+    payload: &Value,
+    updated: u64,
+) -> Option<HassEntity> {
+    let mut map = expand(payload.as_object()?, ABBREVIATIONS);
+    if let Some(base) = map.remove("~").and_then(|b| as_text(&b)) {
+        for (key, value) in map.iter_mut() {
+            if !key.ends_with("_topic") {
+                continue;
+            }
+            if let Value::String(topic) = value {
+                if let Some(rest) = topic.strip_prefix('~') {
+                    *topic = format!("{base}{rest}");
+                } else if let Some(rest) = topic.strip_suffix('~') {
+                    *topic = format!("{rest}{base}");
+                }
+            }
+        }
+    }
+
+    let mut take = |key: &str| map.remove(key).as_ref().and_then(as_text);
+    let name = take("name");
+    let unique_id = take("unique_id");
+    let state_topic = take("state_topic");
+    let command_topic = take("command_topic");
+    let availability_topic = take("availability_topic");
+    let unit = take("unit_of_measurement");
+    let device_class = take("device_class");
+    let value_template = take("value_template");
+    let payload_on = take("payload_on");
+    let payload_off = take("payload_off");
+    let device = map.get("device").and_then(parse_device);
+    let topics = map
+        .iter()
+        .filter(|(key, _)| key.ends_with("_topic"))
+        .filter_map(|(key, value)| as_text(value).map(|v| (key.clone(), v)))
+        .collect();
+
+    Some(HassEntity {
+        id,
+        component,
+        name,
+        unique_id,
+        state_topic,
+        command_topic,
+        availability_topic,
+        unit,
+        device_class,
+        value_template,
+        payload_on,
+        payload_off,
+        topics,
+        device,
+        updated,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    fn parse(topic: &str) -> Option<(String, String)> {
+        parse_discovery_topic("homeassistant", topic)
+    }
+
+    #[test]
+    fn discovery_topics() {
+        let entity = |component: &str, id: &str| Some((component.to_string(), id.to_string()));
+        assert_eq!(
+            parse("homeassistant/sensor/hall_temp/config"),
+            entity("sensor", "sensor/hall_temp")
+        );
+        assert_eq!(
+            parse("homeassistant/light/bridge/kitchen/config"),
+            entity("light", "light/bridge/kitchen")
+        );
+        assert_eq!(parse("homeassistant/sensor/config"), None);
+        assert_eq!(parse("homeassistant/sensor/a/b/c/config"), None);
+        assert_eq!(parse("homeassistant/sensor/hall_temp/state"), None);
+        assert_eq!(parse("homeassistantx/sensor/hall_temp/config"), None);
+        assert_eq!(parse("other/sensor/hall_temp/config"), None);
+    }
+
+    #[test]
+    fn entity_abbreviations_and_base_topic() {
+        let payload = json!({
+            "~": "zigbee2mqtt/lamp",
+            "name": "Lamp",
+            "uniq_id": "lamp_light",
+            "stat_t": "~/state",
+            "cmd_t": "~/set",
+            "bri_cmd_t": "~/brightness/set",
+            "avty_t": "bridge/~",
+            "pl_on": "ON",
+            "brightness": true,
+            "dev": {"ids": "0x1234", "mf": "IKEA", "mdl": "LED1545G12", "name": "Lamp"},
+        });
+        let entity =
+            parse_entity("light/lamp".to_string(), "light".to_string(), &payload, 7).unwrap();
+        assert_eq!(entity.name.as_deref(), Some("Lamp"));
+        assert_eq!(entity.unique_id.as_deref(), Some("lamp_light"));
+        assert_eq!(
+            entity.state_topic.as_deref(),
+            Some("zigbee2mqtt/lamp/state")
+        );
+        assert_eq!(
+            entity.command_topic.as_deref(),
+            Some("zigbee2mqtt/lamp/set")
+        );
+        assert_eq!(
+            entity.availability_topic.as_deref(),
+            Some("bridge/zigbee2mqtt/lamp")
+        );
+        assert_eq!(entity.payload_on.as_deref(), Some("ON"));
+        assert_eq!(entity.payload_off, None);
+        assert_eq!(
+            entity
+                .topics
+                .get("brightness_command_topic")
+                .map(String::as_str),
+            Some("zigbee2mqtt/lamp/brightness/set")
+        );
+        assert_eq!(entity.topics.len(), 1);
+        let device = entity.device.unwrap();
+        assert_eq!(device.identifiers, ["0x1234"]);
+        assert_eq!(device.manufacturer.as_deref(), Some("IKEA"));
+        assert_eq!(device.model.as_deref(), Some("LED1545G12"));
+        assert_eq!(entity.updated, 7);
+    }
+
+    #[test]
+    fn entity_needs_object() {
+        let entity = |payload| parse_entity("a".to_string(), "sensor".to_string(), &payload, 0);
+        assert!(entity(json!("sensor")).is_none());
+        assert!(entity(json!([])).is_none());
+        let sensor = entity(json!({"unit_of_meas": "°C", "dev": "x"})).unwrap();
+        assert_eq!(sensor.unit.as_deref(), Some("°C"));
+        assert!(sensor.device.is_none());
+    }
+}
@@ -0,0 +1,150 @@
+mod config;
+
+use std::{collections::BTreeMap, env, sync::Arc};
+
+use serde::Serialize;
+use tokio::{
+    sync::{broadcast, mpsc, oneshot, RwLock},
+    task::JoinHandle,
+};
+use tracing::{debug, info, warn};
+
+pub(crate) use config::HassEntity;
+use config::{parse_discovery_topic, parse_entity};
+
+use crate::mqtta::{
+    message::{ActorMessage, IncomingMessage},
+    MqttHandle,
+};
+
+/// Change of the discovered entities
+#[derive(Clone, Debug, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub(crate) enum HassEvent {
+    EntityUpdated { entity: Box<HassEntity> },
+    EntityRemoved { id: String },
+}
+
+/// Entities grouped by the device that announced them
+#[derive(Serialize)]
+pub(crate) struct HassDeviceEntry {
+    id: String,
+    name: Option<String>,
+    manufacturer: Option<String>,
+    model: Option<String>,
+    entities: Vec<HassEntity>,
+}
+
+/// Live list of entities announced through Home Assistant MQTT discovery
+#[derive(Clone)]
+pub(crate) struct HassRegistry {
+    entities: Arc<RwLock<BTreeMap<String, HassEntity>>>,
+    events: broadcast::Sender<HassEvent>,
+}
+
+impl Default for HassRegistry {
+    fn default() -> Self {
+        let (events, _) = broadcast::channel(64);
+        Self {
+            entities: Default::default(),
+            events,
+        }
+    }
+}
+
+impl HassRegistry {
+    pub(crate) async fn entities(&self) -> Vec<HassEntity> {
+        self.entities.read().await.values().cloned().collect()
+    }
+
+    pub(crate) async fn devices(&self) -> Vec<HassDeviceEntry> {
+        let entities = self.entities.read().await;
+        let mut devices: BTreeMap<String, HassDeviceEntry> = BTreeMap::new();
+        for entity in entities.values() {
+            let id = entity
+                .device
+                .as_ref()
+                .and_then(|d| d.identifiers.first().cloned())
+                .unwrap_or_else(|| entity.id.clone());
+            let entry = devices
+                .entry(id.clone())
+                .or_insert_with(|| HassDeviceEntry {
+                    id,
+                    name: entity.device.as_ref().and_then(|d| d.name.clone()),
+                    manufacturer: entity.device.as_ref().and_then(|d| d.manufacturer.clone()),
+                    model: entity.device.as_ref().and_then(|d| d.model.clone()),
+                    entities: Vec::new(),
+                });
+            entry.entities.push(entity.clone());
+        }
+        devices.into_values().collect()
+    }
+
+    pub(crate) fn subscribe(&self) -> broadcast::Receiver<HassEvent> {
+        self.events.subscribe()
+    }
+
+    async fn apply(&self, prefix: &str, message: &IncomingMessage) {
+        let Some((component, id)) = parse_discovery_topic(prefix, &message.topic) else {
+            debug!(topic = message.topic, "Not a discovery topic");
+            return;
+        };
+
+        if message.payload.is_empty() {
+            if self.entities.write().await.remove(&id).is_some() {
+                debug!(id, "Discovered entity removed");
+                let _ = self.events.send(HassEvent::EntityRemoved { id });
+            }
+            return;
+        }
+
+        let entity = serde_json::from_slice::<serde_json::Value>(&message.payload)
+            .ok()
+            .and_then(|payload| parse_entity(id, component, &payload, message.ts));
+        let Some(entity) = entity else {
+            warn!(topic = message.topic, "Invalid discovery payload");
+            return;
+        };
+        debug!(id = entity.id, "Discovered entity");
+        self.entities
+            .write()
+            .await
+            .insert(entity.id.clone(), entity.clone());
+        let _ = self.events.send(HassEvent::EntityUpdated {
+            entity: Box::new(entity),
+        });
+    }
+}
+
+/// Subscribe to the discovery prefix from `HCS_HASS_DISCOVERY_PREFIX` and
+/// keep the registry up to date. Discovery is disabled if the variable is
+/// not set.
+pub(crate) fn run_hass_discovery(
+    mqtt: MqttHandle,
+    registry: HassRegistry,
+) -> Option<JoinHandle<()>> {
+    let prefix = env::var("HCS_HASS_DISCOVERY_PREFIX").ok()?;
+    let prefix = prefix.trim_end_matches('/').to_string();
+    if prefix.is_empty() {
+        return None;
+    }
+
+    info!(prefix, "Home Assistant discovery enabled");
+
+    Some(tokio::spawn(async move {
+        let (tx, rx) = oneshot::channel::<mpsc::Receiver<Arc<IncomingMessage>>>();
+        mqtt.send(ActorMessage::Stream {
+            filter: format!("{prefix}/#"),
+            respond_to: tx,
+        })
+        .await;
+        let Ok(mut messages) = rx.await else {
+            warn!(prefix, "Could not subscribe to discovery prefix");
+            return;
+        };
+        while let Some(message) = messages.recv().await {
+            registry.apply(&prefix, &message).await;
+        }
+        debug!("Home Assistant discovery stopped");
+    }))
+}
@@ -0,0 +1,140 @@
+mod store;
+
+use std::{collections::HashMap, env, sync::Arc, time::Duration};
+
+use color_eyre::eyre::{Context, Result};
+use serde::Deserialize;
+use tokio::{
+    sync::{oneshot, watch},
+    task::JoinHandle,
+};
+use tracing::{debug, info, warn};
+
+pub(crate) use store::{Bucket, HistoryStore};
+
+use crate::{
+    datadir::{data_dir, now_millis},
+    jsonpath::extract_number,
+    mqtta::{message::ActorMessage, MqttHandle},
+};
+
+const DAY_MS: u64 = 24 * 60 * 60 * 1000;
+
+fn default_retention_days() -> u64 {
+    30
+}
+
+#[derive(Deserialize)]
+pub(crate) struct HistoryConfig {
+    /// Retention used for topics without their own setting
+    #[serde(default = "default_retention_days")]
+    retention_days: u64,
+    #[serde(default)]
+    topics: Vec<HistoryTopic>,
+}
+
+#[derive(Clone, Deserialize)]
+struct HistoryTopic {
+    topic: String,
+    /// Path into a JSON payload, e.g. `$.temperature`
+    path: Option<String>,
+    retention_days: Option<u64>,
+}
+
+/// Read the recorded topics from the file named by `HCS_HISTORY_CONFIG`.
+/// Recording is disabled if the variable is not set.
+pub(crate) fn history_config_from_env() -> Result<Option<HistoryConfig>> {
+    let Ok(path) = env::var("HCS_HISTORY_CONFIG") else {
+        return Ok(None);
+    };
+    if path.is_empty() {
+        return Ok(None);
+    }
+    let content = std::fs::read_to_string(&path)
+        .wrap_err_with(|| format!("Cannot read history configuration {path}"))?;
+    let config = serde_json::from_str::<HistoryConfig>(&content)
+        .wrap_err_with(|| format!("Invalid history configuration {path}"))?;
+    Ok(Some(config))
+}
+
+/// Open the history store and start one recording task per configured topic.
+pub(crate) async fn run_history_recorder(
+    mqtt: MqttHandle,
+    config: Option<HistoryConfig>,
+) -> Result<(HistoryStore, Vec<JoinHandle<()>>)> {
+    let Some(config) = config else {
+        debug!("History recording disabled");
+        return Ok((HistoryStore::default(), Vec::new()));
+    };
+
+    let retentions: HashMap<String, u64> = config
+        .topics
+        .iter()
+        .map(|t| {
+            (
+                t.topic.clone(),
+                t.retention_days.unwrap_or(config.retention_days) * DAY_MS,
+            )
+        })
+        .collect();
+    let store = HistoryStore::open(data_dir()?.join("history.jsonl"), retentions)?;
+    store.apply_retention().await;
+    info!(topics = config.topics.len(), "History recording enabled");
+
+    // subscribed in the tasks, startup does not wait for the broker
+    let mut tasks: Vec<_> = config
+        .topics
+        .into_iter()
+        .map(|topic| tokio::spawn(record_topic(mqtt.clone(), store.clone(), topic)))
+        .collect();
+
+    let retention_store = store.clone();
+    tasks.push(tokio::spawn(async move {
+        let mut interval = tokio::time::interval(Duration::from_secs(60 * 60));
+        interval.tick().await;
+        loop {
+            interval.tick().await;
+            retention_store.apply_retention().await;
+        }
+    }));
+
+    Ok((store, tasks))
+}
+
+async fn record_topic(mqtt: MqttHandle, store: HistoryStore, topic: HistoryTopic) {
+    let (tx, rx) = oneshot::channel::<watch::Receiver<Arc<String>>>();
+    mqtt.send(ActorMessage::Subscribe {
+        topic: topic.topic.clone(),
+        respond_to: tx,
+    })
+    .await;
+    let Ok(mut w) = rx.await else {
+        warn!(topic = topic.topic, "Could not subscribe history topic");
+        return;
+    };
+    debug!(topic = topic.topic, "History recorder started");
+    while w.changed().await.is_ok() {
+        let update = w.borrow_and_update().clone();
+        let Ok(update) = serde_json::from_str::<serde_json::Value>(&update) else {
+            continue;
+        };
+        // a path refers to the payload as received, not to a transformed value
+        let field = match (&topic.path, update.get("raw")) {
+            (Some(_), Some(_)) => "raw",
+            _ => "data",
+        };
+        let Some(data) = update.get(field).and_then(|d| d.as_str()) else {
+            continue;
+        };
+        // stamped with the receive time of the update, not when it got here
+        let ts = update
+            .get("ts")
+            .and_then(serde_json::Value::as_u64)
+            .unwrap_or_else(now_millis);
+        match extract_number(data, topic.path.as_deref()) {
+            Some(value) => store.record(&topic.topic, ts, value).await,
+            None => debug!(topic = topic.topic, "No numeric value in update"),
+        }
+    }
+    debug!(topic = topic.topic, "History recorder stopped");
+}
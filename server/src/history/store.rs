@@ -0,0 +1,338 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    fs::{File, OpenOptions},
+    io::{BufRead, BufReader, Write},
+    path::PathBuf,
+    sync::Arc,
+    thread,
+};
+
+use color_eyre::eyre::{Context, Result};
+use serde::{Deserialize, Serialize};
+use tokio::sync::{mpsc, RwLock};
+use tracing::{debug, warn};
+
+use crate::datadir::now_millis;
+
+#[derive(Serialize, Deserialize)]
+struct Sample {
+    topic: String,
+    ts: u64,
+    value: f64,
+}
+
+struct Series {
+    retention_ms: u64,
+    points: VecDeque<(u64, f64)>,
+}
+
+/// File operations done by the writer thread, in the order they were sent
+enum FileOp {
+    Append(Sample),
+    /// Replace the file with these samples
+    Compact(Vec<Sample>),
+}
+
+#[derive(Default)]
+struct Inner {
+    series: HashMap<String, Series>,
+    /// Sent to while the lock is held so the file sees the same order
+    writer: Option<mpsc::UnboundedSender<FileOp>>,
+}
+
+/// Owns the store file, keeps blocking writes away from the runtime and
+/// from the lock readers wait on
+struct Writer {
+    path: PathBuf,
+    file: Option<File>,
+}
+
+/// One downsampled bucket of a series.
+#[derive(Debug, Serialize)]
+pub(crate) struct Bucket {
+    pub(crate) ts: u64,
+    pub(crate) min: f64,
+    pub(crate) max: f64,
+    pub(crate) avg: f64,
+    pub(crate) count: usize,
+}
+
+/// Embedded time-series store backed by an append-only file.
+#[derive(Clone, Default)]
+pub(crate) struct HistoryStore {
+    inner: Arc<RwLock<Inner>>,
+}
+
+impl HistoryStore {
+    /// Open the store file, keeping only samples of the given topics that
+    /// are within their retention period.
+    pub(crate) fn open(path: PathBuf, retentions: HashMap<String, u64>) -> Result<Self> {
+        let mut series: HashMap<String, Series> = retentions
+            .into_iter()
+            .map(|(topic, retention_ms)| {
+                (
+                    topic,
+                    Series {
+                        retention_ms,
+                        points: VecDeque::new(),
+                    },
+                )
+            })
+            .collect();
+
+        if path.exists() {
+            let file = File::open(&path)
+                .wrap_err_with(|| format!("Cannot read history file {}", path.display()))?;
+            let mut skipped = 0;
+            for line in BufReader::new(file).lines() {
+                let line = line.wrap_err("Cannot read history file")?;
+                match serde_json::from_str::<Sample>(&line) {
+                    Ok(sample) => {
+                        if let Some(s) = series.get_mut(&sample.topic) {
+                            s.points.push_back((sample.ts, sample.value));
+                        }
+                    }
+                    Err(_) => skipped += 1,
+                }
+            }
+            if skipped > 0 {
+                warn!(skipped, "Ignored invalid history lines");
+            }
+        }
+
+        let (writer, rx) = mpsc::unbounded_channel();
+        let file_writer = Writer { path, file: None };
+        thread::Builder::new()
+            .name("history-writer".to_string())
+            .spawn(move || file_writer.run(rx))
+            .wrap_err("Cannot start history writer")?;
+        let store = Self {
+            inner: Arc::new(RwLock::new(Inner {
+                series,
+                writer: Some(writer),
+            })),
+        };
+        Ok(store)
+    }
+
+    /// Append a new sample for a recorded topic.
+    pub(crate) async fn record(&self, topic: &str, ts: u64, value: f64) {
+        let mut inner = self.inner.write().await;
+        let Some(series) = inner.series.get_mut(topic) else {
+            return;
+        };
+        series.points.push_back((ts, value));
+        inner.send(FileOp::Append(Sample {
+            topic: topic.to_string(),
+            ts,
+            value,
+        }));
+    }
+
+    /// Drop samples older than their retention and rewrite the store file.
+    pub(crate) async fn apply_retention(&self) {
+        let now = now_millis();
+        let mut inner = self.inner.write().await;
+        let mut removed = 0;
+        for series in inner.series.values_mut() {
+            let cutoff = now.saturating_sub(series.retention_ms);
+            while series.points.front().is_some_and(|(ts, _)| *ts < cutoff) {
+                series.points.pop_front();
+                removed += 1;
+            }
+        }
+        debug!(removed, "History retention applied");
+        let samples = inner
+            .series
+            .iter()
+            .flat_map(|(topic, series)| {
+                series.points.iter().map(|&(ts, value)| Sample {
+                    topic: topic.clone(),
+                    ts,
+                    value,
+                })
+            })
+            .collect();
+        inner.send(FileOp::Compact(samples));
+    }
+
+    /// Whether the topic is recorded at all.
+    pub(crate) async fn contains(&self, topic: &str) -> bool {
+        self.inner.read().await.series.contains_key(topic)
+    }
+
+    /// Downsample the samples in `[from, to)` into buckets of `step` milliseconds.
+    pub(crate) async fn query(&self, topic: &str, from: u64, to: u64, step: u64) -> Vec<Bucket> {
+        let inner = self.inner.read().await;
+        let Some(series) = inner.series.get(topic) else {
+            return Vec::new();
+        };
+        let step = step.max(1);
+        let mut buckets: Vec<Bucket> = Vec::new();
+        let mut sum = 0.0;
+        for &(ts, value) in series
+            .points
+            .iter()
+            .filter(|(ts, _)| *ts >= from && *ts < to)
+        {
+            let start = from + (ts - from) / step * step;
+            match buckets.last_mut() {
+                Some(b) if b.ts == start => {
+                    b.min = b.min.min(value);
+                    b.max = b.max.max(value);
+                    b.count += 1;
+                    sum += value;
+                    b.avg = sum / b.count as f64;
+                }
+                _ => {
+                    sum = value;
+                    buckets.push(Bucket {
+                        ts: start,
+                        min: value,
+                        max: value,
+                        avg: value,
+                        count: 1,
+                    });
+                }
+            }
+        }
+        buckets
+    }
+}
+
+impl Inner {
+    fn send(&self, op: FileOp) {
+        let Some(writer) = &self.writer else {
+            return;
+        };
+        if writer.send(op).is_err() {
+            warn!("History writer stopped, sample not stored");
+        }
+    }
+}
+
+impl Writer {
+    fn run(mut self, mut ops: mpsc::UnboundedReceiver<FileOp>) {
+        while let Some(op) = ops.blocking_recv() {
+            let result = match op {
+                FileOp::Append(sample) => self.append(&sample),
+                FileOp::Compact(samples) => self.compact(&samples),
+            };
+            if let Err(e) = result {
+                warn!("Cannot write history file: {:?}", e);
+            }
+        }
+        debug!("History writer stopped");
+    }
+
+    fn append(&mut self, sample: &Sample) -> Result<()> {
+        if self.file.is_none() {
+            let file = OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(&self.path)
+                .wrap_err_with(|| format!("Cannot open history file {}", self.path.display()))?;
+            self.file = Some(file);
+        }
+        if let Some(file) = self.file.as_mut() {
+            let line = serde_json::to_string(sample)?;
+            writeln!(file, "{line}").wrap_err("Cannot append to history file")?;
+        }
+        Ok(())
+    }
+
+    fn compact(&mut self, samples: &[Sample]) -> Result<()> {
+        self.file = None;
+        let tmp = self.path.with_extension("tmp");
+        {
+            let mut file =
+                File::create(&tmp).wrap_err_with(|| format!("Cannot create {}", tmp.display()))?;
+            for sample in samples {
+                let line = serde_json::to_string(sample)?;
+                writeln!(file, "{line}")?;
+            }
+            file.sync_all()?;
+        }
+        std::fs::rename(&tmp, &self.path)
+            .wrap_err_with(|| format!("Cannot replace {}", self.path.display()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn store(points: &[(u64, f64)]) -> HistoryStore {
+        let series = Series {
+            retention_ms: u64::MAX,
+            points: points.iter().copied().collect(),
+        };
+        HistoryStore {
+            inner: Arc::new(RwLock::new(Inner {
+                series: HashMap::from([("t".to_string(), series)]),
+                writer: None,
+            })),
+        }
+    }
+
+    #[tokio::test]
+    async fn query_downsamples() {
+        let store = store(&[
+            (5, 100.0),
+            (10, 1.0),
+            (15, 3.0),
+            (19, 2.0),
+            (35, -1.0),
+            (40, 9.0),
+        ]);
+        let buckets = store.query("t", 10, 40, 10).await;
+        let summary: Vec<_> = buckets
+            .iter()
+            .map(|b| (b.ts, b.min, b.max, b.avg, b.count))
+            .collect();
+        assert_eq!(summary, [(10, 1.0, 3.0, 2.0, 3), (30, -1.0, -1.0, -1.0, 1)]);
+    }
+
+    /// Wait for the writer thread to leave `count` lines in the file
+    async fn wait_for_lines(path: &PathBuf, count: usize) -> bool {
+        for _ in 0..100 {
+            let lines = std::fs::read_to_string(path).map_or(0, |c| c.lines().count());
+            if lines == count {
+                return true;
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+        }
+        false
+    }
+
+    #[tokio::test]
+    async fn writer_appends_and_compacts() {
+        let path = std::env::temp_dir().join(format!("hcs-history-{}.jsonl", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let retentions = || HashMap::from([("t".to_string(), 60_000)]);
+        let now = now_millis();
+
+        let store = HistoryStore::open(path.clone(), retentions()).unwrap();
+        store.record("t", now - 120_000, 1.0).await;
+        store.record("t", now, 2.0).await;
+        store.record("other", now, 3.0).await;
+        assert!(wait_for_lines(&path, 2).await);
+        store.apply_retention().await;
+        assert!(wait_for_lines(&path, 1).await);
+
+        let store = HistoryStore::open(path.clone(), retentions()).unwrap();
+        let buckets = store.query("t", 0, u64::MAX, u64::MAX).await;
+        assert_eq!(buckets.iter().map(|b| b.max).collect::<Vec<_>>(), [2.0]);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn query_zero_step_and_unknown_topic() {
+        let store = store(&[(1, 1.0), (2, 2.0)]);
+        let buckets = store.query("t", 0, 10, 0).await;
+        assert_eq!(buckets.iter().map(|b| b.ts).collect::<Vec<_>>(), [1, 2]);
+        assert!(store.query("other", 0, 10, 1).await.is_empty());
+        assert!(store.contains("t").await);
+        assert!(!store.contains("other").await);
+    }
+}
@@ -0,0 +1,145 @@
+mod tree;
+
+use std::{collections::BTreeMap, env, sync::Arc};
+
+use rumqttc::QoS;
+use tokio::{
+    sync::{mpsc, oneshot, RwLock},
+    task::JoinHandle,
+};
+use tracing::{debug, info, warn};
+
+pub(crate) use tree::HomieDevice;
+
+use crate::mqtta::{
+    message::{ActorMessage, IncomingMessage, PublishMessage},
+    MqttHandle,
+};
+
+/// Error while writing a property
+pub(crate) enum HomieError {
+    Disabled,
+    NotFound,
+    NotSettable,
+    Invalid(String),
+}
+
+/// Devices following the Homie convention
+#[derive(Clone, Default)]
+pub(crate) struct HomieRegistry {
+    base_topic: Option<String>,
+    devices: Arc<RwLock<BTreeMap<String, HomieDevice>>>,
+}
+
+impl HomieRegistry {
+    pub(crate) fn enabled(&self) -> bool {
+        self.base_topic.is_some()
+    }
+
+    pub(crate) async fn devices(&self) -> Vec<HomieDevice> {
+        self.devices.read().await.values().cloned().collect()
+    }
+
+    pub(crate) async fn device(&self, id: &str) -> Option<HomieDevice> {
+        self.devices.read().await.get(id).cloned()
+    }
+
+    /// Build the message that writes a value to the `/set` topic of a
+    /// settable property.
+    pub(crate) async fn set_property(
+        &self,
+        device: &str,
+        node: &str,
+        property: &str,
+        value: &str,
+    ) -> Result<PublishMessage, HomieError> {
+        let Some(base_topic) = &self.base_topic else {
+            return Err(HomieError::Disabled);
+        };
+        let devices = self.devices.read().await;
+        let p = devices
+            .get(device)
+            .and_then(|d| d.nodes.get(node))
+            .and_then(|n| n.properties.get(property))
+            .ok_or(HomieError::NotFound)?;
+        if !p.settable {
+            return Err(HomieError::NotSettable);
+        }
+        p.validate(value).map_err(HomieError::Invalid)?;
+        Ok(PublishMessage::builder()
+            .topic(format!("{base_topic}/{device}/{node}/{property}/set"))
+            .value(value.as_bytes().to_vec())
+            .qos(QoS::AtLeastOnce)
+            .retain(false)
+            .build())
+    }
+
+    async fn apply(&self, base_topic: &str, message: &IncomingMessage) {
+        let Some(rest) = message
+            .topic
+            .strip_prefix(base_topic)
+            .and_then(|t| t.strip_prefix('/'))
+        else {
+            return;
+        };
+        let mut levels = rest.split('/');
+        // `$broadcast` and other `$` ids below the base topic are not devices
+        let Some(device) = levels.next().filter(|d| !d.starts_with('$')) else {
+            return;
+        };
+        let levels: Vec<&str> = levels.collect();
+        let value = String::from_utf8_lossy(&message.payload).to_string();
+
+        let mut devices = self.devices.write().await;
+        if levels == ["$homie"] && value.is_empty() {
+            if devices.remove(device).is_some() {
+                debug!(device, "Homie device removed");
+            }
+            return;
+        }
+        devices
+            .entry(device.to_string())
+            .or_insert_with(|| HomieDevice {
+                id: device.to_string(),
+                ..Default::default()
+            })
+            .apply(&levels, value);
+    }
+}
+
+/// Discover devices below `HCS_HOMIE_BASE_TOPIC`, usually `homie`. Discovery
+/// is disabled if the variable is not set.
+pub(crate) fn run_homie_discovery(mqtt: MqttHandle) -> (HomieRegistry, Option<JoinHandle<()>>) {
+    let Some(base_topic) = env::var("HCS_HOMIE_BASE_TOPIC")
+        .ok()
+        .map(|t| t.trim_end_matches('/').to_string())
+        .filter(|t| !t.is_empty())
+    else {
+        return (HomieRegistry::default(), None);
+    };
+
+    info!(base_topic, "Homie discovery enabled");
+
+    let registry = HomieRegistry {
+        base_topic: Some(base_topic.clone()),
+        ..Default::default()
+    };
+    let loopregistry = registry.clone();
+    let task = tokio::spawn(async move {
+        let (tx, rx) = oneshot::channel::<mpsc::Receiver<Arc<IncomingMessage>>>();
+        mqtt.send(ActorMessage::Stream {
+            filter: format!("{base_topic}/#"),
+            respond_to: tx,
+        })
+        .await;
+        let Ok(mut messages) = rx.await else {
+            warn!(base_topic, "Could not subscribe to Homie topics");
+            return;
+        };
+        while let Some(message) = messages.recv().await {
+            loopregistry.apply(&base_topic, &message).await;
+        }
+        debug!("Homie discovery stopped");
+    });
+    (registry, Some(task))
+}
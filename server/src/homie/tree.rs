@@ -0,0 +1,234 @@
+use std::collections::BTreeMap;
+
+use serde::Serialize;
+
+#[derive(Clone, Debug, Default, Serialize)]
+pub(crate) struct HomieProperty {
+    pub(crate) name: Option<String>,
+    pub(crate) datatype: Option<String>,
+    pub(crate) unit: Option<String>,
+    pub(crate) format: Option<String>,
+    pub(crate) settable: bool,
+    pub(crate) retained: bool,
+    pub(crate) value: Option<String>,
+}
+
+#[derive(Clone, Debug, Default, Serialize)]
+pub(crate) struct HomieNode {
+    pub(crate) name: Option<String>,
+    #[serde(rename = "type")]
+    pub(crate) node_type: Option<String>,
+    pub(crate) properties: BTreeMap<String, HomieProperty>,
+}
+
+#[derive(Clone, Debug, Default, Serialize)]
+pub(crate) struct HomieDevice {
+    pub(crate) id: String,
+    pub(crate) homie: Option<String>,
+    pub(crate) name: Option<String>,
+    pub(crate) state: Option<String>,
+    pub(crate) nodes: BTreeMap<String, HomieNode>,
+}
+
+/// Ids of a `$nodes` or `$properties` list, Homie 3 array ranges like
+/// `led[1-3]` are reduced to their id
+fn listed(value: &str) -> Vec<String> {
+    value
+        .split(',')
+        .filter_map(|id| id.split('[').next())
+        .map(|id| id.trim().to_string())
+        .filter(|id| !id.is_empty())
+        .collect()
+}
+
+/// Properties are retained unless announced otherwise
+fn new_property() -> HomieProperty {
+    HomieProperty {
+        retained: true,
+        ..Default::default()
+    }
+}
+
+impl HomieDevice {
+    /// Apply a message on `<device>/<levels...>` below the base topic.
+    pub(crate) fn apply(&mut self, levels: &[&str], value: String) {
+        match levels {
+            ["$homie"] => self.homie = Some(value),
+            ["$name"] => self.name = Some(value),
+            ["$state"] => self.state = Some(value),
+            ["$nodes"] => {
+                let listed = listed(&value);
+                self.nodes.retain(|key, _| listed.contains(key));
+                for id in listed {
+                    self.nodes.entry(id).or_default();
+                }
+            }
+            [attribute] if attribute.starts_with('$') => {}
+            [node, "$name"] => self.node(node).name = Some(value),
+            [node, "$type"] => self.node(node).node_type = Some(value),
+            [node, "$properties"] => {
+                let listed = listed(&value);
+                let node = self.node(node);
+                node.properties.retain(|key, _| listed.contains(key));
+                for id in listed {
+                    node.properties.entry(id).or_insert_with(new_property);
+                }
+            }
+            [node, attribute] if attribute.starts_with('$') || node.starts_with('$') => {}
+            [node, property] => self.property(node, property).value = Some(value),
+            [_, _, "set"] => {}
+            [node, property, "$name"] => self.property(node, property).name = Some(value),
+            [node, property, "$datatype"] => self.property(node, property).datatype = Some(value),
+            [node, property, "$unit"] => self.property(node, property).unit = Some(value),
+            [node, property, "$format"] => self.property(node, property).format = Some(value),
+            [node, property, "$settable"] => {
+                self.property(node, property).settable = value == "true"
+            }
+            [node, property, "$retained"] => {
+                self.property(node, property).retained = value != "false"
+            }
+            _ => {}
+        }
+    }
+
+    fn node(&mut self, node: &str) -> &mut HomieNode {
+        self.nodes.entry(node.to_string()).or_default()
+    }
+
+    fn property(&mut self, node: &str, property: &str) -> &mut HomieProperty {
+        self.node(node)
+            .properties
+            .entry(property.to_string())
+            .or_insert_with(new_property)
+    }
+}
+
+impl HomieProperty {
+    /// Check a value against the datatype and format of the property.
+    pub(crate) fn validate(&self, value: &str) -> Result<(), String> {
+        let format = self.format.as_deref().unwrap_or_default();
+        let range = || -> Option<(f64, f64)> {
+            let (min, max) = format.split_once(':')?;
+            Some((min.parse().ok()?, max.parse().ok()?))
+        };
+        match self.datatype.as_deref().unwrap_or("string") {
+            "integer" => {
+                let n = value
+                    .parse::<i64>()
+                    .map_err(|_| "Value must be an integer".to_string())?;
+                match range() {
+                    Some((min, max)) if (n as f64) < min || (n as f64) > max => {
+                        Err(format!("Value must be within {format}"))
+                    }
+                    _ => Ok(()),
+                }
+            }
+            "float" => {
+                let n = value
+                    .parse::<f64>()
+                    .map_err(|_| "Value must be a number".to_string())?;
+                match range() {
+                    Some((min, max)) if n < min || n > max => {
+                        Err(format!("Value must be within {format}"))
+                    }
+                    _ => Ok(()),
+                }
+            }
+            "boolean" => match value {
+                "true" | "false" => Ok(()),
+                _ => Err("Value must be true or false".to_string()),
+            },
+            "enum" => {
+                if format.split(',').any(|v| v == value) {
+                    Ok(())
+                } else {
+                    Err(format!("Value must be one of {format}"))
+                }
+            }
+            "color" => {
+                let parts: Vec<&str> = value.split(',').collect();
+                if parts.len() == 3 && parts.iter().all(|p| p.trim().parse::<f64>().is_ok()) {
+                    Ok(())
+                } else {
+                    Err("Value must be a color triple".to_string())
+                }
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn property(datatype: &str, format: Option<&str>) -> HomieProperty {
+        HomieProperty {
+            datatype: Some(datatype.to_string()),
+            format: format.map(str::to_string),
+            ..new_property()
+        }
+    }
+
+    #[test]
+    fn validate_numbers() {
+        let dimmer = property("integer", Some("0:100"));
+        assert!(dimmer.validate("0").is_ok());
+        assert!(dimmer.validate("100").is_ok());
+        assert!(dimmer.validate("101").is_err());
+        assert!(dimmer.validate("-1").is_err());
+        assert!(dimmer.validate("50.5").is_err());
+        assert!(property("integer", None).validate("-12345").is_ok());
+
+        let temperature = property("float", Some("-20.5:40"));
+        assert!(temperature.validate("-20.5").is_ok());
+        assert!(temperature.validate("21").is_ok());
+        assert!(temperature.validate("40.1").is_err());
+        assert!(temperature.validate("warm").is_err());
+        // an unreadable format is not enforced
+        assert!(property("float", Some("low:high")).validate("1e6").is_ok());
+    }
+
+    #[test]
+    fn validate_other_datatypes() {
+        let switch = property("boolean", None);
+        assert!(switch.validate("true").is_ok());
+        assert!(switch.validate("false").is_ok());
+        assert!(switch.validate("on").is_err());
+
+        let mode = property("enum", Some("heat,cool,off"));
+        assert!(mode.validate("cool").is_ok());
+        assert!(mode.validate("auto").is_err());
+        assert!(mode.validate("heat,cool").is_err());
+
+        let color = property("color", Some("rgb"));
+        assert!(color.validate("255, 128,0").is_ok());
+        assert!(color.validate("255,128").is_err());
+        assert!(color.validate("red,green,blue").is_err());
+
+        assert!(property("string", None).validate("anything").is_ok());
+        assert!(HomieProperty::default().validate("anything").is_ok());
+    }
+
+    #[test]
+    fn apply_announcements() {
+        let mut device = HomieDevice::default();
+        device.apply(&["$nodes"], "light,sensor[1-2]".to_string());
+        device.apply(&["light", "$properties"], "on,level".to_string());
+        device.apply(&["light", "on", "$datatype"], "boolean".to_string());
+        device.apply(&["light", "on", "$settable"], "true".to_string());
+        device.apply(&["light", "level", "$retained"], "false".to_string());
+        device.apply(&["light", "on"], "true".to_string());
+        device.apply(&["light", "on", "set"], "false".to_string());
+        assert_eq!(device.nodes.keys().collect::<Vec<_>>(), ["light", "sensor"]);
+        let light = &device.nodes["light"];
+        assert!(light.properties["on"].settable);
+        assert!(light.properties["on"].retained);
+        assert!(!light.properties["level"].retained);
+        assert_eq!(light.properties["on"].value.as_deref(), Some("true"));
+        device.apply(&["$nodes"], "light".to_string());
+        device.apply(&["light", "$properties"], "on".to_string());
+        assert_eq!(device.nodes.len(), 1);
+        assert_eq!(device.nodes["light"].properties.len(), 1);
+    }
+}
@@ -0,0 +1,62 @@
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    Json,
+};
+use jwt_authorizer::JwtClaims;
+use tracing::debug;
+
+use crate::{
+    adapters::{AdapterRegistry, AdapterState, RelayCommand},
+    audit::Audit,
+    http::claims::Claims,
+    mqtta::MqttHandle,
+    ratelimit::RateLimits,
+};
+
+pub(crate) async fn adapters_handler(
+    JwtClaims(user): JwtClaims<Claims>,
+    State(adapters): State<AdapterRegistry>,
+) -> Json<Vec<AdapterState>> {
+    debug!("Adapter list request for user: {:?}", user);
+    Json(adapters.list().await)
+}
+
+pub(crate) async fn adapter_handler(
+    JwtClaims(user): JwtClaims<Claims>,
+    State(adapters): State<AdapterRegistry>,
+    Path(id): Path<String>,
+) -> Result<Json<AdapterState>, StatusCode> {
+    debug!("Adapter request for user: {:?}", user);
+    adapters
+        .get(&id)
+        .await
+        .map(Json)
+        .ok_or(StatusCode::NOT_FOUND)
+}
+
+pub(crate) async fn adapter_command_handler(
+    JwtClaims(user): JwtClaims<Claims>,
+    audit: Audit,
+    State(adapters): State<AdapterRegistry>,
+    State(mqtt): State<MqttHandle>,
+    State(ratelimits): State<RateLimits>,
+    Path(id): Path<String>,
+    Json(command): Json<RelayCommand>,
+) -> Result<String, Response> {
+    debug!("Adapter command request for user: {:?}", user);
+    let entry = audit.entry("adapter.command").target(&id);
+    let Some(payload) = adapters.command(&id, &command) else {
+        entry.write("Unknown adapter");
+        return Err(StatusCode::NOT_FOUND.into_response());
+    };
+    // rejected requests are not audited, a runaway client would flood the log
+    ratelimits
+        .check_topics(&[&payload.topic])
+        .map_err(IntoResponse::into_response)?;
+    let entry = entry.message(&payload);
+    Ok(entry
+        .record(async move { mqtt.publish(payload).await }, String::clone)
+        .await)
+}
@@ -0,0 +1,55 @@
+use axum::{
+    extract::{Query, State},
+    http::StatusCode,
+    Json,
+};
+use jwt_authorizer::JwtClaims;
+use serde::Serialize;
+use tracing::{debug, warn};
+
+use crate::{
+    audit::{AuditLog, AuditQuery, AuditRecord},
+    http::claims::Claims,
+};
+
+const DEFAULT_LIMIT: usize = 100;
+const MAX_LIMIT: usize = 1000;
+
+#[derive(Serialize)]
+pub(crate) struct AuditResponse {
+    offset: usize,
+    records: Vec<AuditRecord>,
+    /// More matching records follow after this page
+    more: bool,
+}
+
+pub(crate) async fn audit_handler(
+    JwtClaims(user): JwtClaims<Claims>,
+    State(audit): State<AuditLog>,
+    Query(query): Query<AuditQuery>,
+) -> Result<Json<AuditResponse>, (StatusCode, String)> {
+    debug!("Audit log request for user: {:?}", user);
+    if let Some(topic) = query.topic.as_ref().filter(|t| !rumqttc::valid_filter(t)) {
+        return Err((StatusCode::BAD_REQUEST, format!("Invalid topic {topic}")));
+    }
+    let limit = query.limit.unwrap_or(DEFAULT_LIMIT);
+    if limit == 0 || limit > MAX_LIMIT {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            format!("limit must be between 1 and {MAX_LIMIT}"),
+        ));
+    }
+    let offset = query.offset.unwrap_or(0);
+    let (records, more) = audit.query(query, limit).await.map_err(|e| {
+        warn!("Cannot query audit log: {:?}", e);
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "Cannot read audit log".to_string(),
+        )
+    })?;
+    Ok(Json(AuditResponse {
+        offset,
+        records,
+        more,
+    }))
+}
@@ -0,0 +1,68 @@
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    Json,
+};
+use jwt_authorizer::JwtClaims;
+use tracing::debug;
+
+use crate::{
+    audit::Audit,
+    devices::{Device, DeviceCommand, DeviceError, DeviceRegistry},
+    http::claims::Claims,
+    mqtta::MqttHandle,
+    ratelimit::RateLimits,
+};
+
+pub(crate) async fn devices_handler(
+    JwtClaims(user): JwtClaims<Claims>,
+    State(devices): State<DeviceRegistry>,
+) -> Json<Vec<Device>> {
+    debug!("Device list request for user: {:?}", user);
+    Json(devices.list().to_vec())
+}
+
+pub(crate) async fn device_handler(
+    JwtClaims(user): JwtClaims<Claims>,
+    State(devices): State<DeviceRegistry>,
+    Path(id): Path<String>,
+) -> Result<Json<Device>, StatusCode> {
+    debug!("Device request for user: {:?}", user);
+    devices
+        .get(&id)
+        .cloned()
+        .map(Json)
+        .ok_or(StatusCode::NOT_FOUND)
+}
+
+pub(crate) async fn device_command_handler(
+    JwtClaims(user): JwtClaims<Claims>,
+    audit: Audit,
+    State(devices): State<DeviceRegistry>,
+    State(mqtt): State<MqttHandle>,
+    State(ratelimits): State<RateLimits>,
+    Path((id, capability)): Path<(String, String)>,
+    Json(command): Json<DeviceCommand>,
+) -> Result<String, Response> {
+    debug!("Device command request for user: {:?}", user);
+    let entry = audit
+        .entry("device.command")
+        .target(format!("{id}/{capability}"));
+    let payload = devices
+        .command(&id, &capability, &command)
+        .map_err(|e| match e {
+            DeviceError::NotFound(m) => (StatusCode::NOT_FOUND, m),
+            DeviceError::Unsupported(m) => (StatusCode::BAD_REQUEST, m),
+        })
+        .inspect_err(|(_, e)| entry.write(e))
+        .map_err(IntoResponse::into_response)?;
+    // rejected requests are not audited, a runaway client would flood the log
+    ratelimits
+        .check_topics(&[&payload.topic])
+        .map_err(IntoResponse::into_response)?;
+    let entry = entry.message(&payload);
+    Ok(entry
+        .record(async move { mqtt.publish(payload).await }, String::clone)
+        .await)
+}
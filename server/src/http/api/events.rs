@@ -0,0 +1,128 @@
+use std::{convert::Infallible, sync::Arc};
+
+use axum::{
+    extract::State,
+    http::{HeaderMap, StatusCode},
+    response::sse::{Event, KeepAlive, Sse},
+};
+use axum_extra::extract::Query;
+use futures::{stream, Stream, StreamExt};
+use jwt_authorizer::JwtClaims;
+use serde::Deserialize;
+use tokio::sync::{oneshot, watch};
+use tracing::debug;
+
+use crate::{
+    http::claims::Claims,
+    mqtta::{message::ActorMessage, MqttHandle},
+};
+
+/// Most topics a single event stream may watch
+const MAX_TOPICS: usize = 50;
+
+#[derive(Deserialize)]
+pub(crate) struct EventsQuery {
+    #[serde(default)]
+    topic: Vec<String>,
+}
+
+/// `seq` of an update from the watch channel, 0 for the empty initial value
+fn update_seq(update: &str) -> u64 {
+    serde_json::from_str::<serde_json::Value>(update)
+        .ok()
+        .and_then(|u| u.get("seq").and_then(|s| s.as_u64()))
+        .unwrap_or(0)
+}
+
+/// Parse a `Last-Event-ID` made of the last `seq` per topic in query order
+fn last_event_id(headers: &HeaderMap, topics: usize) -> Option<Vec<u64>> {
+    let id = headers.get("last-event-id")?.to_str().ok()?;
+    let seqs = id
+        .split(',')
+        .map(|s| s.trim().parse::<u64>().ok())
+        .collect::<Option<Vec<u64>>>()?;
+    (seqs.len() == topics).then_some(seqs)
+}
+
+/// Updates of one topic with its position in the query. The cached value is
+/// sent first unless the client has already seen it, `seq` restarts when a
+/// topic is watched again so any difference counts as unseen.
+fn topic_updates(
+    index: usize,
+    mut w: watch::Receiver<Arc<String>>,
+    seen: Option<u64>,
+) -> impl Stream<Item = (usize, Arc<String>)> {
+    let current = w.borrow_and_update().clone();
+    let cached = (!current.is_empty() && seen.is_none_or(|seen| update_seq(&current) != seen))
+        .then_some((index, current));
+    stream::iter(cached).chain(stream::unfold(w, move |mut w| async move {
+        w.changed().await.ok()?;
+        let update = w.borrow_and_update().clone();
+        Some(((index, update), w))
+    }))
+}
+
+/// Server-sent events for clients that cannot use WebSockets. The event id
+/// holds the last `seq` of every topic, so a reconnecting client gets the
+/// cached value of each topic that changed in the meantime.
+pub(crate) async fn events_handler(
+    JwtClaims(user): JwtClaims<Claims>,
+    State(mqtt): State<MqttHandle>,
+    Query(query): Query<EventsQuery>,
+    headers: HeaderMap,
+) -> Result<Sse<impl Stream<Item = Result<Event, Infallible>>>, (StatusCode, String)> {
+    debug!("Event stream request for user: {:?}", user);
+    let topics = query.topic;
+    if topics.is_empty() || topics.len() > MAX_TOPICS {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            format!("Between 1 and {MAX_TOPICS} topic parameters required"),
+        ));
+    }
+    if let Some(topic) = topics.iter().find(|t| !rumqttc::valid_topic(t)) {
+        return Err((StatusCode::BAD_REQUEST, format!("Invalid topic {topic}")));
+    }
+    let last = last_event_id(&headers, topics.len());
+
+    // created first so the topics watched before a failed subscribe are released
+    let mut guard = mqtt.release_guard(Vec::with_capacity(topics.len()));
+    let mut updates = Vec::with_capacity(topics.len());
+    for (index, topic) in topics.into_iter().enumerate() {
+        let (tx, rx) = oneshot::channel::<watch::Receiver<Arc<String>>>();
+        mqtt.send(ActorMessage::Subscribe {
+            topic: topic.clone(),
+            respond_to: tx,
+        })
+        .await;
+        let w = rx.await.map_err(|_| {
+            (
+                StatusCode::SERVICE_UNAVAILABLE,
+                "Could not subscribe".to_string(),
+            )
+        })?;
+        guard.topics.push(topic);
+        updates.push(topic_updates(
+            index,
+            w,
+            last.as_ref().map(|seqs| seqs[index]),
+        ));
+    }
+
+    let mut seqs = last.unwrap_or_else(|| vec![0; guard.topics.len()]);
+    let events =
+        stream::select_all(updates.into_iter().map(Box::pin)).map(move |(index, update)| {
+            // the guard lives as long as the stream
+            let _ = &guard;
+            seqs[index] = update_seq(&update);
+            let id = seqs
+                .iter()
+                .map(|s| s.to_string())
+                .collect::<Vec<_>>()
+                .join(",");
+            Ok(Event::default()
+                .event("update")
+                .id(id)
+                .data(update.as_str()))
+        });
+    Ok(Sse::new(events).keep_alive(KeepAlive::default()))
+}
@@ -0,0 +1,91 @@
+use axum::{
+    extract::{
+        ws::{Message, WebSocket, WebSocketUpgrade},
+        State,
+    },
+    response::IntoResponse,
+    Json,
+};
+use futures::{sink::SinkExt, stream::StreamExt};
+use jwt_authorizer::JwtClaims;
+use serde_json::json;
+use tokio::sync::broadcast::error::RecvError;
+use tracing::debug;
+
+use crate::{
+    hass::{HassDeviceEntry, HassEntity, HassRegistry},
+    http::{claims::Claims, client::ClientInfo},
+};
+
+pub(crate) async fn hass_devices_handler(
+    JwtClaims(user): JwtClaims<Claims>,
+    State(hass): State<HassRegistry>,
+) -> Json<Vec<HassDeviceEntry>> {
+    debug!("Discovered devices request for user: {:?}", user);
+    Json(hass.devices().await)
+}
+
+pub(crate) async fn hass_entities_handler(
+    JwtClaims(user): JwtClaims<Claims>,
+    State(hass): State<HassRegistry>,
+) -> Json<Vec<HassEntity>> {
+    debug!("Discovered entities request for user: {:?}", user);
+    Json(hass.entities().await)
+}
+
+pub(crate) async fn hass_events_handler(
+    JwtClaims(user): JwtClaims<Claims>,
+    ws: WebSocketUpgrade,
+    client: ClientInfo,
+    State(hass): State<HassRegistry>,
+) -> impl IntoResponse {
+    debug!("Discovery event stream request for user: {:?}", user);
+    ws.on_upgrade(move |socket| handle_events(socket, client, hass))
+}
+
+async fn snapshot(hass: &HassRegistry) -> Message {
+    Message::Text(
+        json!({
+            "type": "snapshot",
+            "entities": hass.entities().await,
+        })
+        .to_string(),
+    )
+}
+
+/// Send the current entity list, then every change until the client leaves
+async fn handle_events(socket: WebSocket, who: ClientInfo, hass: HassRegistry) {
+    let mut events = hass.subscribe();
+    let (mut ws_client_sender, mut ws_client_receiver) = socket.split();
+    if ws_client_sender.send(snapshot(&hass).await).await.is_err() {
+        debug!("Could not send snapshot to {who}");
+        return;
+    }
+
+    loop {
+        tokio::select! {
+            event = events.recv() => {
+                let m = match event {
+                    Ok(event) => match serde_json::to_string(&event) {
+                        Ok(text) => Message::Text(text),
+                        Err(_) => continue,
+                    },
+                    // Missed events, start over with a full list
+                    Err(RecvError::Lagged(_)) => snapshot(&hass).await,
+                    Err(RecvError::Closed) => break,
+                };
+                if ws_client_sender.send(m).await.is_err() {
+                    break;
+                }
+            }
+            ws = ws_client_receiver.next() => {
+                match ws {
+                    None | Some(Ok(Message::Close(_))) | Some(Err(_)) => break,
+                    Some(_) => {}
+                }
+            }
+        }
+    }
+
+    debug!("Discovery event stream {who} closed");
+}
@@ -0,0 +1,40 @@
+use axum::{extract::State, http::StatusCode, Json};
+use serde::Serialize;
+
+use crate::{mqtta::MqttHandle, oidc::OidcStatus};
+
+#[derive(Serialize)]
+pub(crate) struct Readiness {
+    ready: bool,
+    mqtt_connected: bool,
+    oidc_keys_loaded: bool,
+}
+
+/// Liveness, answers as long as the server handles requests
+pub(crate) async fn healthz_handler() -> &'static str {
+    "OK"
+}
+
+/// Readiness, fails while the broker connection is down or the OIDC keys
+/// cannot be loaded
+pub(crate) async fn readyz_handler(
+    State(mqtt): State<MqttHandle>,
+    State(oidc): State<OidcStatus>,
+) -> (StatusCode, Json<Readiness>) {
+    let mqtt_connected = mqtt.connected();
+    let oidc_keys_loaded = oidc.keys_loaded();
+    let ready = mqtt_connected && oidc_keys_loaded;
+    let status = if ready {
+        StatusCode::OK
+    } else {
+        StatusCode::SERVICE_UNAVAILABLE
+    };
+    (
+        status,
+        Json(Readiness {
+            ready,
+            mqtt_connected,
+            oidc_keys_loaded,
+        }),
+    )
+}
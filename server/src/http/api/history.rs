@@ -0,0 +1,72 @@
+use axum::{
+    extract::{Query, State},
+    http::StatusCode,
+    Json,
+};
+use jwt_authorizer::JwtClaims;
+use serde::{Deserialize, Serialize};
+use tracing::debug;
+
+use crate::{
+    datadir::now_millis,
+    history::{Bucket, HistoryStore},
+    http::claims::Claims,
+};
+
+const DEFAULT_RANGE_MS: u64 = 24 * 60 * 60 * 1000;
+const DEFAULT_STEP_SECONDS: u64 = 300;
+const MAX_BUCKETS: u64 = 10_000;
+
+#[derive(Deserialize)]
+pub(crate) struct HistoryQuery {
+    pub topic: String,
+    /// Start of the range in milliseconds since the unix epoch
+    pub from: Option<u64>,
+    /// End of the range in milliseconds since the unix epoch
+    pub to: Option<u64>,
+    /// Bucket size in seconds
+    pub step: Option<u64>,
+}
+
+#[derive(Serialize)]
+pub(crate) struct HistoryResponse {
+    topic: String,
+    from: u64,
+    to: u64,
+    step: u64,
+    points: Vec<Bucket>,
+}
+
+pub(crate) async fn history_handler(
+    JwtClaims(user): JwtClaims<Claims>,
+    State(history): State<HistoryStore>,
+    Query(query): Query<HistoryQuery>,
+) -> Result<Json<HistoryResponse>, (StatusCode, String)> {
+    debug!("History request for user: {:?}", user);
+    if !history.contains(&query.topic).await {
+        return Err((StatusCode::NOT_FOUND, "Topic is not recorded".to_string()));
+    }
+    let to = query.to.unwrap_or_else(now_millis);
+    let from = query.from.unwrap_or(to.saturating_sub(DEFAULT_RANGE_MS));
+    if from >= to {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            "from must be before to".to_string(),
+        ));
+    }
+    let step = query.step.unwrap_or(DEFAULT_STEP_SECONDS);
+    if step == 0 || (to - from) / step.saturating_mul(1000) > MAX_BUCKETS {
+        return Err((StatusCode::BAD_REQUEST, "Invalid step".to_string()));
+    }
+
+    let points = history
+        .query(&query.topic, from, to, step.saturating_mul(1000))
+        .await;
+    Ok(Json(HistoryResponse {
+        topic: query.topic,
+        from,
+        to,
+        step,
+        points,
+    }))
+}
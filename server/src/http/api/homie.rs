@@ -0,0 +1,90 @@
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    Json,
+};
+use jwt_authorizer::JwtClaims;
+use serde::Deserialize;
+use tracing::debug;
+
+use crate::{
+    audit::Audit,
+    homie::{HomieDevice, HomieError, HomieRegistry},
+    http::claims::Claims,
+    mqtta::MqttHandle,
+    ratelimit::RateLimits,
+};
+
+#[derive(Deserialize)]
+pub(crate) struct HomieSetRequest {
+    pub value: String,
+}
+
+fn disabled() -> (StatusCode, String) {
+    (
+        StatusCode::NOT_FOUND,
+        "Homie discovery disabled".to_string(),
+    )
+}
+
+pub(crate) async fn homie_devices_handler(
+    JwtClaims(user): JwtClaims<Claims>,
+    State(homie): State<HomieRegistry>,
+) -> Result<Json<Vec<HomieDevice>>, (StatusCode, String)> {
+    debug!("Homie devices request for user: {:?}", user);
+    if !homie.enabled() {
+        return Err(disabled());
+    }
+    Ok(Json(homie.devices().await))
+}
+
+pub(crate) async fn homie_device_handler(
+    JwtClaims(user): JwtClaims<Claims>,
+    State(homie): State<HomieRegistry>,
+    Path(id): Path<String>,
+) -> Result<Json<HomieDevice>, (StatusCode, String)> {
+    debug!("Homie device request for user: {:?}", user);
+    homie
+        .device(&id)
+        .await
+        .map(Json)
+        .ok_or((StatusCode::NOT_FOUND, "Unknown device".to_string()))
+}
+
+pub(crate) async fn homie_set_handler(
+    JwtClaims(user): JwtClaims<Claims>,
+    audit: Audit,
+    State(homie): State<HomieRegistry>,
+    State(mqtt): State<MqttHandle>,
+    State(ratelimits): State<RateLimits>,
+    Path((device, node, property)): Path<(String, String, String)>,
+    Json(request): Json<HomieSetRequest>,
+) -> Result<String, Response> {
+    debug!("Homie set request for user: {:?}", user);
+    let entry = audit
+        .entry("homie.set")
+        .target(format!("{device}/{node}/{property}"));
+    let payload = homie
+        .set_property(&device, &node, &property, &request.value)
+        .await
+        .map_err(|e| match e {
+            HomieError::Disabled => disabled(),
+            HomieError::NotFound => (StatusCode::NOT_FOUND, "Unknown property".to_string()),
+            HomieError::NotSettable => (
+                StatusCode::BAD_REQUEST,
+                "Property is not settable".to_string(),
+            ),
+            HomieError::Invalid(m) => (StatusCode::BAD_REQUEST, m),
+        })
+        .inspect_err(|(_, e)| entry.write(e))
+        .map_err(IntoResponse::into_response)?;
+    // rejected requests are not audited, a runaway client would flood the log
+    ratelimits
+        .check_topics(&[&payload.topic])
+        .map_err(IntoResponse::into_response)?;
+    let entry = entry.message(&payload);
+    Ok(entry
+        .record(async move { mqtt.publish(payload).await }, String::clone)
+        .await)
+}
@@ -0,0 +1,28 @@
+use axum::{
+    extract::State,
+    http::{header, HeaderMap, StatusCode},
+    response::IntoResponse,
+};
+use tracing::debug;
+
+use crate::{http::websocket::WebSockets, metrics::Metrics, mqtta::MqttHandle};
+
+/// Prometheus metrics, protected by `HCS_METRICS_TOKEN` instead of a JWT
+pub(crate) async fn metrics_handler(
+    State(metrics): State<Metrics>,
+    State(mqtt): State<MqttHandle>,
+    State(websockets): State<WebSockets>,
+    headers: HeaderMap,
+) -> Result<impl IntoResponse, StatusCode> {
+    let authorization = headers
+        .get(header::AUTHORIZATION)
+        .and_then(|h| h.to_str().ok());
+    if !metrics.authorized(authorization) {
+        debug!("Unauthorized metrics request");
+        return Err(StatusCode::UNAUTHORIZED);
+    }
+    Ok((
+        [(header::CONTENT_TYPE, metrics.content_type())],
+        metrics.render(mqtt.queue_depth(), &websockets.stats),
+    ))
+}
@@ -1,3 +1,17 @@
+pub(crate) mod adapters;
+pub(crate) mod audit;
+pub(crate) mod devices;
+pub(crate) mod events;
+pub(crate) mod hass;
+pub(crate) mod health;
+pub(crate) mod history;
+pub(crate) mod homie;
+pub(crate) mod metrics;
+pub(crate) mod rules;
+pub(crate) mod scenes;
+pub(crate) mod schedules;
 pub(crate) mod status;
+pub(crate) mod timers;
 pub(crate) mod web2mqtt;
 pub(crate) mod ws;
+pub(crate) mod zigbee;
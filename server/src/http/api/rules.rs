@@ -0,0 +1,132 @@
+use axum::{
+    extract::{Path, Query, State},
+    http::StatusCode,
+    Json,
+};
+use jwt_authorizer::JwtClaims;
+use serde::Deserialize;
+use tracing::debug;
+
+use crate::{
+    audit::{outcome, Audit},
+    http::claims::Claims,
+    rules::{FireRecord, Rule, RuleDefinition, RuleEngine, RuleError},
+};
+
+const DEFAULT_LOG_LIMIT: usize = 100;
+
+#[derive(Deserialize)]
+pub(crate) struct RuleLogQuery {
+    /// Only entries of this rule
+    pub rule: Option<String>,
+    pub limit: Option<usize>,
+}
+
+fn rule_error(e: RuleError) -> (StatusCode, String) {
+    match e {
+        RuleError::NotFound => (StatusCode::NOT_FOUND, "Unknown rule".to_string()),
+        RuleError::ReadOnly => (
+            StatusCode::CONFLICT,
+            "Rule is defined in the configuration".to_string(),
+        ),
+        RuleError::Invalid(m) => (StatusCode::BAD_REQUEST, m),
+    }
+}
+
+pub(crate) async fn rules_handler(
+    JwtClaims(user): JwtClaims<Claims>,
+    State(rules): State<RuleEngine>,
+) -> Json<Vec<Rule>> {
+    debug!("Rule list request for user: {:?}", user);
+    Json(rules.list().await)
+}
+
+pub(crate) async fn rule_handler(
+    JwtClaims(user): JwtClaims<Claims>,
+    State(rules): State<RuleEngine>,
+    Path(id): Path<String>,
+) -> Result<Json<Rule>, StatusCode> {
+    debug!("Rule request for user: {:?}", user);
+    rules.get(&id).await.map(Json).ok_or(StatusCode::NOT_FOUND)
+}
+
+pub(crate) async fn rule_create_handler(
+    JwtClaims(user): JwtClaims<Claims>,
+    audit: Audit,
+    State(rules): State<RuleEngine>,
+    Json(definition): Json<RuleDefinition>,
+) -> Result<(StatusCode, Json<Rule>), (StatusCode, String)> {
+    debug!("Rule create request for user: {:?}", user);
+    audit
+        .entry("rule.create")
+        .definition(&definition)
+        .record(
+            async move {
+                rules
+                    .create(definition)
+                    .await
+                    .map(|rule| (StatusCode::CREATED, Json(rule)))
+                    .map_err(rule_error)
+            },
+            outcome,
+        )
+        .await
+}
+
+pub(crate) async fn rule_update_handler(
+    JwtClaims(user): JwtClaims<Claims>,
+    audit: Audit,
+    State(rules): State<RuleEngine>,
+    Path(id): Path<String>,
+    Json(definition): Json<RuleDefinition>,
+) -> Result<Json<Rule>, (StatusCode, String)> {
+    debug!("Rule update request for user: {:?}", user);
+    audit
+        .entry("rule.update")
+        .target(&id)
+        .definition(&definition)
+        .record(
+            async move {
+                rules
+                    .update(&id, definition)
+                    .await
+                    .map(Json)
+                    .map_err(rule_error)
+            },
+            outcome,
+        )
+        .await
+}
+
+pub(crate) async fn rule_delete_handler(
+    JwtClaims(user): JwtClaims<Claims>,
+    audit: Audit,
+    State(rules): State<RuleEngine>,
+    Path(id): Path<String>,
+) -> Result<StatusCode, (StatusCode, String)> {
+    debug!("Rule delete request for user: {:?}", user);
+    audit
+        .entry("rule.delete")
+        .target(&id)
+        .record(
+            async move {
+                rules
+                    .delete(&id)
+                    .await
+                    .map(|_| StatusCode::NO_CONTENT)
+                    .map_err(rule_error)
+            },
+            outcome,
+        )
+        .await
+}
+
+pub(crate) async fn rule_log_handler(
+    JwtClaims(user): JwtClaims<Claims>,
+    State(rules): State<RuleEngine>,
+    Query(query): Query<RuleLogQuery>,
+) -> Json<Vec<FireRecord>> {
+    debug!("Rule log request for user: {:?}", user);
+    let limit = query.limit.unwrap_or(DEFAULT_LOG_LIMIT);
+    Json(rules.log(query.rule.as_deref(), limit).await)
+}
@@ -0,0 +1,89 @@
+use std::time::Duration;
+
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    Json,
+};
+use jwt_authorizer::JwtClaims;
+use serde::Serialize;
+use tracing::debug;
+
+use crate::{
+    audit::Audit,
+    http::claims::Claims,
+    mqtta::MqttHandle,
+    ratelimit::RateLimits,
+    scenes::{Scene, Scenes, StepResult},
+};
+
+/// Longest total delay of a scene activated over HTTP, activation has to
+/// finish within the 10 second request timeout
+const MAX_ACTIVATION_DELAY: Duration = Duration::from_secs(8);
+
+#[derive(Serialize)]
+pub(crate) struct ActivationResponse {
+    scene: String,
+    ok: bool,
+    steps: Vec<StepResult>,
+}
+
+fn activation_outcome(steps: &Option<Vec<StepResult>>) -> String {
+    let Some(steps) = steps else {
+        return String::from("Unknown scene");
+    };
+    match steps.iter().filter(|s| !s.ok()).count() {
+        0 => String::from("OK"),
+        failed => format!("{failed} of {} steps failed", steps.len()),
+    }
+}
+
+pub(crate) async fn scenes_handler(
+    JwtClaims(user): JwtClaims<Claims>,
+    State(scenes): State<Scenes>,
+) -> Json<Vec<Scene>> {
+    debug!("Scene list request for user: {:?}", user);
+    Json(scenes.list().to_vec())
+}
+
+pub(crate) async fn scene_activate_handler(
+    JwtClaims(user): JwtClaims<Claims>,
+    audit: Audit,
+    State(scenes): State<Scenes>,
+    State(mqtt): State<MqttHandle>,
+    State(ratelimits): State<RateLimits>,
+    Path(id): Path<String>,
+) -> Result<Json<ActivationResponse>, Response> {
+    debug!("Scene activation request for user: {:?}", user);
+    // rejected requests are not audited, a runaway client would flood the log
+    if let Some(scene) = scenes.get(&id) {
+        ratelimits
+            .check_topics(&scene.topics())
+            .map_err(IntoResponse::into_response)?;
+        if scene.delay() > MAX_ACTIVATION_DELAY {
+            let message = format!(
+                "Delays of the scene add up to {} ms, at most {} ms fit into a request",
+                scene.delay().as_millis(),
+                MAX_ACTIVATION_DELAY.as_millis()
+            );
+            audit.entry("scene.activate").target(&id).write(&message);
+            return Err((StatusCode::BAD_REQUEST, message).into_response());
+        }
+    }
+    let scene = id.clone();
+    let steps = audit
+        .entry("scene.activate")
+        .target(&id)
+        .record(
+            async move { scenes.activate(&mqtt, &scene).await },
+            activation_outcome,
+        )
+        .await
+        .ok_or_else(|| StatusCode::NOT_FOUND.into_response())?;
+    Ok(Json(ActivationResponse {
+        scene: id,
+        ok: steps.iter().all(StepResult::ok),
+        steps,
+    }))
+}
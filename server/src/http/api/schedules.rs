@@ -0,0 +1,103 @@
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    Json,
+};
+use jwt_authorizer::JwtClaims;
+use tracing::debug;
+
+use crate::{
+    audit::{outcome, Audit},
+    http::claims::Claims,
+    scheduler::{ScheduleDefinition, ScheduleEntry, Scheduler},
+};
+
+pub(crate) async fn schedules_handler(
+    JwtClaims(user): JwtClaims<Claims>,
+    State(scheduler): State<Scheduler>,
+) -> Json<Vec<ScheduleEntry>> {
+    debug!("Schedule list request for user: {:?}", user);
+    Json(scheduler.list().await)
+}
+
+pub(crate) async fn schedule_handler(
+    JwtClaims(user): JwtClaims<Claims>,
+    State(scheduler): State<Scheduler>,
+    Path(id): Path<String>,
+) -> Result<Json<ScheduleEntry>, StatusCode> {
+    debug!("Schedule request for user: {:?}", user);
+    scheduler
+        .get(&id)
+        .await
+        .map(Json)
+        .ok_or(StatusCode::NOT_FOUND)
+}
+
+pub(crate) async fn schedule_create_handler(
+    JwtClaims(user): JwtClaims<Claims>,
+    audit: Audit,
+    State(scheduler): State<Scheduler>,
+    Json(definition): Json<ScheduleDefinition>,
+) -> Result<(StatusCode, Json<ScheduleEntry>), (StatusCode, String)> {
+    debug!("Schedule create request for user: {:?}", user);
+    audit
+        .entry("schedule.create")
+        .definition(&definition)
+        .record(
+            async move {
+                scheduler
+                    .create(definition)
+                    .await
+                    .map(|entry| (StatusCode::CREATED, Json(entry)))
+                    .map_err(|e| (StatusCode::BAD_REQUEST, e))
+            },
+            outcome,
+        )
+        .await
+}
+
+pub(crate) async fn schedule_update_handler(
+    JwtClaims(user): JwtClaims<Claims>,
+    audit: Audit,
+    State(scheduler): State<Scheduler>,
+    Path(id): Path<String>,
+    Json(definition): Json<ScheduleDefinition>,
+) -> Result<Json<ScheduleEntry>, (StatusCode, String)> {
+    debug!("Schedule update request for user: {:?}", user);
+    audit
+        .entry("schedule.update")
+        .target(&id)
+        .definition(&definition)
+        .record(
+            async move {
+                match scheduler.update(&id, definition).await {
+                    Ok(Some(entry)) => Ok(Json(entry)),
+                    Ok(None) => Err((StatusCode::NOT_FOUND, "Unknown schedule".to_string())),
+                    Err(e) => Err((StatusCode::BAD_REQUEST, e)),
+                }
+            },
+            outcome,
+        )
+        .await
+}
+
+pub(crate) async fn schedule_delete_handler(
+    JwtClaims(user): JwtClaims<Claims>,
+    audit: Audit,
+    State(scheduler): State<Scheduler>,
+    Path(id): Path<String>,
+) -> StatusCode {
+    debug!("Schedule delete request for user: {:?}", user);
+    let deleted = audit
+        .entry("schedule.delete")
+        .target(&id)
+        .record(async move { scheduler.delete(&id).await }, |deleted| {
+            String::from(if *deleted { "OK" } else { "Unknown schedule" })
+        })
+        .await;
+    if deleted {
+        StatusCode::NO_CONTENT
+    } else {
+        StatusCode::NOT_FOUND
+    }
+}
@@ -1,12 +1,15 @@
 use axum::extract::State;
-use jwt_authorizer::{JwtClaims, RegisteredClaims};
+use jwt_authorizer::JwtClaims;
 use tokio::sync::oneshot;
 use tracing::debug;
 
-use crate::mqtta::{message::ActorMessage, MqttHandle};
+use crate::{
+    http::claims::Claims,
+    mqtta::{message::ActorMessage, MqttHandle},
+};
 
 pub(crate) async fn status_handler(
-    JwtClaims(user): JwtClaims<RegisteredClaims>,
+    JwtClaims(user): JwtClaims<Claims>,
     State(mqtt): State<MqttHandle>,
 ) -> String {
     debug!("Status request for user: {:?}", user);
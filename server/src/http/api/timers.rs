@@ -0,0 +1,84 @@
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    Json,
+};
+use jwt_authorizer::JwtClaims;
+use tracing::debug;
+
+use crate::{
+    audit::{outcome, Audit},
+    http::claims::Claims,
+    ratelimit::RateLimits,
+    scenes::Scenes,
+    timers::{TimerEntry, TimerRequest, Timers},
+};
+
+pub(crate) async fn timers_handler(
+    JwtClaims(user): JwtClaims<Claims>,
+    State(timers): State<Timers>,
+) -> Json<Vec<TimerEntry>> {
+    debug!("Timer list request for user: {:?}", user);
+    Json(timers.list().await)
+}
+
+pub(crate) async fn timer_handler(
+    JwtClaims(user): JwtClaims<Claims>,
+    State(timers): State<Timers>,
+    Path(id): Path<String>,
+) -> Result<Json<TimerEntry>, StatusCode> {
+    debug!("Timer request for user: {:?}", user);
+    timers.get(&id).await.map(Json).ok_or(StatusCode::NOT_FOUND)
+}
+
+pub(crate) async fn timer_create_handler(
+    JwtClaims(user): JwtClaims<Claims>,
+    audit: Audit,
+    State(timers): State<Timers>,
+    State(scenes): State<Scenes>,
+    State(ratelimits): State<RateLimits>,
+    Json(request): Json<TimerRequest>,
+) -> Result<(StatusCode, Json<TimerEntry>), Response> {
+    debug!("Timer create request for user: {:?}", user);
+    // rejected requests are not audited, a runaway client would flood the log
+    ratelimits
+        .check_topics(&request.action().topics(&scenes))
+        .map_err(IntoResponse::into_response)?;
+    audit
+        .entry("timer.create")
+        .definition(&request)
+        .record(
+            async move {
+                timers
+                    .create(request)
+                    .await
+                    .map(|entry| (StatusCode::CREATED, Json(entry)))
+                    .map_err(|e| (StatusCode::BAD_REQUEST, e))
+            },
+            outcome,
+        )
+        .await
+        .map_err(IntoResponse::into_response)
+}
+
+pub(crate) async fn timer_cancel_handler(
+    JwtClaims(user): JwtClaims<Claims>,
+    audit: Audit,
+    State(timers): State<Timers>,
+    Path(id): Path<String>,
+) -> StatusCode {
+    debug!("Timer cancel request for user: {:?}", user);
+    let cancelled = audit
+        .entry("timer.cancel")
+        .target(&id)
+        .record(async move { timers.cancel(&id).await }, |cancelled| {
+            String::from(if *cancelled { "OK" } else { "Unknown timer" })
+        })
+        .await;
+    if cancelled {
+        StatusCode::NO_CONTENT
+    } else {
+        StatusCode::NOT_FOUND
+    }
+}
@@ -1,13 +1,16 @@
 use axum::{debug_handler, extract::State, Json};
-use jwt_authorizer::{JwtClaims, RegisteredClaims};
-use rumqttc::QoS;
+use jwt_authorizer::JwtClaims;
 use serde::Deserialize;
-use tokio::sync::oneshot;
 use tracing::debug;
 
-use crate::mqtta::{
-    message::{ActorMessage, PublishMessage},
-    MqttHandle,
+use crate::{
+    audit::Audit,
+    http::{appstate::AppState, claims::Claims, client::ClientInfo},
+    mqtta::{
+        message::{qos_from_u8, PublishMessage},
+        MqttHandle,
+    },
+    ratelimit::{RateLimited, RateLimits},
 };
 
 #[derive(Deserialize)]
@@ -18,34 +21,26 @@ pub(crate) struct Web2MqttRequestBody {
     pub retain: bool,
 }
 
-#[debug_handler]
+#[debug_handler(state = AppState)]
 pub(crate) async fn web2mqtt_handler(
-    JwtClaims(user): JwtClaims<RegisteredClaims>,
+    JwtClaims(user): JwtClaims<Claims>,
+    client: ClientInfo,
+    audit: Audit,
     State(mqtt): State<MqttHandle>,
+    State(ratelimits): State<RateLimits>,
     Json(payload): Json<Web2MqttRequestBody>,
-) -> String {
+) -> Result<String, RateLimited> {
     debug!("Publish request for user: {:?}", user);
+    // rejected requests are not audited, a runaway client would flood the log
+    ratelimits.check(user.sub(), client.ip, Some(&payload.topic))?;
     let payload = PublishMessage::builder()
         .topic(payload.topic.clone())
         .value(payload.value.clone().into_bytes())
-        .qos(match payload.qos {
-            2 => QoS::ExactlyOnce,
-            1 => QoS::AtLeastOnce,
-            _ => QoS::AtMostOnce,
-        })
+        .qos(qos_from_u8(payload.qos))
         .retain(payload.retain)
         .build();
-    let (tx, rx) = oneshot::channel::<String>();
-    tokio::spawn(async move {
-        mqtt.send(ActorMessage::Publish {
-            payload,
-            respond_to: tx,
-        })
-        .await;
-    });
-
-    match rx.await {
-        Ok(v) => v,
-        Err(_) => "No response".to_string(),
-    }
+    let entry = audit.entry("publish").message(&payload);
+    Ok(entry
+        .record(async move { mqtt.publish(payload).await }, String::clone)
+        .await)
 }
@@ -1,4 +1,8 @@
-use std::{net::SocketAddr, sync::Arc};
+use std::{
+    net::IpAddr,
+    sync::{atomic::Ordering, Arc},
+    time::Duration,
+};
 
 use axum::{
     extract::{
@@ -6,34 +10,136 @@ use axum::{
         State,
     },
     response::IntoResponse,
+    Json,
 };
 use axum_extra::TypedHeader;
-
-//allows to extract the IP of connecting user
-use axum::extract::connect_info::ConnectInfo;
-
 use color_eyre::eyre::{eyre, Context, OptionExt, Result};
 //allows to split the websocket stream into separate TX and RX branches
 use futures::{sink::SinkExt, stream::StreamExt};
-use jwt_authorizer::{JwtClaims, RegisteredClaims};
+use jwt_authorizer::JwtClaims;
+use serde_json::json;
 use tokio::{
-    sync::{mpsc, oneshot, watch},
+    sync::{broadcast::error::RecvError, oneshot, watch},
     task::JoinHandle,
+    time::Instant,
+};
+use tracing::{debug, error, warn};
+
+use crate::{
+    datadir::now_millis,
+    http::{
+        claims::Claims,
+        client::ClientInfo,
+        websocket::{WebSockets, WsStats, WsStatsSnapshot},
+    },
+    jsonpath::parse_number,
+    mqtta::{message::ActorMessage, MqttHandle},
+    ratelimit::{RateLimited, RateLimits},
+    timers::Timers,
 };
-use tracing::{debug, error};
 
-use crate::mqtta::{message::ActorMessage, MqttHandle};
+/// Per subscription limits for the updates sent to the client
+#[derive(Debug)]
+struct SubscriptionOptions {
+    /// Minimum time between two updates, the latest value is sent when the
+    /// interval has passed
+    min_interval: Option<Duration>,
+    /// Skip numeric values closer than this to the last value sent
+    deadband: Option<f64>,
+    /// Skip values equal to the last value sent
+    only_on_change: bool,
+}
+
+/// State of the options for one subscription
+struct SubscriptionFilter {
+    options: SubscriptionOptions,
+    last_data: Option<String>,
+    last_sent: Option<Instant>,
+}
+
+impl SubscriptionFilter {
+    fn new(options: SubscriptionOptions) -> Self {
+        Self {
+            options,
+            last_data: None,
+            last_sent: None,
+        }
+    }
+
+    /// Whether an update differs enough from the last one sent
+    fn passes(&self, data: &str) -> bool {
+        let Some(last) = &self.last_data else {
+            return true;
+        };
+        if self.options.only_on_change && last == data {
+            return false;
+        }
+        if let Some(deadband) = self.options.deadband {
+            if let (Some(last), Some(new)) = (parse_number(last), parse_number(data)) {
+                return (new - last).abs() >= deadband;
+            }
+        }
+        true
+    }
+
+    /// Earliest time the next update may be sent
+    fn next_allowed(&self) -> Option<Instant> {
+        Some(self.last_sent? + self.options.min_interval?)
+    }
+
+    fn sent(&mut self, data: String) {
+        self.last_data = Some(data);
+        self.last_sent = Some(Instant::now());
+    }
+}
+
+/// The `data` of an update from the watch channel
+fn update_data(update: &str) -> String {
+    serde_json::from_str::<serde_json::Value>(update)
+        .ok()
+        .and_then(|u| u.get("data").and_then(|d| d.as_str()).map(String::from))
+        .unwrap_or_default()
+}
 
 enum WSIncomingMessage {
-    Subscribe { topic: String },
+    Subscribe {
+        topic: String,
+        options: SubscriptionOptions,
+    },
+    /// Forward timer events to the client
+    Timers,
+    /// Application level ping for browsers that hide pong frames
+    Ping,
+}
+
+impl WSIncomingMessage {
+    /// Take a token for the command, pings are never limited so an idle
+    /// client is not taken for dead
+    fn rate_limit(
+        &self,
+        ratelimits: &RateLimits,
+        user: Option<&str>,
+        ip: IpAddr,
+    ) -> Result<(), RateLimited> {
+        match self {
+            Self::Subscribe { topic, .. } => ratelimits.check(user, ip, Some(topic)),
+            Self::Timers => ratelimits.check(user, ip, None),
+            Self::Ping => Ok(()),
+        }
+    }
 }
 
+// one extractor per state, as in the other handlers
+#[allow(clippy::too_many_arguments)]
 pub(crate) async fn ws_handler(
-    JwtClaims(user): JwtClaims<RegisteredClaims>,
+    JwtClaims(user): JwtClaims<Claims>,
     ws: WebSocketUpgrade,
     user_agent: Option<TypedHeader<headers::UserAgent>>,
-    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    client: ClientInfo,
     State(mqtt): State<MqttHandle>,
+    State(timers): State<Timers>,
+    State(websockets): State<WebSockets>,
+    State(ratelimits): State<RateLimits>,
 ) -> impl IntoResponse {
     debug!("Websocket request for user: {:?}", user);
     let user_agent = if let Some(TypedHeader(user_agent)) = user_agent {
@@ -41,14 +147,40 @@ pub(crate) async fn ws_handler(
     } else {
         String::from("Unknown browser")
     };
-    debug!("`{user_agent}` at {addr} connected.");
+    debug!("`{user_agent}` at {client} connected.");
     // finalize the upgrade process by returning upgrade callback.
     // we can customize the callback by sending additional info such as address.
-    ws.on_upgrade(move |socket| handle_socket(socket, addr, mqtt))
+    ws.on_upgrade(move |socket| {
+        handle_socket(
+            socket,
+            client,
+            user.registered.sub,
+            mqtt,
+            timers,
+            websockets,
+            ratelimits,
+        )
+    })
+}
+
+pub(crate) async fn ws_stats_handler(
+    JwtClaims(user): JwtClaims<Claims>,
+    State(websockets): State<WebSockets>,
+) -> Json<WsStatsSnapshot> {
+    debug!("Websocket stats request for user: {:?}", user);
+    Json(websockets.stats.snapshot())
 }
 
 /// Actual websocket statemachine (one will be spawned per connection)
-async fn handle_socket(mut socket: WebSocket, who: SocketAddr, mqtt: MqttHandle) {
+async fn handle_socket(
+    mut socket: WebSocket,
+    who: ClientInfo,
+    user: Option<String>,
+    mqtt: MqttHandle,
+    timers: Timers,
+    websockets: WebSockets,
+    ratelimits: RateLimits,
+) {
     // send a ping (unsupported by some browsers) just to kick things off and get a response
     if socket.send(Message::Ping(vec![1, 2, 3])).await.is_ok() {
         debug!("Pinged {who}...");
@@ -62,40 +194,103 @@ async fn handle_socket(mut socket: WebSocket, who: SocketAddr, mqtt: MqttHandle)
     // By splitting socket we can send and receive at the same time. In this example we will send
     // unsolicited messages to client based on some sort of server's internal event (i.e .timer).
     let (mut ws_client_sender, mut ws_client_receiver) = socket.split();
-    let (subscription_updates_tx, mut subscription_updates_rx) = mpsc::channel::<Arc<String>>(100);
+    // watcher tasks never wait for a slow client, the queue applies the backpressure policy
+    let subscription_updates = websockets.queue();
+    let stats = websockets.stats.clone();
+    WsStats::inc(&stats.connections);
+
+    let pong_timeout = websockets.pong_timeout;
+    let mut ping = tokio::time::interval(
+        websockets
+            .ping_interval
+            .unwrap_or(Duration::from_secs(3600)),
+    );
+    ping.tick().await;
+    // set when a ping was sent, cleared by any frame from the client
+    let mut pong_deadline: Option<Instant> = None;
 
     let mut tasks: Vec<(oneshot::Sender<()>, JoinHandle<()>)> = Vec::new();
+    let mut topics: Vec<String> = Vec::new();
+    // one timer event task per socket, events are not sent twice
+    let mut timer_events = false;
     loop {
         tokio::select! {
             // Forward incoming value updates to ws_client
-            Some(v) = subscription_updates_rx.recv() => {
-                    let m = Message::Text(v.to_string());
-                    let _ = ws_client_sender.send(m).await;
+            v = subscription_updates.pop() => {
+                let Some(v) = v else {
+                    warn!("Closing websocket {who}, client too slow");
+                    WsStats::inc(&stats.slow_disconnects);
+                    let _ = ws_client_sender.send(Message::Close(None)).await;
+                    break;
+                };
+                let m = Message::Text(v.to_string());
+                match tokio::time::timeout(pong_timeout, ws_client_sender.send(m)).await {
+                    Ok(Ok(_)) => WsStats::inc(&stats.messages_sent),
+                    Ok(Err(e)) => {
+                        debug!("Could not send to {who}: {:?}", e);
+                        break;
+                    }
+                    Err(_) => {
+                        warn!("Closing websocket {who}, send timed out");
+                        WsStats::inc(&stats.dead_disconnects);
+                        break;
+                    }
+                }
+            }
+            _ = ping.tick(), if websockets.ping_interval.is_some() => {
+                if pong_deadline.is_none() {
+                    pong_deadline = Some(Instant::now() + pong_timeout);
+                }
+                if ws_client_sender.send(Message::Ping(vec![1, 2, 3])).await.is_err() {
+                    debug!("Could not send ping {who}!");
+                    break;
+                }
+            }
+            _ = tokio::time::sleep_until(pong_deadline.unwrap_or_else(Instant::now)), if pong_deadline.is_some() => {
+                warn!("Closing websocket {who}, no pong received");
+                WsStats::inc(&stats.dead_disconnects);
+                break;
             }
             // Process incoming websocket messages
             ws = ws_client_receiver.next() => {
+                pong_deadline = None;
                 match ws {
                     None => break,
                     Some(msg) => {
                         debug!("Received message: {:?}", msg);
                         if let Ok(Message::Text(text)) = msg {
                             let m = p(&text);
+                            if let Err(limited) = m.as_ref().map_or(Ok(()), |m| {
+                                m.rate_limit(&ratelimits, user.as_deref(), who.ip)
+                            }) {
+                                let error = json!({
+                                    "type": "error",
+                                    "status": 429,
+                                    "retry_after": limited.retry_after(),
+                                });
+                                subscription_updates.push(None, Arc::new(error.to_string()));
+                                continue;
+                            }
                             match m {
                                 Ok(m) => match m {
-                                    WSIncomingMessage::Subscribe { topic } => {
+                                    WSIncomingMessage::Subscribe { topic, options } => {
                                         let (tx_subscribe, rx_subscribe) =
                                             oneshot::channel::<watch::Receiver<Arc<String>>>();
                                         let (tx_quit, mut rx_quit) = oneshot::channel::<()>();
 
-                                        let tsubscription_updates_tx = subscription_updates_tx.clone();
+                                        let tsubscription_updates = subscription_updates.clone();
                                         let tmqtt = mqtt.clone();
                                         let ttopic = topic.clone();
+                                        topics.push(topic.clone());
                                         let subscribe_task = tokio::spawn(async move {
                                             debug!(topic = ttopic, "Watcher task started");
                                             match rx_subscribe.await {
                                                 Ok(mut w) => {
                                                     debug!("Received watch channel");
+                                                    let mut filter = SubscriptionFilter::new(options);
+                                                    let mut pending: Option<(Arc<String>, String)> = None;
                                                     loop {
+                                                        let wait = filter.next_allowed().filter(|_| pending.is_some());
                                                         tokio::select! {
                                                             _ = &mut rx_quit => {
                                                                 debug!("Subscribe watcher task received stop signal");
@@ -104,7 +299,24 @@ async fn handle_socket(mut socket: WebSocket, who: SocketAddr, mqtt: MqttHandle)
                                                             _ = w.changed() => {
                                                                 debug!("Received watch channel change");
                                                                 let v = (*w.borrow_and_update()).clone();
-                                                                let _ = tsubscription_updates_tx.send(Arc::new(v.to_string())).await;
+                                                                let data = update_data(&v);
+                                                                if !filter.passes(&data) {
+                                                                    pending = None;
+                                                                    continue;
+                                                                }
+                                                                if filter.next_allowed().is_some_and(|t| t > Instant::now()) {
+                                                                    pending = Some((v, data));
+                                                                    continue;
+                                                                }
+                                                                pending = None;
+                                                                filter.sent(data);
+                                                                tsubscription_updates.push(Some(&ttopic), v);
+                                                            }
+                                                            _ = tokio::time::sleep_until(wait.unwrap_or_else(Instant::now)), if wait.is_some() => {
+                                                                if let Some((v, data)) = pending.take() {
+                                                                    filter.sent(data);
+                                                                    tsubscription_updates.push(Some(&ttopic), v);
+                                                                }
                                                             }
                                                         }
                                                     }
@@ -125,6 +337,36 @@ async fn handle_socket(mut socket: WebSocket, who: SocketAddr, mqtt: MqttHandle)
 
                                         tasks.push((tx_quit, subscribe_task));
                                     }
+                                    WSIncomingMessage::Ping => {
+                                        let pong = json!({ "type": "pong", "ts": now_millis() });
+                                        subscription_updates.push(None, Arc::new(pong.to_string()));
+                                    }
+                                    WSIncomingMessage::Timers if timer_events => {
+                                        debug!("{who} already receives timer events");
+                                    }
+                                    WSIncomingMessage::Timers => {
+                                        timer_events = true;
+                                        let (tx_quit, mut rx_quit) = oneshot::channel::<()>();
+                                        let mut events = timers.subscribe();
+                                        let tsubscription_updates = subscription_updates.clone();
+                                        let timer_task = tokio::spawn(async move {
+                                            debug!("Timer event task started");
+                                            loop {
+                                                tokio::select! {
+                                                    _ = &mut rx_quit => break,
+                                                    event = events.recv() => match event {
+                                                        Ok(event) => {
+                                                            tsubscription_updates.push(None, event);
+                                                        }
+                                                        Err(RecvError::Lagged(_)) => continue,
+                                                        Err(RecvError::Closed) => break,
+                                                    }
+                                                }
+                                            }
+                                            debug!("Timer event task stopped");
+                                        });
+                                        tasks.push((tx_quit, timer_task));
+                                    }
                                 },
                                 Err(e) => error!("Invalid message {:?}", e),
                             }
@@ -142,6 +384,9 @@ async fn handle_socket(mut socket: WebSocket, who: SocketAddr, mqtt: MqttHandle)
         }
     }
 
+    // watchers without other listeners are dropped in the actor
+    mqtt.send(ActorMessage::Release { topics }).await;
+    stats.connections.fetch_sub(1, Ordering::Relaxed);
     // returning from the handler closes the websocket connection
     debug!("Websocket context {who} destroyed");
 }
@@ -155,15 +400,45 @@ fn p(text: &str) -> Result<WSIncomingMessage> {
         .ok_or_eyre("Missing command")?
         .as_str()
         .ok_or_eyre("Command must be a string")?;
-    let mb_topic = obj
-        .get("topic")
-        .ok_or_eyre("Missing topic")?
-        .as_str()
-        .ok_or_eyre("Topic must be a string")?;
     match mb_command {
-        "sub" => Ok(WSIncomingMessage::Subscribe {
-            topic: mb_topic.to_string(),
-        }),
+        "sub" => {
+            let mb_topic = obj
+                .get("topic")
+                .ok_or_eyre("Missing topic")?
+                .as_str()
+                .ok_or_eyre("Topic must be a string")?;
+            Ok(WSIncomingMessage::Subscribe {
+                topic: mb_topic.to_string(),
+                options: subscription_options(obj)?,
+            })
+        }
+        "timers" => Ok(WSIncomingMessage::Timers),
+        "ping" => Ok(WSIncomingMessage::Ping),
         _ => Err(eyre!("Unknown command: {mb_command}")),
     }
 }
+
+fn subscription_options(
+    obj: &serde_json::Map<String, serde_json::Value>,
+) -> Result<SubscriptionOptions> {
+    let min_interval = match obj.get("min_interval_ms") {
+        None => None,
+        Some(v) => Some(Duration::from_millis(
+            v.as_u64()
+                .ok_or_eyre("min_interval_ms must be a positive integer")?,
+        )),
+    };
+    let deadband = match obj.get("deadband") {
+        None => None,
+        Some(v) => Some(v.as_f64().ok_or_eyre("deadband must be a number")?),
+    };
+    let only_on_change = match obj.get("only_on_change") {
+        None => false,
+        Some(v) => v.as_bool().ok_or_eyre("only_on_change must be a boolean")?,
+    };
+    Ok(SubscriptionOptions {
+        min_interval,
+        deadband,
+        only_on_change,
+    })
+}
@@ -0,0 +1,181 @@
+use axum::{
+    extract::{Path, Query, State},
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    Json,
+};
+use jwt_authorizer::JwtClaims;
+use serde::Deserialize;
+use serde_json::{json, Value};
+use tracing::debug;
+
+use crate::{
+    audit::{outcome, Audit},
+    http::claims::Claims,
+    ratelimit::{RateLimited, RateLimits},
+    zigbee::{BridgeInfo, ZigbeeBridge, ZigbeeDevice, ZigbeeError},
+};
+
+#[derive(Deserialize)]
+pub(crate) struct PermitJoinRequest {
+    /// Seconds to allow joining, `0` closes the network
+    pub time: u32,
+    /// Only allow joining through this router
+    pub device: Option<String>,
+}
+
+#[derive(Deserialize)]
+pub(crate) struct RenameRequest {
+    pub to: String,
+    #[serde(default)]
+    pub homeassistant_rename: bool,
+}
+
+#[derive(Deserialize)]
+pub(crate) struct RemoveQuery {
+    #[serde(default)]
+    pub force: bool,
+}
+
+/// Topic limits of the bridge request topic, rejected requests are not
+/// audited
+fn check_limits(
+    zigbee: &ZigbeeBridge,
+    ratelimits: &RateLimits,
+    path: &str,
+) -> Result<(), RateLimited> {
+    match zigbee.request_topic(path) {
+        Some(topic) => ratelimits.check_topics(&[&topic]),
+        None => Ok(()),
+    }
+}
+
+fn error_response(e: ZigbeeError) -> (StatusCode, String) {
+    match e {
+        ZigbeeError::Disabled => (
+            StatusCode::NOT_FOUND,
+            "Zigbee2MQTT integration disabled".to_string(),
+        ),
+        ZigbeeError::Publish => (StatusCode::BAD_GATEWAY, "Publish failed".to_string()),
+        ZigbeeError::Timeout => (
+            StatusCode::GATEWAY_TIMEOUT,
+            "No response from bridge".to_string(),
+        ),
+        ZigbeeError::Bridge(m) => (StatusCode::BAD_GATEWAY, m),
+    }
+}
+
+pub(crate) async fn zigbee_devices_handler(
+    JwtClaims(user): JwtClaims<Claims>,
+    State(zigbee): State<ZigbeeBridge>,
+) -> Result<Json<Vec<ZigbeeDevice>>, (StatusCode, String)> {
+    debug!("Zigbee devices request for user: {:?}", user);
+    if !zigbee.enabled() {
+        return Err(error_response(ZigbeeError::Disabled));
+    }
+    Ok(Json(zigbee.devices().await))
+}
+
+pub(crate) async fn zigbee_bridge_handler(
+    JwtClaims(user): JwtClaims<Claims>,
+    State(zigbee): State<ZigbeeBridge>,
+) -> Result<Json<BridgeInfo>, (StatusCode, String)> {
+    debug!("Zigbee bridge request for user: {:?}", user);
+    if !zigbee.enabled() {
+        return Err(error_response(ZigbeeError::Disabled));
+    }
+    Ok(Json(zigbee.bridge().await))
+}
+
+pub(crate) async fn zigbee_permit_join_handler(
+    JwtClaims(user): JwtClaims<Claims>,
+    audit: Audit,
+    State(zigbee): State<ZigbeeBridge>,
+    State(ratelimits): State<RateLimits>,
+    Json(request): Json<PermitJoinRequest>,
+) -> Result<Json<Value>, Response> {
+    debug!("Zigbee permit join request for user: {:?}", user);
+    let mut body = json!({ "value": request.time > 0, "time": request.time });
+    if let Some(device) = request.device {
+        body["device"] = Value::from(device);
+    }
+    let entry = audit.entry("zigbee.permit_join").payload(body.to_string());
+    check_limits(&zigbee, &ratelimits, "permit_join").map_err(IntoResponse::into_response)?;
+    entry
+        .record(
+            async move {
+                zigbee
+                    .request("permit_join", body)
+                    .await
+                    .map(Json)
+                    .map_err(error_response)
+            },
+            outcome,
+        )
+        .await
+        .map_err(IntoResponse::into_response)
+}
+
+pub(crate) async fn zigbee_rename_handler(
+    JwtClaims(user): JwtClaims<Claims>,
+    audit: Audit,
+    State(zigbee): State<ZigbeeBridge>,
+    State(ratelimits): State<RateLimits>,
+    Path(id): Path<String>,
+    Json(request): Json<RenameRequest>,
+) -> Result<Json<Value>, Response> {
+    debug!("Zigbee rename request for user: {:?}", user);
+    let body = json!({
+        "from": id,
+        "to": request.to,
+        "homeassistant_rename": request.homeassistant_rename,
+    });
+    let entry = audit
+        .entry("zigbee.rename")
+        .target(&id)
+        .payload(body.to_string());
+    check_limits(&zigbee, &ratelimits, "device/rename").map_err(IntoResponse::into_response)?;
+    entry
+        .record(
+            async move {
+                zigbee
+                    .request("device/rename", body)
+                    .await
+                    .map(Json)
+                    .map_err(error_response)
+            },
+            outcome,
+        )
+        .await
+        .map_err(IntoResponse::into_response)
+}
+
+pub(crate) async fn zigbee_remove_handler(
+    JwtClaims(user): JwtClaims<Claims>,
+    audit: Audit,
+    State(zigbee): State<ZigbeeBridge>,
+    State(ratelimits): State<RateLimits>,
+    Path(id): Path<String>,
+    Query(query): Query<RemoveQuery>,
+) -> Result<Json<Value>, Response> {
+    debug!("Zigbee remove request for user: {:?}", user);
+    let body = json!({ "id": id, "force": query.force });
+    let entry = audit
+        .entry("zigbee.remove")
+        .target(&id)
+        .payload(body.to_string());
+    check_limits(&zigbee, &ratelimits, "device/remove").map_err(IntoResponse::into_response)?;
+    entry
+        .record(
+            async move {
+                zigbee
+                    .request("device/remove", body)
+                    .await
+                    .map(Json)
+                    .map_err(error_response)
+            },
+            outcome,
+        )
+        .await
+        .map_err(IntoResponse::into_response)
+}
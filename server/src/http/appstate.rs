@@ -1,9 +1,30 @@
 use axum::extract::FromRef;
 use typed_builder::TypedBuilder;
 
-use crate::mqtta::MqttHandle;
+use super::websocket::WebSockets;
+use crate::{
+    adapters::AdapterRegistry, audit::AuditLog, devices::DeviceRegistry, hass::HassRegistry,
+    history::HistoryStore, homie::HomieRegistry, metrics::Metrics, mqtta::MqttHandle,
+    oidc::OidcStatus, ratelimit::RateLimits, rules::RuleEngine, scenes::Scenes,
+    scheduler::Scheduler, timers::Timers, zigbee::ZigbeeBridge,
+};
 
 #[derive(Clone, FromRef, TypedBuilder)]
 pub(crate) struct AppState {
     mqtt: MqttHandle,
+    history: HistoryStore,
+    devices: DeviceRegistry,
+    hass: HassRegistry,
+    zigbee: ZigbeeBridge,
+    adapters: AdapterRegistry,
+    homie: HomieRegistry,
+    scenes: Scenes,
+    scheduler: Scheduler,
+    rules: RuleEngine,
+    timers: Timers,
+    websockets: WebSockets,
+    metrics: Metrics,
+    oidc: OidcStatus,
+    ratelimits: RateLimits,
+    audit: AuditLog,
 }
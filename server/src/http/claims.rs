@@ -0,0 +1,22 @@
+use jwt_authorizer::RegisteredClaims;
+use serde::Deserialize;
+
+/// Claims of an access token, the registered ones and the name of the user
+#[derive(Clone, Debug, Deserialize)]
+pub(crate) struct Claims {
+    #[serde(flatten)]
+    pub(crate) registered: RegisteredClaims,
+    pub(crate) name: Option<String>,
+    pub(crate) preferred_username: Option<String>,
+}
+
+impl Claims {
+    pub(crate) fn sub(&self) -> Option<&str> {
+        self.registered.sub.as_deref()
+    }
+
+    /// `name` or `preferred_username`, whichever the issuer sets
+    pub(crate) fn display_name(&self) -> Option<&str> {
+        self.name.as_deref().or(self.preferred_username.as_deref())
+    }
+}
@@ -0,0 +1,469 @@
+use std::{
+    convert::Infallible,
+    env, fmt,
+    net::{IpAddr, Ipv4Addr, SocketAddr},
+    sync::Arc,
+};
+
+use axum::{
+    async_trait,
+    extract::{connect_info::ConnectInfo, FromRequestParts},
+    http::{request::Parts, HeaderMap},
+};
+use color_eyre::eyre::{eyre, Context, Result};
+use tracing::info;
+
+/// Address range in CIDR notation, a plain address is a range of one
+#[derive(Clone, Copy, Debug)]
+struct IpRange {
+    addr: IpAddr,
+    prefix: u8,
+}
+
+impl IpRange {
+    fn parse(value: &str) -> Result<Self> {
+        let (addr, prefix) = value.split_once('/').unwrap_or((value, ""));
+        let parsed = addr
+            .parse::<IpAddr>()
+            .wrap_err_with(|| format!("Invalid address {value} in HCS_TRUSTED_PROXIES"))?;
+        let max = if parsed.is_ipv4() { 32 } else { 128 };
+        let prefix = if prefix.is_empty() {
+            max
+        } else {
+            prefix
+                .parse::<u8>()
+                .ok()
+                .filter(|p| *p <= max)
+                .ok_or_else(|| eyre!("Invalid prefix in {value} in HCS_TRUSTED_PROXIES"))?
+        };
+        let addr = parsed.to_canonical();
+        // an IPv4-mapped range is matched as the IPv4 range it contains
+        let prefix = if addr.is_ipv4() && parsed.is_ipv6() {
+            prefix.checked_sub(96).ok_or_else(|| {
+                eyre!("Prefix of {value} in HCS_TRUSTED_PROXIES must be at least 96")
+            })?
+        } else {
+            prefix
+        };
+        Ok(Self { addr, prefix })
+    }
+
+    fn contains(&self, ip: IpAddr) -> bool {
+        match (self.addr, ip.to_canonical()) {
+            (IpAddr::V4(net), IpAddr::V4(ip)) => {
+                let mask = u32::MAX
+                    .checked_shl(32 - u32::from(self.prefix))
+                    .unwrap_or(0);
+                u32::from(net) & mask == u32::from(ip) & mask
+            }
+            (IpAddr::V6(net), IpAddr::V6(ip)) => {
+                let mask = u128::MAX
+                    .checked_shl(128 - u32::from(self.prefix))
+                    .unwrap_or(0);
+                u128::from(net) & mask == u128::from(ip) & mask
+            }
+            _ => false,
+        }
+    }
+}
+
+/// Forwarding header written by the trusted proxies
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+enum ProxyHeader {
+    /// `Forwarded` as in RFC 7239
+    Forwarded,
+    /// `X-Forwarded-For` and `X-Forwarded-Proto`
+    #[default]
+    XForwardedFor,
+}
+
+impl ProxyHeader {
+    fn from_env() -> Result<Self> {
+        match env::var("HCS_TRUSTED_PROXY_HEADER")
+            .unwrap_or_default()
+            .to_ascii_lowercase()
+            .as_str()
+        {
+            "" | "x-forwarded-for" => Ok(Self::XForwardedFor),
+            "forwarded" => Ok(Self::Forwarded),
+            other => Err(eyre!(
+                "Invalid HCS_TRUSTED_PROXY_HEADER {other}, use forwarded or x-forwarded-for"
+            )),
+        }
+    }
+}
+
+/// Reverse proxies from `HCS_TRUSTED_PROXIES` whose forwarding headers are
+/// believed
+#[derive(Clone, Default)]
+pub(crate) struct TrustedProxies {
+    ranges: Arc<Vec<IpRange>>,
+    header: ProxyHeader,
+}
+
+/// One hop of a forwarding header, the client address and the scheme it used
+struct Hop {
+    ip: Option<IpAddr>,
+    scheme: Option<String>,
+}
+
+/// Node of a `Forwarded` or `X-Forwarded-For` header, e.g. `192.0.2.43`,
+/// `"[2001:db8::17]:4711"` or `unknown`
+fn parse_node(node: &str) -> Option<IpAddr> {
+    let node = node.trim().trim_matches('"');
+    if let Some(v6) = node.strip_prefix('[') {
+        return v6.split(']').next()?.parse().ok();
+    }
+    node.parse::<IpAddr>()
+        .ok()
+        .or_else(|| node.parse::<SocketAddr>().ok().map(|a| a.ip()))
+}
+
+fn header_values<'a>(headers: &'a HeaderMap, name: &str) -> impl Iterator<Item = &'a str> {
+    headers
+        .get_all(name)
+        .iter()
+        .filter_map(|v| v.to_str().ok())
+        .flat_map(|v| v.split(','))
+        .map(str::trim)
+}
+
+/// Hops of the configured forwarding header. The other one is ignored, a
+/// proxy passes on what the client sent in a header it does not write.
+fn forwarded_hops(headers: &HeaderMap, header: ProxyHeader) -> Vec<Hop> {
+    if header == ProxyHeader::Forwarded {
+        return header_values(headers, "forwarded")
+            .map(|element| {
+                let mut hop = Hop {
+                    ip: None,
+                    scheme: None,
+                };
+                for pair in element.split(';') {
+                    if let Some((key, value)) = pair.split_once('=') {
+                        match key.trim().to_ascii_lowercase().as_str() {
+                            "for" => hop.ip = parse_node(value),
+                            "proto" => {
+                                hop.scheme = Some(value.trim().trim_matches('"').to_string())
+                            }
+                            _ => {}
+                        }
+                    }
+                }
+                hop
+            })
+            .collect();
+    }
+    let schemes: Vec<&str> = header_values(headers, "x-forwarded-proto").collect();
+    let ips: Vec<&str> = header_values(headers, "x-forwarded-for").collect();
+    let count = ips.len();
+    ips.into_iter()
+        .enumerate()
+        .map(|(index, ip)| Hop {
+            ip: parse_node(ip),
+            // one scheme per hop or a single one set by the nearest proxy
+            scheme: if schemes.len() == count {
+                schemes.get(index)
+            } else {
+                schemes.last()
+            }
+            .map(|s| s.to_string()),
+        })
+        .collect()
+}
+
+impl TrustedProxies {
+    /// Read `HCS_TRUSTED_PROXIES`, comma separated addresses or CIDR ranges,
+    /// and `HCS_TRUSTED_PROXY_HEADER`. Not set trusts no proxy and ignores
+    /// forwarding headers.
+    pub(crate) fn from_env() -> Result<Self> {
+        let ranges = env::var("HCS_TRUSTED_PROXIES")
+            .unwrap_or_default()
+            .split(',')
+            .map(str::trim)
+            .filter(|r| !r.is_empty())
+            .map(IpRange::parse)
+            .collect::<Result<Vec<_>>>()?;
+        let header = ProxyHeader::from_env()?;
+        if !ranges.is_empty() {
+            info!(
+                ranges = ranges.len(),
+                ?header,
+                "Trusting forwarding headers of proxies"
+            );
+        }
+        Ok(Self {
+            ranges: Arc::new(ranges),
+            header,
+        })
+    }
+
+    fn trusted(&self, ip: IpAddr) -> bool {
+        self.ranges.iter().any(|r| r.contains(ip))
+    }
+
+    /// Client of a request from `peer`. Walks the forwarding headers from the
+    /// nearest hop and stops at the first address that is not a trusted
+    /// proxy, so clients cannot spoof their address by sending the headers
+    /// themselves.
+    pub(crate) fn client(&self, peer: SocketAddr, tls: bool, headers: &HeaderMap) -> ClientInfo {
+        let mut client = ClientInfo {
+            ip: peer.ip().to_canonical(),
+            scheme: if tls { "https" } else { "http" }.to_string(),
+            peer,
+        };
+        if !self.trusted(client.ip) {
+            return client;
+        }
+        for hop in forwarded_hops(headers, self.header).into_iter().rev() {
+            let Some(ip) = hop.ip else {
+                break;
+            };
+            client.ip = ip.to_canonical();
+            if let Some(scheme) = hop.scheme.filter(|s| s == "http" || s == "https") {
+                client.scheme = scheme;
+            }
+            if !self.trusted(client.ip) {
+                break;
+            }
+        }
+        client
+    }
+}
+
+/// Client of a request as seen through trusted proxies
+#[derive(Clone, Debug)]
+pub(crate) struct ClientInfo {
+    /// Address of the client
+    pub(crate) ip: IpAddr,
+    /// Scheme the client used, `http` or `https`
+    pub(crate) scheme: String,
+    /// Address of the connected peer, the nearest proxy for forwarded
+    /// requests
+    pub(crate) peer: SocketAddr,
+}
+
+impl fmt::Display for ClientInfo {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.ip == self.peer.ip().to_canonical() {
+            write!(f, "{}", self.peer)
+        } else {
+            write!(f, "{} via {}", self.ip, self.peer)
+        }
+    }
+}
+
+#[async_trait]
+impl<S: Send + Sync> FromRequestParts<S> for ClientInfo {
+    type Rejection = Infallible;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        if let Some(client) = parts.extensions.get::<ClientInfo>() {
+            return Ok(client.clone());
+        }
+        // served without the connection info, e.g. in a nested service
+        let peer = parts.extensions.get::<ConnectInfo<SocketAddr>>().map_or(
+            SocketAddr::from((Ipv4Addr::UNSPECIFIED, 0)),
+            |ConnectInfo(peer)| *peer,
+        );
+        Ok(TrustedProxies::default().client(peer, false, &parts.headers))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::http::HeaderValue;
+
+    use super::*;
+
+    fn range(value: &str) -> IpRange {
+        IpRange::parse(value).unwrap()
+    }
+
+    fn ip(value: &str) -> IpAddr {
+        value.parse().unwrap()
+    }
+
+    fn proxies(ranges: &[&str], header: ProxyHeader) -> TrustedProxies {
+        TrustedProxies {
+            ranges: Arc::new(ranges.iter().map(|r| range(r)).collect()),
+            header,
+        }
+    }
+
+    fn headers(pairs: &[(&'static str, &str)]) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        for (name, value) in pairs {
+            headers.append(*name, HeaderValue::from_str(value).unwrap());
+        }
+        headers
+    }
+
+    fn peer(value: &str) -> SocketAddr {
+        SocketAddr::new(ip(value), 40000)
+    }
+
+    #[test]
+    fn range_prefix_zero_matches_everything_of_its_family() {
+        assert!(range("0.0.0.0/0").contains(ip("203.0.113.5")));
+        assert!(range("0.0.0.0/0").contains(ip("255.255.255.255")));
+        assert!(!range("0.0.0.0/0").contains(ip("2001:db8::1")));
+        assert!(range("::/0").contains(ip("2001:db8::1")));
+    }
+
+    #[test]
+    fn range_full_prefix_matches_one_address() {
+        assert!(range("10.0.0.1/32").contains(ip("10.0.0.1")));
+        assert!(!range("10.0.0.1/32").contains(ip("10.0.0.2")));
+        assert!(range("2001:db8::1/128").contains(ip("2001:db8::1")));
+        assert!(!range("2001:db8::1/128").contains(ip("2001:db8::2")));
+        assert!(range("10.0.0.1").contains(ip("10.0.0.1")));
+        assert!(!range("10.0.0.1").contains(ip("10.0.0.2")));
+    }
+
+    #[test]
+    fn range_prefix_boundary() {
+        let r = range("172.16.0.0/12");
+        assert!(r.contains(ip("172.16.0.0")));
+        assert!(r.contains(ip("172.31.255.255")));
+        assert!(!r.contains(ip("172.32.0.0")));
+        assert!(!r.contains(ip("172.15.255.255")));
+    }
+
+    #[test]
+    fn range_ipv4_mapped_ipv6() {
+        assert!(range("10.0.0.0/8").contains(ip("::ffff:10.1.2.3")));
+        assert!(range("::ffff:10.0.0.0/104").contains(ip("10.1.2.3")));
+        assert!(!range("::ffff:10.0.0.0/104").contains(ip("11.0.0.1")));
+        assert!(range("::ffff:127.0.0.1").contains(ip("127.0.0.1")));
+        assert!(IpRange::parse("::ffff:10.0.0.0/64").is_err());
+    }
+
+    #[test]
+    fn range_invalid() {
+        assert!(IpRange::parse("10.0.0.0/33").is_err());
+        assert!(IpRange::parse("2001:db8::/129").is_err());
+        assert!(IpRange::parse("proxy.local").is_err());
+    }
+
+    #[test]
+    fn untrusted_peer_headers_ignored() {
+        let proxies = proxies(&["10.0.0.0/8"], ProxyHeader::XForwardedFor);
+        let client = proxies.client(
+            peer("203.0.113.5"),
+            false,
+            &headers(&[("x-forwarded-for", "192.0.2.1")]),
+        );
+        assert_eq!(client.ip, ip("203.0.113.5"));
+    }
+
+    #[test]
+    fn spoofed_hop_before_real_client_ignored() {
+        let proxies = proxies(&["10.0.0.1"], ProxyHeader::XForwardedFor);
+        // the client sent 192.0.2.1 itself, the proxy appended its address
+        let client = proxies.client(
+            peer("10.0.0.1"),
+            false,
+            &headers(&[("x-forwarded-for", "192.0.2.1, 203.0.113.5")]),
+        );
+        assert_eq!(client.ip, ip("203.0.113.5"));
+    }
+
+    #[test]
+    fn header_not_configured_is_ignored() {
+        let proxies = proxies(&["10.0.0.1"], ProxyHeader::XForwardedFor);
+        let client = proxies.client(
+            peer("10.0.0.1"),
+            false,
+            &headers(&[
+                ("forwarded", "for=192.0.2.1"),
+                ("x-forwarded-for", "203.0.113.5"),
+            ]),
+        );
+        assert_eq!(client.ip, ip("203.0.113.5"));
+
+        let proxies = self::proxies(&["10.0.0.1"], ProxyHeader::Forwarded);
+        let client = proxies.client(
+            peer("10.0.0.1"),
+            false,
+            &headers(&[
+                ("forwarded", "for=203.0.113.5"),
+                ("x-forwarded-for", "192.0.2.1"),
+            ]),
+        );
+        assert_eq!(client.ip, ip("203.0.113.5"));
+    }
+
+    #[test]
+    fn multiple_trusted_hops() {
+        let proxies = proxies(&["10.0.0.0/8"], ProxyHeader::XForwardedFor);
+        let client = proxies.client(
+            peer("10.0.0.1"),
+            false,
+            &headers(&[
+                ("x-forwarded-for", "203.0.113.5, 10.0.0.2"),
+                ("x-forwarded-proto", "https"),
+            ]),
+        );
+        assert_eq!(client.ip, ip("203.0.113.5"));
+        assert_eq!(client.scheme, "https");
+        assert_eq!(client.to_string(), "203.0.113.5 via 10.0.0.1:40000");
+    }
+
+    #[test]
+    fn unknown_node_stops_at_last_proxy() {
+        let proxies = proxies(&["10.0.0.0/8"], ProxyHeader::Forwarded);
+        let client = proxies.client(
+            peer("10.0.0.1"),
+            false,
+            &headers(&[("forwarded", "for=unknown, for=10.0.0.2")]),
+        );
+        assert_eq!(client.ip, ip("10.0.0.2"));
+    }
+
+    #[test]
+    fn ipv6_nodes() {
+        let proxies = proxies(&["::1"], ProxyHeader::Forwarded);
+        let client = proxies.client(
+            peer("::1"),
+            true,
+            &headers(&[("forwarded", r#"for="[2001:db8::17]:4711";proto=http"#)]),
+        );
+        assert_eq!(client.ip, ip("2001:db8::17"));
+        assert_eq!(client.scheme, "http");
+
+        let proxies = self::proxies(&["::1"], ProxyHeader::XForwardedFor);
+        let client = proxies.client(
+            peer("::1"),
+            false,
+            &headers(&[("x-forwarded-for", "2001:db8::17")]),
+        );
+        assert_eq!(client.ip, ip("2001:db8::17"));
+    }
+
+    #[test]
+    fn invalid_scheme_ignored() {
+        let proxies = proxies(&["10.0.0.1"], ProxyHeader::XForwardedFor);
+        let client = proxies.client(
+            peer("10.0.0.1"),
+            true,
+            &headers(&[
+                ("x-forwarded-for", "203.0.113.5"),
+                ("x-forwarded-proto", "javascript"),
+            ]),
+        );
+        assert_eq!(client.scheme, "https");
+    }
+
+    #[test]
+    fn parse_nodes() {
+        assert_eq!(parse_node("192.0.2.43"), Some(ip("192.0.2.43")));
+        assert_eq!(parse_node("192.0.2.43:8080"), Some(ip("192.0.2.43")));
+        assert_eq!(
+            parse_node(r#""[2001:db8:cafe::17]:4711""#),
+            Some(ip("2001:db8:cafe::17"))
+        );
+        assert_eq!(parse_node("[2001:db8::1]"), Some(ip("2001:db8::1")));
+        assert_eq!(parse_node("unknown"), None);
+        assert_eq!(parse_node("_hidden"), None);
+    }
+}
@@ -0,0 +1,50 @@
+use std::{env, time::Duration};
+
+use axum::http::{header, HeaderName, HeaderValue, Method};
+use color_eyre::eyre::{eyre, Context, Result};
+use tower_http::cors::{AllowOrigin, CorsLayer};
+use tracing::info;
+
+/// CORS for the origins in `HCS_CORS_ORIGINS`. Without origins no CORS
+/// headers are sent and browsers only allow same origin requests.
+pub(crate) fn cors_layer_from_env() -> Result<Option<CorsLayer>> {
+    let origins = env::var("HCS_CORS_ORIGINS")
+        .unwrap_or_default()
+        .split(',')
+        .map(str::trim)
+        .filter(|o| !o.is_empty())
+        .map(|o| {
+            if !(o.starts_with("http://") || o.starts_with("https://")) {
+                return Err(eyre!("Invalid origin {o} in HCS_CORS_ORIGINS"));
+            }
+            HeaderValue::from_str(o.trim_end_matches('/'))
+                .wrap_err_with(|| format!("Invalid origin {o} in HCS_CORS_ORIGINS"))
+        })
+        .collect::<Result<Vec<_>>>()?;
+    if origins.is_empty() {
+        return Ok(None);
+    }
+    let max_age = env::var("HCS_CORS_MAX_AGE")
+        .unwrap_or_else(|_| "600".to_string())
+        .parse::<u64>()
+        .context("Cannot parse HCS_CORS_MAX_AGE")?;
+    info!(origins = origins.len(), "CORS enabled");
+    Ok(Some(
+        CorsLayer::new()
+            .allow_origin(AllowOrigin::list(origins))
+            .allow_credentials(true)
+            .allow_methods([
+                Method::GET,
+                Method::POST,
+                Method::PUT,
+                Method::DELETE,
+                Method::OPTIONS,
+            ])
+            .allow_headers([
+                header::AUTHORIZATION,
+                header::CONTENT_TYPE,
+                HeaderName::from_static("last-event-id"),
+            ])
+            .max_age(Duration::from_secs(max_age)),
+    ))
+}
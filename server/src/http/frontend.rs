@@ -0,0 +1,196 @@
+use std::{env, path::PathBuf};
+
+use axum::{
+    extract::{Request, State},
+    handler::Handler,
+    http::{header, HeaderValue, StatusCode},
+    middleware::{self, Next},
+    response::{IntoResponse, Response},
+    routing::get,
+    Json, Router,
+};
+use color_eyre::eyre::{eyre, Result};
+use serde::Serialize;
+use tower::ServiceExt;
+use tower_http::services::{ServeDir, ServeFile};
+use tracing::info;
+
+/// Assets with a content hash in the name never change
+const IMMUTABLE: &str = "public, max-age=31536000, immutable";
+const SHORT: &str = "public, max-age=3600";
+/// Always revalidate the entry point, it references the current assets
+const REVALIDATE: &str = "no-cache";
+
+/// Settings handed to the frontend at runtime
+#[derive(Clone, Serialize)]
+struct FrontendConfig {
+    issuer: String,
+    client_id: Option<String>,
+}
+
+async fn config_handler(State(config): State<FrontendConfig>) -> Json<FrontendConfig> {
+    Json(config)
+}
+
+/// Whether a file name carries a build hash, e.g. `main.3f2a1b4c5d6e7f80.js`
+/// or `chunk-5YHQPZ2C.js`
+fn is_fingerprinted(path: &str) -> bool {
+    let name = path.rsplit('/').next().unwrap_or(path);
+    name.split(['.', '-']).skip(1).any(|part| {
+        part.len() >= 8
+            && part.chars().all(|c| c.is_ascii_alphanumeric())
+            && part.chars().any(|c| c.is_ascii_digit())
+    })
+}
+
+/// Whether the last segment of a path has a file extension, e.g. `main.js`
+fn has_extension(path: &str) -> bool {
+    path.rsplit('/')
+        .next()
+        .is_some_and(|name| name.rfind('.').is_some_and(|i| i > 0 && i + 1 < name.len()))
+}
+
+/// `index.html` for the client side routes, missing files stay missing so
+/// a stale asset is never cached as the entry point
+async fn spa_fallback(State(index): State<PathBuf>, request: Request) -> Response {
+    if has_extension(request.uri().path()) {
+        return StatusCode::NOT_FOUND.into_response();
+    }
+    ServeFile::new(index).oneshot(request).await.into_response()
+}
+
+async fn cache_headers(request: Request, next: Next) -> Response {
+    let path = request.uri().path().to_string();
+    let mut response = next.run(request).await;
+    if !response.status().is_success() || response.headers().contains_key(header::CACHE_CONTROL) {
+        return response;
+    }
+    let html = response
+        .headers()
+        .get(header::CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .is_some_and(|v| v.starts_with("text/html"));
+    let value = if html || path.ends_with("/config.json") {
+        REVALIDATE
+    } else if is_fingerprinted(&path) {
+        IMMUTABLE
+    } else {
+        SHORT
+    };
+    response
+        .headers_mut()
+        .insert(header::CACHE_CONTROL, HeaderValue::from_static(value));
+    response
+}
+
+/// Frontend from the directory in `HCS_STATIC_DIR`, `None` if not set.
+/// Unknown paths without a file extension fall back to `index.html` so the
+/// client side router can handle them.
+pub(crate) fn frontend_routes() -> Result<Option<Router>> {
+    let Some(dir) = env::var("HCS_STATIC_DIR").ok().filter(|d| !d.is_empty()) else {
+        return Ok(None);
+    };
+    let dir = PathBuf::from(dir);
+    let index = dir.join("index.html");
+    if !index.is_file() {
+        return Err(eyre!("No index.html in HCS_STATIC_DIR {}", dir.display()));
+    }
+    let config = FrontendConfig {
+        issuer: env::var("HCS_JWT_ISSUER").unwrap_or_default(),
+        client_id: env::var("HCS_OIDC_CLIENT_ID")
+            .ok()
+            .filter(|c| !c.is_empty()),
+    };
+    info!("Serving frontend from {}", dir.display());
+    let files = ServeDir::new(&dir)
+        .precompressed_br()
+        .precompressed_gzip()
+        .fallback(spa_fallback.with_state(index));
+    Ok(Some(
+        Router::new()
+            .route("/config.json", get(config_handler))
+            .with_state(config)
+            .fallback_service(files)
+            .layer(middleware::from_fn(cache_headers)),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::body::Body;
+
+    use super::*;
+
+    async fn status(app: &Router, path: &str) -> (StatusCode, Option<HeaderValue>) {
+        let response = app
+            .clone()
+            .oneshot(Request::get(path).body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        let cache = response.headers().get(header::CACHE_CONTROL).cloned();
+        (response.status(), cache)
+    }
+
+    #[tokio::test]
+    async fn fallback_only_for_routes() {
+        let dir = env::temp_dir().join(format!("hcs-frontend-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("index.html"), "<html></html>").unwrap();
+        std::fs::write(dir.join("main.3f2a1b4c5d6e7f80.js"), "").unwrap();
+        env::set_var("HCS_STATIC_DIR", &dir);
+        let frontend = frontend_routes().unwrap().unwrap();
+        let api = Router::new()
+            .route("/status", get(|| async { "OK" }))
+            .fallback(|| async { StatusCode::NOT_FOUND });
+        let app = Router::new().nest("/api", api).merge(frontend);
+
+        let revalidate = Some(HeaderValue::from_static(REVALIDATE));
+        let immutable = Some(HeaderValue::from_static(IMMUTABLE));
+        assert_eq!(
+            status(&app, "/").await,
+            (StatusCode::OK, revalidate.clone())
+        );
+        assert_eq!(
+            status(&app, "/devices/lamp").await,
+            (StatusCode::OK, revalidate)
+        );
+        assert_eq!(
+            status(&app, "/main.3f2a1b4c5d6e7f80.js").await,
+            (StatusCode::OK, immutable)
+        );
+        assert_eq!(
+            status(&app, "/main.0000000000000000.js").await.0,
+            StatusCode::NOT_FOUND
+        );
+        assert_eq!(status(&app, "/api/status").await.0, StatusCode::OK);
+        assert_eq!(status(&app, "/api/unknown").await.0, StatusCode::NOT_FOUND);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn fingerprinted_names() {
+        assert!(is_fingerprinted("/main.3f2a1b4c5d6e7f80.js"));
+        assert!(is_fingerprinted("/assets/chunk-5YHQPZ2C.js"));
+        assert!(is_fingerprinted("styles.ABCD1234.css"));
+        assert!(!is_fingerprinted("/index.html"));
+        assert!(!is_fingerprinted("/favicon.ico"));
+        assert!(!is_fingerprinted("/assets/config.json"));
+        // too short, or without a digit
+        assert!(!is_fingerprinted("/app.1234567.js"));
+        assert!(!is_fingerprinted("/polyfills.abcdefgh.js"));
+        // the first part of the name and the directories do not count
+        assert!(!is_fingerprinted("/1a2b3c4d5e.js"));
+        assert!(!is_fingerprinted("/build-1a2b3c4d5e/logo.png"));
+    }
+
+    #[test]
+    fn extensions() {
+        assert!(has_extension("/main.3f2a1b4c5d6e7f80.js"));
+        assert!(has_extension("/assets/logo.png"));
+        assert!(!has_extension("/"));
+        assert!(!has_extension("/devices/living-lamp"));
+        assert!(!has_extension("/v1.2/settings"));
+        assert!(!has_extension("/.well-known"));
+        assert!(!has_extension("/ends-with."));
+    }
+}
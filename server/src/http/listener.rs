@@ -0,0 +1,146 @@
+use std::{
+    env, fmt, io,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+    path::PathBuf,
+};
+
+use color_eyre::eyre::{Context, Result};
+use tokio::net::{TcpListener, TcpStream};
+#[cfg(unix)]
+use tokio::net::{UnixListener, UnixStream};
+use tracing::debug;
+
+/// Peer reported for connections over a Unix domain socket, these come from
+/// a process on the same host
+pub(crate) const UNIX_PEER: SocketAddr = SocketAddr::new(IpAddr::V4(Ipv4Addr::LOCALHOST), 0);
+
+/// Address from `HCS_LISTEN`
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(crate) enum ListenAddr {
+    Tcp(SocketAddr),
+    #[cfg(unix)]
+    Unix(PathBuf),
+}
+
+impl fmt::Display for ListenAddr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Tcp(addr) => write!(f, "{addr}"),
+            #[cfg(unix)]
+            Self::Unix(path) => write!(f, "unix:{}", path.display()),
+        }
+    }
+}
+
+impl ListenAddr {
+    /// Parse `ip:port`, `ip` with `port` or `unix:path`
+    fn parse(value: &str, port: u16) -> Result<Self> {
+        if let Some(path) = value.strip_prefix("unix:") {
+            #[cfg(unix)]
+            return Ok(Self::Unix(PathBuf::from(path)));
+            #[cfg(not(unix))]
+            return Err(color_eyre::eyre::eyre!(
+                "Unix domain socket {path} not supported"
+            ));
+        }
+        if let Ok(addr) = value.parse::<SocketAddr>() {
+            return Ok(Self::Tcp(addr));
+        }
+        let ip = value
+            .trim_start_matches('[')
+            .trim_end_matches(']')
+            .parse::<IpAddr>()
+            .wrap_err_with(|| format!("Invalid listen address {value}"))?;
+        Ok(Self::Tcp(SocketAddr::new(ip, port)))
+    }
+
+    /// Read `HCS_LISTEN`, a comma separated list of addresses. Addresses
+    /// without a port use `PORT`, not set listens on all interfaces.
+    pub(crate) fn from_env() -> Result<Vec<Self>> {
+        let port = env::var("PORT")
+            .unwrap_or_else(|_| "3000".to_string())
+            .parse::<u16>()
+            .context("Cannot parse PORT")?;
+        let mut addrs = env::var("HCS_LISTEN")
+            .unwrap_or_default()
+            .split(',')
+            .map(str::trim)
+            .filter(|a| !a.is_empty())
+            .map(|a| Self::parse(a, port))
+            .collect::<Result<Vec<_>>>()?;
+        if addrs.is_empty() {
+            addrs.push(Self::Tcp(SocketAddr::new(
+                Ipv6Addr::UNSPECIFIED.into(),
+                port,
+            )));
+        }
+        addrs.dedup();
+        Ok(addrs)
+    }
+
+    pub(crate) async fn bind(&self) -> Result<Listener> {
+        match self {
+            Self::Tcp(addr) => TcpListener::bind(addr)
+                .await
+                .map(Listener::Tcp)
+                .wrap_err_with(|| format!("Cannot listen on {addr}")),
+            #[cfg(unix)]
+            Self::Unix(path) => {
+                // a socket left over from a previous run blocks the bind
+                if std::fs::symlink_metadata(path)
+                    .is_ok_and(|m| std::os::unix::fs::FileTypeExt::is_socket(&m.file_type()))
+                {
+                    std::fs::remove_file(path)
+                        .wrap_err_with(|| format!("Cannot remove {}", path.display()))?;
+                }
+                UnixListener::bind(path)
+                    .map(|l| Listener::Unix(l, path.clone()))
+                    .wrap_err_with(|| format!("Cannot listen on {}", path.display()))
+            }
+        }
+    }
+}
+
+/// Bound listener
+pub(crate) enum Listener {
+    Tcp(TcpListener),
+    #[cfg(unix)]
+    Unix(UnixListener, PathBuf),
+}
+
+/// Accepted connection
+pub(crate) enum Stream {
+    Tcp(TcpStream),
+    #[cfg(unix)]
+    Unix(UnixStream),
+}
+
+impl Listener {
+    /// Next connection with the address of the peer, [`UNIX_PEER`] for Unix
+    /// domain sockets
+    pub(crate) async fn accept(&self) -> io::Result<(Stream, SocketAddr)> {
+        match self {
+            Self::Tcp(listener) => {
+                let (stream, peer) = listener.accept().await?;
+                Ok((Stream::Tcp(stream), peer))
+            }
+            #[cfg(unix)]
+            Self::Unix(listener, _) => {
+                let (stream, _) = listener.accept().await?;
+                if let Ok(cred) = stream.peer_cred() {
+                    debug!(uid = cred.uid(), pid = cred.pid(), "Unix socket connection");
+                }
+                Ok((Stream::Unix(stream), UNIX_PEER))
+            }
+        }
+    }
+}
+
+impl Drop for Listener {
+    fn drop(&mut self) {
+        #[cfg(unix)]
+        if let Self::Unix(_, path) = self {
+            let _ = std::fs::remove_file(path);
+        }
+    }
+}
@@ -1,70 +1,289 @@
 mod api;
 pub(crate) mod appstate;
+pub(crate) mod claims;
+pub(crate) mod client;
+mod cors;
+mod frontend;
+mod listener;
+mod serve;
+mod tls;
+pub(crate) mod websocket;
 
 use std::{
-    net::{IpAddr, SocketAddr},
-    str::FromStr,
+    collections::HashSet,
+    net::SocketAddr,
+    sync::{Arc, OnceLock},
     time::Duration,
 };
 
-use api::{status::status_handler, web2mqtt::web2mqtt_handler, ws::ws_handler};
+use api::{
+    adapters::{adapter_command_handler, adapter_handler, adapters_handler},
+    audit::audit_handler,
+    devices::{device_command_handler, device_handler, devices_handler},
+    events::events_handler,
+    hass::{hass_devices_handler, hass_entities_handler, hass_events_handler},
+    health::{healthz_handler, readyz_handler},
+    history::history_handler,
+    homie::{homie_device_handler, homie_devices_handler, homie_set_handler},
+    metrics::metrics_handler,
+    rules::{
+        rule_create_handler, rule_delete_handler, rule_handler, rule_log_handler,
+        rule_update_handler, rules_handler,
+    },
+    scenes::{scene_activate_handler, scenes_handler},
+    schedules::{
+        schedule_create_handler, schedule_delete_handler, schedule_handler,
+        schedule_update_handler, schedules_handler,
+    },
+    status::status_handler,
+    timers::{timer_cancel_handler, timer_create_handler, timer_handler, timers_handler},
+    web2mqtt::web2mqtt_handler,
+    ws::{ws_handler, ws_stats_handler},
+    zigbee::{
+        zigbee_bridge_handler, zigbee_devices_handler, zigbee_permit_join_handler,
+        zigbee_remove_handler, zigbee_rename_handler,
+    },
+};
 use appstate::AppState;
 use axum::{
-    routing::{get, post},
+    extract::{FromRef, Request, State},
+    handler::Handler,
+    http::StatusCode,
+    middleware,
+    response::{IntoResponse, Response},
+    routing::{delete, get, post, put},
     Router,
 };
+use claims::Claims;
+use client::ClientInfo;
 use color_eyre::{eyre::Context, Result};
-use jwt_authorizer::{Authorizer, IntoLayer, JwtAuthorizer, Validation};
-use tokio::signal;
-use tower_http::{timeout::TimeoutLayer, trace::TraceLayer};
-use tracing::debug;
-
-async fn api_routes(state: AppState) -> Result<Router> {
-    let url = std::env::var("HCS_JWT_ISSUER").wrap_err("Missing HCS_JWT_ISSUER variable")?;
-    let validation = Validation::new()
-        .iss(&[url.clone()])
-        .aud(&["homecontrol"])
-        .leeway(5);
-    let auth: Authorizer = JwtAuthorizer::from_oidc(&url)
-        .validation(validation)
-        .build()
-        .await
-        .wrap_err("JWT authorization initialization failed")?;
-    Ok(Router::new()
+use jwt_authorizer::{Authorizer, IntoLayer};
+use tokio::{signal, sync::watch};
+use tower::ServiceExt;
+use tower_http::{
+    timeout::TimeoutLayer,
+    trace::{DefaultOnResponse, OnResponse, TraceLayer},
+};
+use tracing::{debug, Span};
+
+use crate::{
+    metrics::Metrics,
+    oidc::OidcStatus,
+    ratelimit::{limit_requests, RateLimits},
+};
+
+fn api_routes(state: AppState, auth: Arc<Authorizer<Claims>>) -> Router {
+    // user and address limits for the handlers that publish, `/publish`
+    // checks them together with its topic
+    let limit = middleware::from_fn_with_state(RateLimits::from_ref(&state), limit_requests);
+    Router::new()
         .route("/status", get(status_handler))
         .route("/publish", post(web2mqtt_handler))
         .route("/ws", get(ws_handler))
+        .route("/events", get(events_handler))
+        .route("/ws/stats", get(ws_stats_handler))
+        .route("/history", get(history_handler))
+        .route("/audit", get(audit_handler))
+        .route("/devices", get(devices_handler))
+        .route("/devices/:id", get(device_handler))
+        .route(
+            "/devices/:id/:capability",
+            post(device_command_handler.layer(limit.clone())),
+        )
+        .route("/hass/devices", get(hass_devices_handler))
+        .route("/hass/entities", get(hass_entities_handler))
+        .route("/hass/events", get(hass_events_handler))
+        .route("/zigbee/bridge", get(zigbee_bridge_handler))
+        .route("/zigbee/devices", get(zigbee_devices_handler))
+        .route(
+            "/zigbee/devices/:id",
+            delete(zigbee_remove_handler.layer(limit.clone())),
+        )
+        .route(
+            "/zigbee/devices/:id/rename",
+            post(zigbee_rename_handler.layer(limit.clone())),
+        )
+        .route(
+            "/zigbee/permit_join",
+            post(zigbee_permit_join_handler.layer(limit.clone())),
+        )
+        .route("/adapters", get(adapters_handler))
+        .route("/adapters/:id", get(adapter_handler))
+        .route(
+            "/adapters/:id/command",
+            post(adapter_command_handler.layer(limit.clone())),
+        )
+        .route("/homie", get(homie_devices_handler))
+        .route("/homie/:device", get(homie_device_handler))
+        .route(
+            "/homie/:device/:node/:property",
+            put(homie_set_handler.layer(limit.clone())),
+        )
+        .route("/scenes", get(scenes_handler))
+        .route(
+            "/scenes/:id/activate",
+            post(scene_activate_handler.layer(limit.clone())),
+        )
+        .route(
+            "/schedules",
+            get(schedules_handler).post(schedule_create_handler),
+        )
+        .route(
+            "/schedules/:id",
+            get(schedule_handler)
+                .put(schedule_update_handler)
+                .delete(schedule_delete_handler),
+        )
+        .route(
+            "/timers",
+            get(timers_handler).post(timer_create_handler.layer(limit.clone())),
+        )
+        .route(
+            "/timers/:id",
+            get(timer_handler).delete(timer_cancel_handler),
+        )
+        .route("/rules", get(rules_handler).post(rule_create_handler))
+        .route("/rules/log", get(rule_log_handler))
+        .route(
+            "/rules/:id",
+            get(rule_handler)
+                .put(rule_update_handler)
+                .delete(rule_delete_handler),
+        )
+        // unknown API paths must not reach the frontend fallback
+        .fallback(|| async { StatusCode::NOT_FOUND })
         .layer(auth.into_layer())
-        .with_state(state))
+        .with_state(state)
+}
+
+/// The JWT protected API, built on first use once OIDC discovery succeeded
+#[derive(Clone)]
+struct Api {
+    state: AppState,
+    router: Arc<OnceLock<Router>>,
+}
+
+/// Answer `/api` with 503 until the authorizer is available, health checks
+/// and the frontend are served meanwhile
+async fn api_service(State(api): State<Api>, request: Request) -> Response {
+    let Some(auth) = OidcStatus::from_ref(&api.state).authorizer() else {
+        return (
+            StatusCode::SERVICE_UNAVAILABLE,
+            "Authorization not available",
+        )
+            .into_response();
+    };
+    let router = api
+        .router
+        .get_or_init(|| api_routes(api.state.clone(), auth))
+        .clone();
+    router.oneshot(request).await.into_response()
+}
+
+/// Routes outside of the JWT protected API
+fn public_routes(state: AppState) -> Router {
+    let mut router = Router::new()
+        .route("/healthz", get(healthz_handler))
+        .route("/readyz", get(readyz_handler));
+    if Metrics::from_ref(&state).enabled() {
+        router = router.route("/metrics", get(metrics_handler));
+    }
+    router.with_state(state)
 }
 
 pub(crate) async fn http_server(state: AppState) -> Result<()> {
-    let app = Router::new().nest("/api", api_routes(state).await?).layer((
-        TraceLayer::new_for_http(),
-        TimeoutLayer::new(Duration::from_secs(10)),
-    ));
+    let metrics = Metrics::from_ref(&state);
+    let trace = TraceLayer::new_for_http()
+        .make_span_with(|request: &Request| {
+            let (client, scheme) = request
+                .extensions()
+                .get::<ClientInfo>()
+                .map_or_else(Default::default, |c| (c.ip.to_string(), c.scheme.clone()));
+            tracing::debug_span!(
+                "request",
+                method = %request.method(),
+                uri = %request.uri(),
+                version = ?request.version(),
+                client = %client,
+                scheme = %scheme,
+            )
+        })
+        .on_response(move |response: &Response, latency: Duration, span: &Span| {
+            DefaultOnResponse::new().on_response(response, latency, span);
+            metrics.http_response(response.status(), latency);
+        });
+    let api = Api {
+        state: state.clone(),
+        router: Default::default(),
+    };
+    let mut app = Router::new()
+        .merge(public_routes(state))
+        .nest_service("/api", Router::new().fallback(api_service).with_state(api));
+    if let Some(frontend) = frontend::frontend_routes()? {
+        app = app.merge(frontend);
+    }
+    // outside of the JWT layer, preflight requests carry no token
+    if let Some(cors) = cors::cors_layer_from_env()? {
+        app = app.layer(cors);
+    }
+    let app = app.layer((trace, TimeoutLayer::new(Duration::from_secs(10))));
     debug!("Initializing service...");
-    // run it
-    let addr = SocketAddr::new(
-        IpAddr::from_str("::")?,
-        std::env::var("PORT")
-            .unwrap_or_else(|_| "3000".to_string())
-            .parse::<u16>()
-            .context("Cannot parse PORT")?,
-    );
-
-    tracing::info!("listening on http://{}", addr);
-    let listener = tokio::net::TcpListener::bind(&addr)
-        .await
-        .context("Cannot start server")?;
-
-    axum::serve(
-        listener,
-        app.into_make_service_with_connect_info::<SocketAddr>(),
-    )
-    .with_graceful_shutdown(shutdown_signal())
-    .await
-    .context("error running server")?;
+    let addrs = listener::ListenAddr::from_env()?;
+    let tls = tls::Tls::from_env()?;
+    let proxies = client::TrustedProxies::from_env()?;
+
+    let (shutdown_tx, shutdown_rx) = watch::channel(());
+    tokio::spawn(async move {
+        shutdown_signal().await;
+        let _ = shutdown_tx.send(());
+    });
+
+    let mut servers = Vec::new();
+    let mut redirects = HashSet::new();
+    for addr in addrs {
+        let listener = addr.bind().await?;
+        // TLS is for the network, Unix domain sockets stay plain
+        let tls = match &addr {
+            listener::ListenAddr::Tcp(tcp) => {
+                let redirect = tls
+                    .as_ref()
+                    .and_then(|t| t.redirect_port)
+                    .map(|port| SocketAddr::new(tcp.ip(), port))
+                    .filter(|redirect| redirects.insert(*redirect));
+                if let Some(redirect) = redirect {
+                    let redirect_listener = listener::ListenAddr::Tcp(redirect).bind().await?;
+                    tracing::info!("redirecting http://{} to https", redirect);
+                    servers.push(tokio::spawn(serve::serve(
+                        redirect_listener,
+                        None,
+                        tls::redirect_routes(tcp.port()),
+                        proxies.clone(),
+                        shutdown_rx.clone(),
+                    )));
+                }
+                tls.clone()
+            }
+            #[cfg(unix)]
+            listener::ListenAddr::Unix(_) => None,
+        };
+        let scheme = if tls.is_some() { "https" } else { "http" };
+        tracing::info!("listening on {scheme}://{addr}");
+        servers.push(tokio::spawn(serve::serve(
+            listener,
+            tls,
+            app.clone(),
+            proxies.clone(),
+            shutdown_rx.clone(),
+        )));
+    }
+    let reload = tls.map(|t| t.run_reload());
+
+    for server in servers {
+        server.await.context("error running server")?;
+    }
+    if let Some(reload) = reload {
+        reload.abort();
+    }
 
     debug!("Shutdown completed");
     Ok(())
@@ -0,0 +1,116 @@
+use std::{net::SocketAddr, time::Duration};
+
+use axum::{
+    extract::{connect_info::ConnectInfo, Request},
+    Router,
+};
+use hyper::body::Incoming;
+use hyper_util::{
+    rt::{TokioExecutor, TokioIo},
+    server::conn::auto::Builder,
+};
+use tokio::{
+    io::{AsyncRead, AsyncWrite},
+    sync::watch,
+};
+use tower::ServiceExt;
+use tracing::{debug, warn};
+
+use super::{
+    client::TrustedProxies,
+    listener::{Listener, Stream},
+    tls::Tls,
+};
+
+/// Time a client gets to complete the TLS handshake
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Serve one connection until it is done or the server shuts down. The peer
+/// address is available to handlers as `ConnectInfo<SocketAddr>`, like with
+/// `axum::serve`, the client behind trusted proxies as
+/// [`ClientInfo`](super::client::ClientInfo).
+async fn serve_connection<I>(
+    io: I,
+    peer: SocketAddr,
+    tls: bool,
+    app: Router,
+    proxies: TrustedProxies,
+    mut shutdown: watch::Receiver<()>,
+) where
+    I: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+{
+    let service = hyper::service::service_fn(move |mut request: Request<Incoming>| {
+        let client = proxies.client(peer, tls, request.headers());
+        request.extensions_mut().insert(client);
+        request.extensions_mut().insert(ConnectInfo(peer));
+        app.clone().oneshot(request)
+    });
+    let builder = Builder::new(TokioExecutor::new());
+    let conn = builder.serve_connection_with_upgrades(TokioIo::new(io), service);
+    tokio::pin!(conn);
+    let result = tokio::select! {
+        result = conn.as_mut() => result,
+        _ = shutdown.changed() => {
+            conn.as_mut().graceful_shutdown();
+            conn.await
+        }
+    };
+    if let Err(e) = result {
+        debug!(%peer, "Connection closed with error: {e}");
+    }
+}
+
+/// Serve `listener` until `shutdown` changes, then wait for the open
+/// connections to finish. TCP connections use TLS if `tls` is set.
+pub(crate) async fn serve(
+    listener: Listener,
+    tls: Option<Tls>,
+    app: Router,
+    proxies: TrustedProxies,
+    mut shutdown: watch::Receiver<()>,
+) {
+    let (close_tx, close_rx) = watch::channel(());
+    loop {
+        let (stream, peer) = tokio::select! {
+            accepted = listener.accept() => match accepted {
+                Ok(accepted) => accepted,
+                Err(e) => {
+                    // e.g. too many open files, give the system some time
+                    warn!("Cannot accept connection: {e}");
+                    tokio::time::sleep(Duration::from_secs(1)).await;
+                    continue;
+                }
+            },
+            _ = shutdown.changed() => break,
+        };
+        let acceptor = tls.as_ref().map(Tls::acceptor);
+        let app = app.clone();
+        let proxies = proxies.clone();
+        let shutdown = shutdown.clone();
+        let close = close_rx.clone();
+        tokio::spawn(async move {
+            match (stream, acceptor) {
+                (Stream::Tcp(stream), Some(acceptor)) => {
+                    match tokio::time::timeout(HANDSHAKE_TIMEOUT, acceptor.accept(stream)).await {
+                        Ok(Ok(stream)) => {
+                            serve_connection(stream, peer, true, app, proxies, shutdown).await
+                        }
+                        Ok(Err(e)) => debug!(%peer, "TLS handshake failed: {e}"),
+                        Err(_) => debug!(%peer, "TLS handshake timed out"),
+                    }
+                }
+                (Stream::Tcp(stream), None) => {
+                    serve_connection(stream, peer, false, app, proxies, shutdown).await
+                }
+                #[cfg(unix)]
+                (Stream::Unix(stream), _) => {
+                    serve_connection(stream, peer, false, app, proxies, shutdown).await
+                }
+            }
+            drop(close);
+        });
+    }
+    drop(close_rx);
+    drop(listener);
+    close_tx.closed().await;
+}
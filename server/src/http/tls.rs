@@ -0,0 +1,153 @@
+use std::{
+    env,
+    fs::File,
+    io::BufReader,
+    path::{Path, PathBuf},
+    sync::{Arc, RwLock},
+    time::{Duration, SystemTime},
+};
+
+use axum::{
+    extract::Request,
+    http::{header, StatusCode},
+    response::{IntoResponse, Redirect},
+    Router,
+};
+use color_eyre::eyre::{eyre, Context, Result};
+use tokio::task::JoinHandle;
+use tokio_rustls::{rustls::ServerConfig, TlsAcceptor};
+use tracing::{info, warn};
+
+/// Interval of the checks for renewed certificate files
+const RELOAD_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Certificate and key from `HCS_TLS_CERT_FILE` and `HCS_TLS_KEY_FILE`.
+/// Renewed files, e.g. by certbot, are picked up without a restart.
+#[derive(Clone)]
+pub(crate) struct Tls {
+    cert: PathBuf,
+    key: PathBuf,
+    config: Arc<RwLock<Arc<ServerConfig>>>,
+    /// Port of the plain HTTP listener that redirects to HTTPS
+    pub(crate) redirect_port: Option<u16>,
+}
+
+fn load_config(cert: &Path, key: &Path) -> Result<Arc<ServerConfig>> {
+    let certs = rustls_pemfile::certs(&mut BufReader::new(
+        File::open(cert).wrap_err_with(|| format!("Cannot open {}", cert.display()))?,
+    ))
+    .collect::<Result<Vec<_>, _>>()
+    .wrap_err_with(|| format!("Invalid certificate in {}", cert.display()))?;
+    if certs.is_empty() {
+        return Err(eyre!("No certificate in {}", cert.display()));
+    }
+    let key = rustls_pemfile::private_key(&mut BufReader::new(
+        File::open(key).wrap_err_with(|| format!("Cannot open {}", key.display()))?,
+    ))
+    .wrap_err_with(|| format!("Invalid private key in {}", key.display()))?
+    .ok_or_else(|| eyre!("No private key in {}", key.display()))?;
+    let mut config = ServerConfig::builder()
+        .with_no_client_auth()
+        .with_single_cert(certs, key)
+        .wrap_err("Certificate does not match the private key")?;
+    config.alpn_protocols = vec![b"h2".to_vec(), b"http/1.1".to_vec()];
+    Ok(Arc::new(config))
+}
+
+fn modified(path: &Path) -> Option<SystemTime> {
+    path.metadata().and_then(|m| m.modified()).ok()
+}
+
+impl Tls {
+    /// Read `HCS_TLS_CERT_FILE`, `HCS_TLS_KEY_FILE` and
+    /// `HCS_TLS_REDIRECT_PORT`, `None` serves plain HTTP.
+    pub(crate) fn from_env() -> Result<Option<Self>> {
+        let file = |name: &str| env::var(name).ok().filter(|f| !f.is_empty());
+        let (cert, key) = match (file("HCS_TLS_CERT_FILE"), file("HCS_TLS_KEY_FILE")) {
+            (Some(cert), Some(key)) => (PathBuf::from(cert), PathBuf::from(key)),
+            (None, None) => return Ok(None),
+            _ => {
+                return Err(eyre!(
+                    "HCS_TLS_CERT_FILE and HCS_TLS_KEY_FILE must be set together"
+                ))
+            }
+        };
+        let redirect_port = env::var("HCS_TLS_REDIRECT_PORT")
+            .ok()
+            .filter(|p| !p.is_empty())
+            .map(|p| p.parse::<u16>())
+            .transpose()
+            .context("Cannot parse HCS_TLS_REDIRECT_PORT")?;
+        let config = load_config(&cert, &key)?;
+        info!("TLS enabled with certificate {}", cert.display());
+        Ok(Some(Self {
+            cert,
+            key,
+            config: Arc::new(RwLock::new(config)),
+            redirect_port,
+        }))
+    }
+
+    /// Acceptor with the current certificate
+    pub(crate) fn acceptor(&self) -> TlsAcceptor {
+        let config = self.config.read().unwrap_or_else(|e| e.into_inner());
+        TlsAcceptor::from(config.clone())
+    }
+
+    /// Reload certificate and key whenever one of the files changes. A
+    /// failed reload, e.g. when only the certificate has been replaced yet,
+    /// keeps the current certificate and is retried on the next check.
+    pub(crate) fn run_reload(&self) -> JoinHandle<()> {
+        let tls = self.clone();
+        tokio::spawn(async move {
+            let mut loaded = (modified(&tls.cert), modified(&tls.key));
+            let mut interval = tokio::time::interval(RELOAD_INTERVAL);
+            interval.tick().await;
+            loop {
+                interval.tick().await;
+                let current = (modified(&tls.cert), modified(&tls.key));
+                if current == loaded {
+                    continue;
+                }
+                match load_config(&tls.cert, &tls.key) {
+                    Ok(config) => {
+                        *tls.config.write().unwrap_or_else(|e| e.into_inner()) = config;
+                        loaded = current;
+                        info!("TLS certificate reloaded from {}", tls.cert.display());
+                    }
+                    Err(e) => warn!("Cannot reload TLS certificate: {:?}", e),
+                }
+            }
+        })
+    }
+}
+
+/// Host of a `Host` header without the port
+fn host_name(host: &str) -> &str {
+    if host.starts_with('[') {
+        // IPv6 literal
+        host.find(']').map_or(host, |end| &host[..=end])
+    } else {
+        host.split(':').next().unwrap_or(host)
+    }
+}
+
+/// Plain HTTP service that sends every request to the same URL on HTTPS
+pub(crate) fn redirect_routes(https_port: u16) -> Router {
+    Router::new().fallback(move |request: Request| async move {
+        let Some(host) = request
+            .headers()
+            .get(header::HOST)
+            .and_then(|h| h.to_str().ok())
+        else {
+            return (StatusCode::BAD_REQUEST, "Missing Host header").into_response();
+        };
+        let port = if https_port == 443 {
+            String::new()
+        } else {
+            format!(":{https_port}")
+        };
+        let path = request.uri().path_and_query().map_or("/", |p| p.as_str());
+        Redirect::permanent(&format!("https://{}{port}{path}", host_name(host))).into_response()
+    })
+}
@@ -0,0 +1,275 @@
+use std::{
+    collections::VecDeque,
+    env,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    time::Duration,
+};
+
+use color_eyre::eyre::{eyre, Context, Result};
+use serde::Serialize;
+use tokio::sync::Notify;
+
+/// What happens when a client does not read its updates fast enough
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum BackpressurePolicy {
+    /// Drop the oldest queued message
+    DropOldest,
+    /// Replace a queued update of the same topic, drop the oldest otherwise
+    Coalesce,
+    /// Close the connection
+    Disconnect,
+}
+
+/// Counters of the WebSocket connections
+#[derive(Default)]
+pub(crate) struct WsStats {
+    pub(crate) connections: AtomicU64,
+    pub(crate) messages_sent: AtomicU64,
+    pub(crate) messages_dropped: AtomicU64,
+    pub(crate) slow_disconnects: AtomicU64,
+    pub(crate) dead_disconnects: AtomicU64,
+}
+
+/// Values of [`WsStats`] at one point in time
+#[derive(Serialize)]
+pub(crate) struct WsStatsSnapshot {
+    pub(crate) connections: u64,
+    pub(crate) messages_sent: u64,
+    pub(crate) messages_dropped: u64,
+    pub(crate) slow_disconnects: u64,
+    pub(crate) dead_disconnects: u64,
+}
+
+impl WsStats {
+    pub(crate) fn inc(counter: &AtomicU64) {
+        counter.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn snapshot(&self) -> WsStatsSnapshot {
+        WsStatsSnapshot {
+            connections: self.connections.load(Ordering::Relaxed),
+            messages_sent: self.messages_sent.load(Ordering::Relaxed),
+            messages_dropped: self.messages_dropped.load(Ordering::Relaxed),
+            slow_disconnects: self.slow_disconnects.load(Ordering::Relaxed),
+            dead_disconnects: self.dead_disconnects.load(Ordering::Relaxed),
+        }
+    }
+}
+
+/// Settings shared by all WebSocket connections
+#[derive(Clone)]
+pub(crate) struct WebSockets {
+    queue_size: usize,
+    policy: BackpressurePolicy,
+    /// Interval of the pings sent to clients, `None` disables pings
+    pub(crate) ping_interval: Option<Duration>,
+    /// Time a client may stay silent after a ping before it is closed
+    pub(crate) pong_timeout: Duration,
+    pub(crate) stats: Arc<WsStats>,
+}
+
+impl WebSockets {
+    /// Read `HCS_WS_QUEUE_SIZE`, `HCS_WS_BACKPRESSURE`, `HCS_WS_PING_INTERVAL`
+    /// and `HCS_WS_PONG_TIMEOUT`.
+    pub(crate) fn from_env() -> Result<Self> {
+        let queue_size = env::var("HCS_WS_QUEUE_SIZE")
+            .unwrap_or_else(|_| "100".to_string())
+            .parse::<usize>()
+            .context("Cannot parse HCS_WS_QUEUE_SIZE")?;
+        if queue_size == 0 {
+            return Err(eyre!("HCS_WS_QUEUE_SIZE must be at least 1"));
+        }
+        let policy = match env::var("HCS_WS_BACKPRESSURE")
+            .unwrap_or_else(|_| "drop_oldest".to_string())
+            .to_lowercase()
+            .as_str()
+        {
+            "drop_oldest" => BackpressurePolicy::DropOldest,
+            "coalesce" => BackpressurePolicy::Coalesce,
+            "disconnect" => BackpressurePolicy::Disconnect,
+            other => return Err(eyre!("Unknown HCS_WS_BACKPRESSURE {other}")),
+        };
+        let ping_interval = env::var("HCS_WS_PING_INTERVAL")
+            .unwrap_or_else(|_| "30".to_string())
+            .parse::<u64>()
+            .context("Cannot parse HCS_WS_PING_INTERVAL")?;
+        let pong_timeout = env::var("HCS_WS_PONG_TIMEOUT")
+            .unwrap_or_else(|_| "10".to_string())
+            .parse::<u64>()
+            .context("Cannot parse HCS_WS_PONG_TIMEOUT")?;
+        if pong_timeout == 0 {
+            return Err(eyre!("HCS_WS_PONG_TIMEOUT must be at least 1"));
+        }
+        Ok(Self {
+            queue_size,
+            policy,
+            ping_interval: (ping_interval > 0).then(|| Duration::from_secs(ping_interval)),
+            pong_timeout: Duration::from_secs(pong_timeout),
+            stats: Default::default(),
+        })
+    }
+
+    pub(crate) fn queue(&self) -> ClientQueue {
+        ClientQueue {
+            state: Default::default(),
+            notify: Default::default(),
+            capacity: self.queue_size,
+            policy: self.policy,
+            stats: self.stats.clone(),
+        }
+    }
+}
+
+#[derive(Default)]
+struct QueueState {
+    /// Messages with the topic they belong to, used for coalescing
+    items: VecDeque<(Option<String>, Arc<String>)>,
+    overflowed: bool,
+}
+
+/// Outgoing messages of one client. Pushing never waits, a full queue is
+/// handled according to the backpressure policy.
+#[derive(Clone)]
+pub(crate) struct ClientQueue {
+    state: Arc<Mutex<QueueState>>,
+    notify: Arc<Notify>,
+    capacity: usize,
+    policy: BackpressurePolicy,
+    stats: Arc<WsStats>,
+}
+
+impl ClientQueue {
+    pub(crate) fn push(&self, topic: Option<&str>, message: Arc<String>) {
+        {
+            let mut state = self.state.lock().unwrap();
+            if state.overflowed {
+                return;
+            }
+            if self.policy == BackpressurePolicy::Coalesce {
+                if let Some(item) = topic.and_then(|topic| {
+                    state
+                        .items
+                        .iter_mut()
+                        .find(|(t, _)| t.as_deref() == Some(topic))
+                }) {
+                    item.1 = message;
+                    WsStats::inc(&self.stats.messages_dropped);
+                    drop(state);
+                    self.notify.notify_one();
+                    return;
+                }
+            }
+            if state.items.len() >= self.capacity {
+                if self.policy == BackpressurePolicy::Disconnect {
+                    state.overflowed = true;
+                    state.items.clear();
+                    drop(state);
+                    self.notify.notify_one();
+                    return;
+                }
+                state.items.pop_front();
+                WsStats::inc(&self.stats.messages_dropped);
+            }
+            state.items.push_back((topic.map(String::from), message));
+        }
+        self.notify.notify_one();
+    }
+
+    /// Next message to send, `None` once the client has to be disconnected
+    pub(crate) async fn pop(&self) -> Option<Arc<String>> {
+        loop {
+            {
+                let mut state = self.state.lock().unwrap();
+                if state.overflowed {
+                    return None;
+                }
+                if let Some((_, message)) = state.items.pop_front() {
+                    return Some(message);
+                }
+            }
+            self.notify.notified().await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn queue(policy: BackpressurePolicy) -> ClientQueue {
+        WebSockets {
+            queue_size: 2,
+            policy,
+            ping_interval: None,
+            pong_timeout: Duration::from_secs(1),
+            stats: Default::default(),
+        }
+        .queue()
+    }
+
+    fn push(queue: &ClientQueue, topic: &str, message: &str) {
+        queue.push(Some(topic), Arc::new(message.to_string()));
+    }
+
+    /// Messages queued right now, without waiting
+    fn drain(queue: &ClientQueue) -> Vec<String> {
+        let mut state = queue.state.lock().unwrap();
+        state.items.drain(..).map(|(_, m)| m.to_string()).collect()
+    }
+
+    fn dropped(queue: &ClientQueue) -> u64 {
+        queue.stats.messages_dropped.load(Ordering::Relaxed)
+    }
+
+    #[test]
+    fn drop_oldest() {
+        let queue = queue(BackpressurePolicy::DropOldest);
+        push(&queue, "a", "1");
+        push(&queue, "a", "2");
+        push(&queue, "b", "3");
+        assert_eq!(drain(&queue), ["2", "3"]);
+        assert_eq!(dropped(&queue), 1);
+    }
+
+    #[test]
+    fn coalesce_same_topic() {
+        let queue = queue(BackpressurePolicy::Coalesce);
+        push(&queue, "a", "1");
+        push(&queue, "b", "2");
+        push(&queue, "a", "3");
+        assert_eq!(drain(&queue), ["3", "2"]);
+        // messages without topic are never coalesced
+        queue.push(None, Arc::new("4".to_string()));
+        queue.push(None, Arc::new("5".to_string()));
+        push(&queue, "c", "6");
+        assert_eq!(drain(&queue), ["5", "6"]);
+        assert_eq!(dropped(&queue), 2);
+    }
+
+    #[tokio::test]
+    async fn disconnect_when_full() {
+        let queue = queue(BackpressurePolicy::Disconnect);
+        push(&queue, "a", "1");
+        assert_eq!(queue.pop().await.as_deref().map(String::as_str), Some("1"));
+        push(&queue, "a", "2");
+        push(&queue, "b", "3");
+        push(&queue, "c", "4");
+        assert!(queue.pop().await.is_none());
+        push(&queue, "d", "5");
+        assert!(queue.pop().await.is_none());
+        assert_eq!(dropped(&queue), 0);
+    }
+
+    #[tokio::test]
+    async fn pop_waits_for_push() {
+        let queue = queue(BackpressurePolicy::DropOldest);
+        let sender = queue.clone();
+        let pop = tokio::spawn(async move { queue.pop().await });
+        tokio::task::yield_now().await;
+        push(&sender, "a", "1");
+        assert_eq!(pop.await.unwrap().as_deref().map(String::as_str), Some("1"));
+    }
+}
@@ -0,0 +1,99 @@
+use serde_json::Value;
+
+/// Resolve a simple path like `$.sensor.values[0]` or `sensor.values.0`
+/// inside a JSON value.
+pub(crate) fn lookup<'a>(value: &'a Value, path: &str) -> Option<&'a Value> {
+    let path = path.trim();
+    let path = path.strip_prefix('$').unwrap_or(path);
+    let mut current = value;
+    for segment in path
+        .split(['.', '[', ']'])
+        .filter(|segment| !segment.is_empty())
+    {
+        current = match current {
+            Value::Object(map) => map.get(segment)?,
+            Value::Array(items) => items.get(segment.parse::<usize>().ok()?)?,
+            _ => return None,
+        };
+    }
+    Some(current)
+}
+
+/// Interpret a JSON value as a number. Strings and booleans are converted
+/// when they have an obvious numeric meaning.
+pub(crate) fn as_number(value: &Value) -> Option<f64> {
+    match value {
+        Value::Number(n) => n.as_f64(),
+        Value::Bool(b) => Some(if *b { 1.0 } else { 0.0 }),
+        Value::String(s) => parse_number(s),
+        _ => None,
+    }
+}
+
+/// Parse a raw payload as a number, accepting common textual switch states.
+pub(crate) fn parse_number(text: &str) -> Option<f64> {
+    let text = text.trim();
+    if let Ok(n) = text.parse::<f64>() {
+        return Some(n);
+    }
+    match text.to_lowercase().as_str() {
+        "on" | "true" => Some(1.0),
+        "off" | "false" => Some(0.0),
+        _ => None,
+    }
+}
+
+/// Extract a number from a payload, optionally following a path into a
+/// JSON document.
+pub(crate) fn extract_number(payload: &str, path: Option<&str>) -> Option<f64> {
+    match path {
+        Some(path) if !path.is_empty() => {
+            let parsed = serde_json::from_str::<Value>(payload).ok()?;
+            as_number(lookup(&parsed, path)?)
+        }
+        _ => parse_number(payload),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn lookup_paths() {
+        let value = json!({"sensor": {"values": [1, {"temp": 21.5}]}});
+        assert_eq!(lookup(&value, "$.sensor.values[0]"), Some(&json!(1)));
+        assert_eq!(lookup(&value, "sensor.values.1.temp"), Some(&json!(21.5)));
+        assert_eq!(lookup(&value, " $ "), Some(&value));
+        assert_eq!(lookup(&value, "sensor.missing"), None);
+        assert_eq!(lookup(&value, "sensor.values[5]"), None);
+        assert_eq!(lookup(&value, "sensor.values.x"), None);
+        assert_eq!(lookup(&value, "sensor.values[0].deeper"), None);
+    }
+
+    #[test]
+    fn parse_numbers() {
+        assert_eq!(parse_number(" 21.5 "), Some(21.5));
+        assert_eq!(parse_number("-3"), Some(-3.0));
+        assert_eq!(parse_number("ON"), Some(1.0));
+        assert_eq!(parse_number("true"), Some(1.0));
+        assert_eq!(parse_number("Off"), Some(0.0));
+        assert_eq!(parse_number("false"), Some(0.0));
+        assert_eq!(parse_number("open"), None);
+        assert_eq!(parse_number(""), None);
+    }
+
+    #[test]
+    fn extract_numbers() {
+        assert_eq!(
+            extract_number("{\"a\":{\"b\":\"7\"}}", Some("a.b")),
+            Some(7.0)
+        );
+        assert_eq!(extract_number("{\"a\":true}", Some("$.a")), Some(1.0));
+        assert_eq!(extract_number("not json", Some("a")), None);
+        assert_eq!(extract_number("12", Some("")), Some(12.0));
+        assert_eq!(extract_number("12", None), Some(12.0));
+    }
+}
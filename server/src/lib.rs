@@ -1,10 +1,43 @@
+use adapters::run_adapters;
+use audit::AuditLog;
 use color_eyre::eyre::{Context, Result};
-use http::appstate::AppState;
+use devices::DeviceRegistry;
+use hass::{run_hass_discovery, HassRegistry};
+use history::{history_config_from_env, run_history_recorder};
+use homie::run_homie_discovery;
+use http::{appstate::AppState, websocket::WebSockets};
+use metrics::Metrics;
 use mqtta::run_subscriber_actor;
+use oidc::run_oidc;
+use ratelimit::RateLimits;
+use rules::run_rules;
+use scenes::Scenes;
+use scheduler::{run_scheduler, Scheduler};
+use timers::run_timers;
 use tracing::debug;
+use transforms::Transforms;
+use zigbee::run_zigbee_bridge;
 
+mod actions;
+mod adapters;
+mod audit;
+mod datadir;
+mod devices;
+mod hass;
+mod history;
+mod homie;
 mod http;
+mod jsonpath;
+mod metrics;
 mod mqtta;
+mod oidc;
+mod ratelimit;
+mod rules;
+mod scenes;
+mod scheduler;
+mod timers;
+mod transforms;
+mod zigbee;
 
 pub async fn run() -> Result<()> {
     let channelsize = std::env::var("HCS_PERF_CHANNELBUFSIZE")
@@ -12,8 +45,44 @@ pub async fn run() -> Result<()> {
         .parse::<usize>()
         .context("Cannot parse HCS_PERF_CHANNELBUFSIZE")?;
     let mo = mqtta::mqtt_options_from_env()?;
-    let (handle, tx, jh) = run_subscriber_actor(channelsize, mo).await;
-    let appstate = AppState::builder().mqtt(handle).build();
+    let transforms = Transforms::from_env()?;
+    let websockets = WebSockets::from_env()?;
+    let metrics = Metrics::from_env()?;
+    let ratelimits = RateLimits::from_env(metrics.clone())?;
+    let audit = AuditLog::from_env()?;
+    let history_config = history_config_from_env()?;
+    let devices = DeviceRegistry::from_env()?;
+    let scenes = Scenes::from_env()?;
+    let scheduler = Scheduler::from_env(scenes.clone())?;
+    let (handle, tx, jh) = run_subscriber_actor(channelsize, mo, transforms, metrics.clone()).await;
+    let (history, _history_tasks) = run_history_recorder(handle.clone(), history_config).await?;
+    let hass = HassRegistry::default();
+    let _hass_task = run_hass_discovery(handle.clone(), hass.clone());
+    let (zigbee, _zigbee_task) = run_zigbee_bridge(handle.clone());
+    let (adapters, _adapter_tasks) = run_adapters(handle.clone())?;
+    let (homie, _homie_task) = run_homie_discovery(handle.clone());
+    let _scheduler_task = run_scheduler(handle.clone(), scheduler.clone());
+    let rules = run_rules(handle.clone(), scenes.clone()).await?;
+    let (oidc, _oidc_task) = run_oidc()?;
+    let (timers, _timer_task) = run_timers(handle.clone(), scenes.clone()).await?;
+    let appstate = AppState::builder()
+        .mqtt(handle)
+        .history(history)
+        .devices(devices)
+        .hass(hass)
+        .zigbee(zigbee)
+        .adapters(adapters)
+        .homie(homie)
+        .scenes(scenes)
+        .scheduler(scheduler)
+        .rules(rules)
+        .timers(timers)
+        .websockets(websockets)
+        .metrics(metrics)
+        .oidc(oidc)
+        .ratelimits(ratelimits)
+        .audit(audit)
+        .build();
     http::http_server(appstate).await?;
     debug!("Shutdown");
     let _ = tx.send(());
@@ -0,0 +1,267 @@
+use std::{env, sync::Arc, time::Duration};
+
+use axum::http::StatusCode;
+use color_eyre::eyre::{eyre, Context, Result};
+use prometheus::{
+    Encoder, HistogramOpts, HistogramVec, IntCounter, IntCounterVec, IntGauge, Opts, Registry,
+    TextEncoder,
+};
+use subtle::ConstantTimeEq;
+use tracing::warn;
+
+use crate::{datadir::now_millis, http::websocket::WsStats};
+
+/// Label for topics that match none of the configured patterns
+const OTHER_TOPICS: &str = "other";
+
+/// Prometheus metrics of the server
+#[derive(Clone)]
+pub(crate) struct Metrics {
+    registry: Registry,
+    /// Topic filters used as label for the message counters
+    patterns: Arc<Vec<String>>,
+    /// Bearer token required to read the metrics, not set disables the endpoint
+    token: Option<String>,
+    mqtt_messages_in: IntCounterVec,
+    mqtt_messages_out: IntCounterVec,
+    mqtt_publish_failures: IntCounter,
+    mqtt_reconnects: IntCounter,
+    mqtt_connected: IntGauge,
+    mqtt_last_message: IntGauge,
+    mqtt_watchers: IntGauge,
+    mqtt_actor_queue: IntGauge,
+    mqtt_stream_dropped: IntCounter,
+    ws_connections: IntGauge,
+    ws_messages_sent: IntCounter,
+    ws_messages_dropped: IntCounter,
+    ws_disconnects: IntCounterVec,
+    http_request_duration: HistogramVec,
+    rate_limited: IntCounterVec,
+}
+
+fn register<T: prometheus::core::Collector + Clone + 'static>(
+    registry: &Registry,
+    metric: T,
+) -> Result<T> {
+    registry
+        .register(Box::new(metric.clone()))
+        .wrap_err("Cannot register metric")?;
+    Ok(metric)
+}
+
+impl Metrics {
+    /// Read `HCS_METRICS_TOKEN` and `HCS_METRICS_TOPIC_PATTERNS`.
+    pub(crate) fn from_env() -> Result<Self> {
+        let token = env::var("HCS_METRICS_TOKEN").ok().filter(|t| !t.is_empty());
+        let patterns: Vec<String> = env::var("HCS_METRICS_TOPIC_PATTERNS")
+            .unwrap_or_default()
+            .split(',')
+            .map(|p| p.trim().to_string())
+            .filter(|p| !p.is_empty())
+            .collect();
+        if let Some(p) = patterns.iter().find(|p| !rumqttc::valid_filter(p)) {
+            return Err(eyre!(
+                "Invalid topic filter {p} in HCS_METRICS_TOPIC_PATTERNS"
+            ));
+        }
+
+        let registry = Registry::new_custom(Some("hcs".to_string()), None)?;
+        Ok(Self {
+            patterns: Arc::new(patterns),
+            token,
+            mqtt_messages_in: register(
+                &registry,
+                IntCounterVec::new(
+                    Opts::new("mqtt_messages_received_total", "MQTT messages received"),
+                    &["pattern"],
+                )?,
+            )?,
+            mqtt_messages_out: register(
+                &registry,
+                IntCounterVec::new(
+                    Opts::new("mqtt_messages_published_total", "MQTT messages published"),
+                    &["pattern"],
+                )?,
+            )?,
+            mqtt_publish_failures: register(
+                &registry,
+                IntCounter::new("mqtt_publish_failures_total", "Failed MQTT publishes")?,
+            )?,
+            mqtt_reconnects: register(
+                &registry,
+                IntCounter::new("mqtt_reconnects_total", "Reconnects to the MQTT broker")?,
+            )?,
+            mqtt_connected: register(
+                &registry,
+                IntGauge::new("mqtt_connected", "1 while connected to the MQTT broker")?,
+            )?,
+            mqtt_last_message: register(
+                &registry,
+                IntGauge::new(
+                    "mqtt_last_message_timestamp_seconds",
+                    "Time the last MQTT message was received",
+                )?,
+            )?,
+            mqtt_watchers: register(
+                &registry,
+                IntGauge::new("mqtt_watched_topics", "Topics watched for clients")?,
+            )?,
+            mqtt_actor_queue: register(
+                &registry,
+                IntGauge::new(
+                    "mqtt_actor_queue_depth",
+                    "Messages waiting for the MQTT actor",
+                )?,
+            )?,
+            mqtt_stream_dropped: register(
+                &registry,
+                IntCounter::new(
+                    "mqtt_stream_dropped_total",
+                    "Messages dropped for slow topic filter streams",
+                )?,
+            )?,
+            ws_connections: register(
+                &registry,
+                IntGauge::new("ws_connections", "Open WebSocket connections")?,
+            )?,
+            ws_messages_sent: register(
+                &registry,
+                IntCounter::new(
+                    "ws_messages_sent_total",
+                    "Messages sent to WebSocket clients",
+                )?,
+            )?,
+            ws_messages_dropped: register(
+                &registry,
+                IntCounter::new(
+                    "ws_messages_dropped_total",
+                    "Messages dropped for slow WebSocket clients",
+                )?,
+            )?,
+            ws_disconnects: register(
+                &registry,
+                IntCounterVec::new(
+                    Opts::new(
+                        "ws_disconnects_total",
+                        "WebSocket clients closed by the server",
+                    ),
+                    &["reason"],
+                )?,
+            )?,
+            http_request_duration: register(
+                &registry,
+                HistogramVec::new(
+                    HistogramOpts::new("http_request_duration_seconds", "HTTP request latency"),
+                    &["status"],
+                )?,
+            )?,
+            rate_limited: register(
+                &registry,
+                IntCounterVec::new(
+                    Opts::new("rate_limited_total", "Requests rejected by a rate limit"),
+                    &["scope"],
+                )?,
+            )?,
+            registry,
+        })
+    }
+
+    /// Content type of [`Metrics::render`]
+    pub(crate) fn content_type(&self) -> String {
+        TextEncoder::new().format_type().to_string()
+    }
+
+    pub(crate) fn enabled(&self) -> bool {
+        self.token.is_some()
+    }
+
+    /// Whether the value of an `Authorization` header grants access
+    pub(crate) fn authorized(&self, header: Option<&str>) -> bool {
+        match (&self.token, header.and_then(|h| h.strip_prefix("Bearer "))) {
+            // constant time, the comparison must not tell how much of a guess is right
+            (Some(token), Some(given)) => token.as_bytes().ct_eq(given.as_bytes()).into(),
+            _ => false,
+        }
+    }
+
+    fn pattern(&self, topic: &str) -> &str {
+        self.patterns
+            .iter()
+            .find(|p| rumqttc::matches(topic, p))
+            .map_or(OTHER_TOPICS, |p| p.as_str())
+    }
+
+    pub(crate) fn message_received(&self, topic: &str) {
+        self.mqtt_messages_in
+            .with_label_values(&[self.pattern(topic)])
+            .inc();
+        self.mqtt_last_message.set((now_millis() / 1000) as i64);
+    }
+
+    pub(crate) fn message_published(&self, topic: &str) {
+        self.mqtt_messages_out
+            .with_label_values(&[self.pattern(topic)])
+            .inc();
+    }
+
+    pub(crate) fn publish_failed(&self) {
+        self.mqtt_publish_failures.inc();
+    }
+
+    pub(crate) fn connected(&self, reconnect: bool) {
+        if reconnect {
+            self.mqtt_reconnects.inc();
+        }
+        self.mqtt_connected.set(1);
+    }
+
+    pub(crate) fn disconnected(&self) {
+        self.mqtt_connected.set(0);
+    }
+
+    pub(crate) fn watched_topics(&self, count: usize) {
+        self.mqtt_watchers.set(count as i64);
+    }
+
+    pub(crate) fn stream_dropped(&self) {
+        self.mqtt_stream_dropped.inc();
+    }
+
+    pub(crate) fn http_response(&self, status: StatusCode, latency: Duration) {
+        self.http_request_duration
+            .with_label_values(&[status.as_str()])
+            .observe(latency.as_secs_f64());
+    }
+
+    pub(crate) fn rate_limited(&self, scope: &str) {
+        self.rate_limited.with_label_values(&[scope]).inc();
+    }
+
+    /// Text exposition of all metrics, values owned by other components are
+    /// passed in at scrape time.
+    pub(crate) fn render(&self, actor_queue: usize, ws: &WsStats) -> String {
+        self.mqtt_actor_queue.set(actor_queue as i64);
+        let ws = ws.snapshot();
+        self.ws_connections.set(ws.connections as i64);
+        // the WebSocket counters are kept by the connections, catch up to them
+        let catch_up = |counter: &IntCounter, value: u64| {
+            counter.inc_by(value.saturating_sub(counter.get()));
+        };
+        catch_up(&self.ws_messages_sent, ws.messages_sent);
+        catch_up(&self.ws_messages_dropped, ws.messages_dropped);
+        catch_up(
+            &self.ws_disconnects.with_label_values(&["slow"]),
+            ws.slow_disconnects,
+        );
+        catch_up(
+            &self.ws_disconnects.with_label_values(&["dead"]),
+            ws.dead_disconnects,
+        );
+
+        let mut buffer = Vec::new();
+        if let Err(e) = TextEncoder::new().encode(&self.registry.gather(), &mut buffer) {
+            warn!("Cannot encode metrics: {:?}", e);
+        }
+        String::from_utf8(buffer).unwrap_or_default()
+    }
+}
@@ -1,26 +1,60 @@
-use std::{collections::HashMap, env, sync::Arc, time::Duration};
+use std::{
+    collections::HashMap,
+    env,
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
 
 use color_eyre::eyre::{eyre, Context, Result};
 use rand::distributions::{Alphanumeric, DistString};
 use rumqttc::{
-    mqttbytes::v4::Packet::Publish, AsyncClient, Event::Incoming, MqttOptions, QoS, Transport,
+    mqttbytes::v4::Packet::{ConnAck, Publish},
+    AsyncClient,
+    Event::Incoming,
+    MqttOptions, QoS, Transport,
 };
 use serde_json::json;
 use tokio::{
-    sync::{mpsc, watch, RwLock},
+    sync::{
+        mpsc::{self, error::TrySendError},
+        watch, RwLock,
+    },
     task,
 };
 use tracing::{debug, error, info, warn};
 
-use super::message::ActorMessage;
+use super::message::{ActorMessage, IncomingMessage};
+use crate::{datadir::now_millis, metrics::Metrics, transforms::Transforms};
+
+struct Watcher {
+    tx: watch::Sender<Arc<String>>,
+    rx: watch::Receiver<Arc<String>>,
+    /// Number of messages received on the topic, lets clients detect gaps
+    seq: AtomicU64,
+}
+
+type WatcherMap = Arc<RwLock<HashMap<String, Watcher>>>;
 
-type WatcherMap =
-    Arc<RwLock<HashMap<String, (watch::Sender<Arc<String>>, watch::Receiver<Arc<String>>)>>>;
+/// The broker delivers at most the QoS of the subscription, subscribing with
+/// the highest one keeps the QoS of the original publish in updates
+const SUBSCRIBE_QOS: QoS = QoS::ExactlyOnce;
+
+/// Messages buffered per stream listener, newer ones are dropped while it is full
+const STREAM_CAPACITY: usize = 1000;
+
+/// Topic filters with listeners that receive every matching message
+type StreamList = Arc<RwLock<Vec<(String, mpsc::Sender<Arc<IncomingMessage>>)>>>;
 
 pub(super) struct SubscriberActor {
     pub(crate) receiver: mpsc::Receiver<ActorMessage>,
     watchers: WatcherMap,
+    streams: StreamList,
     client: AsyncClient,
+    transforms: Transforms,
+    metrics: Metrics,
     run: Arc<RwLock<bool>>,
     polltask: task::JoinHandle<()>,
 }
@@ -121,11 +155,72 @@ pub(crate) fn mqtt_options_from_env() -> color_eyre::Result<MqttOptions> {
     Ok(mqttoptions)
 }
 
+/// Forward a message to all stream listeners whose filter matches, count
+/// messages dropped for listeners that fall behind and forget listeners that
+/// went away.
+async fn dispatch_streams(
+    streams: &StreamList,
+    watchers: &WatcherMap,
+    client: &AsyncClient,
+    metrics: &Metrics,
+    message: IncomingMessage,
+) {
+    let mut closed = false;
+    {
+        let list = streams.read().await;
+        let matching: Vec<_> = list
+            .iter()
+            .filter(|(filter, _)| rumqttc::matches(&message.topic, filter))
+            .collect();
+        if matching.is_empty() {
+            return;
+        }
+        let message = Arc::new(message);
+        for (filter, tx) in matching {
+            match tx.try_send(message.clone()) {
+                Ok(()) => {}
+                Err(TrySendError::Full(_)) => {
+                    metrics.stream_dropped();
+                    debug!("Stream is full, dropping message for: {}", filter);
+                }
+                Err(TrySendError::Closed(_)) => closed = true,
+            }
+        }
+    }
+    if closed {
+        // same lock order as Release, watchers before streams
+        let watched = watchers.read().await;
+        let mut list = streams.write().await;
+        let (gone, open): (Vec<_>, Vec<_>) = list.drain(..).partition(|(_, tx)| tx.is_closed());
+        *list = open;
+        for (filter, _) in gone {
+            if watched.contains_key(&filter) || list.iter().any(|(f, _)| *f == filter) {
+                continue;
+            }
+            debug!("Unsubscribing from stream: {}", &filter);
+            // called from the poll loop, which drains the request channel
+            if let Err(e) = client.try_unsubscribe(&filter) {
+                error!("Error unsubscribing from: {} - {:?}", filter, e);
+            }
+        }
+    }
+}
+
 impl SubscriberActor {
-    pub(super) fn new(receiver: mpsc::Receiver<ActorMessage>, mqttoptions: MqttOptions) -> Self {
+    pub(super) fn new(
+        receiver: mpsc::Receiver<ActorMessage>,
+        mqttoptions: MqttOptions,
+        transforms: Transforms,
+        metrics: Metrics,
+        connected: Arc<AtomicBool>,
+    ) -> Self {
         debug!("Creating subscriber actor");
+        let looptransforms = transforms.clone();
+        let loopmetrics = metrics.clone();
         let watchers: WatcherMap = Default::default();
         let loopmap = watchers.clone();
+        let streams: StreamList = Default::default();
+        let loopstreams = streams.clone();
         let runindicator = Arc::new(RwLock::new(true));
         let runloopindicator = runindicator.clone();
 
@@ -142,8 +237,10 @@ impl SubscriberActor {
             port, clientid, with_credentials, with_tls, "Using mqtt"
         );
         let (client, mut eventloop) = AsyncClient::new(mqttoptions, 10);
+        let loopclient = client.clone();
         let polltask = task::spawn(async move {
             debug!("Actor mqtt started");
+            let mut connected_before = false;
             loop {
                 let p = eventloop.poll().await;
                 match p {
@@ -151,26 +248,57 @@ impl SubscriberActor {
                         debug!("Actor mqtt received = {:?}", p);
                         if let Incoming(i) = p {
                             match i {
+                                ConnAck(_) => {
+                                    connected.store(true, Ordering::Relaxed);
+                                    loopmetrics.connected(connected_before);
+                                    connected_before = true;
+                                }
                                 Publish(p) => {
                                     let topic = p.topic;
+                                    loopmetrics.message_received(&topic);
+                                    let ts = now_millis();
                                     let map = loopmap.read().await;
                                     if let Some(w) = map.get(&topic) {
-                                        let tx = w.0.clone();
-                                        let new_message = Arc::new(
-                                            json!({
-                                                "type": "update",
-                                                "topic": topic.clone(),
-                                                "data": String::from_utf8(p.payload.to_vec())
-                                                .unwrap_or_default()
-                                            })
-                                            .to_string(),
-                                        );
+                                        let tx = w.tx.clone();
+                                        let seq = w.seq.fetch_add(1, Ordering::Relaxed) + 1;
+                                        let raw = String::from_utf8(p.payload.to_vec())
+                                            .unwrap_or_default();
+                                        let mut update = json!({
+                                            "type": "update",
+                                            "topic": topic.clone(),
+                                            "ts": ts,
+                                            "seq": seq,
+                                            "retain": p.retain,
+                                            "qos": p.qos as u8,
+                                        });
+                                        // the payload as received is kept for history paths
+                                        match looptransforms.apply(&topic, &raw) {
+                                            Some(data) => {
+                                                update["data"] = data.into();
+                                                update["raw"] = raw.into();
+                                            }
+                                            None => update["data"] = raw.into(),
+                                        }
+                                        let new_message = Arc::new(update.to_string());
                                         if tx.send(new_message).is_err() {
                                             error!("Error sending message to watcher: {:?}", topic);
                                         }
                                     } else {
                                         debug!("No watcher for topic: {}", &topic);
                                     }
+                                    drop(map);
+                                    dispatch_streams(
+                                        &loopstreams,
+                                        &loopmap,
+                                        &loopclient,
+                                        &loopmetrics,
+                                        IncomingMessage {
+                                            topic,
+                                            payload: p.payload.to_vec(),
+                                            ts,
+                                        },
+                                    )
+                                    .await;
                                 }
                                 _ => {
                                     debug!("No match for Incoming packet");
@@ -181,6 +309,8 @@ impl SubscriberActor {
                         }
                     }
                     Err(e) => {
+                        connected.store(false, Ordering::Relaxed);
+                        loopmetrics.disconnected();
                         error!("Error polling: {:?}", e);
                     }
                 }
@@ -195,7 +325,10 @@ impl SubscriberActor {
         SubscriberActor {
             receiver,
             watchers,
+            streams,
             client,
+            transforms,
+            metrics,
             run: runindicator,
             polltask,
         }
@@ -207,18 +340,20 @@ impl SubscriberActor {
                 payload,
                 respond_to,
             } => {
+                let value = self
+                    .transforms
+                    .reverse(&payload.topic, payload.value.clone());
                 let pubresult = self
                     .client
-                    .publish(
-                        &payload.topic,
-                        payload.qos,
-                        payload.retain,
-                        payload.value.clone(),
-                    )
+                    .publish(&payload.topic, payload.qos, payload.retain, value)
                     .await;
                 let _ = respond_to.send(match pubresult {
-                    Ok(_) => String::from("OK"),
+                    Ok(_) => {
+                        self.metrics.message_published(&payload.topic);
+                        String::from("OK")
+                    }
                     Err(err) => {
+                        self.metrics.publish_failed();
                         warn!("Sending {:?} failed {:?}", payload, err);
                         String::from("Error")
                     }
@@ -231,21 +366,64 @@ impl SubscriberActor {
                 let mut w = self.watchers.write().await;
                 let vo = w.get(&topic);
                 let rx = if let Some(v) = vo {
-                    v.1.clone()
+                    v.rx.clone()
                 } else {
                     let (tx, rx) = watch::channel(Arc::new(String::new()));
                     let rrx = rx.clone();
-                    w.insert(topic.clone(), (tx, rx));
+                    w.insert(
+                        topic.clone(),
+                        Watcher {
+                            tx,
+                            rx,
+                            seq: AtomicU64::new(0),
+                        },
+                    );
+                    self.metrics.watched_topics(w.len());
                     rrx
                 };
                 debug!("Subscribing to: {}", &topic);
-                let s = self.client.subscribe(&topic, QoS::AtMostOnce).await;
+                let s = self.client.subscribe(&topic, SUBSCRIBE_QOS).await;
                 match s {
                     Ok(_) => debug!("Subscribed to: {}", &topic),
                     Err(e) => error!("Error subscribing to: {} - {:?}", topic, e),
                 }
                 let _ = respond_to.send(rx);
             }
+            ActorMessage::Release { topics } => {
+                let mut w = self.watchers.write().await;
+                let streams = self.streams.read().await;
+                for topic in topics {
+                    // the watcher keeps one receiver itself
+                    let unused = w.get(&topic).is_some_and(|v| v.tx.receiver_count() <= 1);
+                    if !unused {
+                        continue;
+                    }
+                    w.remove(&topic);
+                    self.metrics.watched_topics(w.len());
+                    if streams.iter().any(|(filter, _)| *filter == topic) {
+                        continue;
+                    }
+                    debug!("Unsubscribing from: {}", &topic);
+                    if let Err(e) = self.client.unsubscribe(&topic).await {
+                        error!("Error unsubscribing from: {} - {:?}", topic, e);
+                    }
+                }
+            }
+            ActorMessage::Stream { filter, respond_to } => {
+                if !rumqttc::valid_filter(&filter) {
+                    warn!("Invalid topic filter: {}", &filter);
+                    return;
+                }
+                let (tx, rx) = mpsc::channel(STREAM_CAPACITY);
+                self.streams.write().await.push((filter.clone(), tx));
+                debug!("Streaming: {}", &filter);
+                let s = self.client.subscribe(&filter, SUBSCRIBE_QOS).await;
+                match s {
+                    Ok(_) => debug!("Subscribed to: {}", &filter),
+                    Err(e) => error!("Error subscribing to: {} - {:?}", filter, e),
+                }
+                let _ = respond_to.send(rx);
+            }
         }
     }
 
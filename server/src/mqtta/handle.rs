@@ -1,18 +1,71 @@
-use tokio::sync::mpsc;
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc,
+};
 
-use super::message::ActorMessage;
+use tokio::sync::{mpsc, oneshot};
+
+use super::message::{ActorMessage, PublishMessage};
 
 #[derive(Clone)]
 pub struct MqttHandle {
     sender: mpsc::Sender<ActorMessage>,
+    connected: Arc<AtomicBool>,
 }
 
 impl MqttHandle {
-    pub(super) fn new(sender: mpsc::Sender<ActorMessage>) -> Self {
-        Self { sender }
+    pub(super) fn new(sender: mpsc::Sender<ActorMessage>, connected: Arc<AtomicBool>) -> Self {
+        Self { sender, connected }
+    }
+
+    /// Whether the actor is connected to the broker
+    pub(crate) fn connected(&self) -> bool {
+        self.connected.load(Ordering::Relaxed)
+    }
+
+    /// Number of messages waiting for the actor
+    pub(crate) fn queue_depth(&self) -> usize {
+        self.sender.max_capacity() - self.sender.capacity()
     }
 
     pub(crate) async fn send(&self, message: ActorMessage) {
         let _ = self.sender.send(message).await;
     }
+
+    /// Guard that releases `topics` once dropped, push topics as they are
+    /// watched
+    pub(crate) fn release_guard(&self, topics: Vec<String>) -> ReleaseGuard {
+        ReleaseGuard {
+            mqtt: self.clone(),
+            topics,
+        }
+    }
+
+    /// Publish a message and wait for the result from the actor
+    pub(crate) async fn publish(&self, payload: PublishMessage) -> String {
+        let (tx, rx) = oneshot::channel::<String>();
+        self.send(ActorMessage::Publish {
+            payload,
+            respond_to: tx,
+        })
+        .await;
+        match rx.await {
+            Ok(v) => v,
+            Err(_) => "No response".to_string(),
+        }
+    }
+}
+
+/// Releases the watched topics once the watcher is gone
+pub(crate) struct ReleaseGuard {
+    mqtt: MqttHandle,
+    pub(crate) topics: Vec<String>,
+}
+
+impl Drop for ReleaseGuard {
+    fn drop(&mut self) {
+        let mqtt = self.mqtt.clone();
+        let topics = std::mem::take(&mut self.topics);
+        tokio::spawn(async move { mqtt.send(ActorMessage::Release { topics }).await });
+    }
 }
@@ -1,7 +1,7 @@
 use std::sync::Arc;
 
 use rumqttc::QoS;
-use tokio::sync::{oneshot, watch};
+use tokio::sync::{mpsc, oneshot, watch};
 use typed_builder::TypedBuilder;
 
 #[derive(Debug, TypedBuilder)]
@@ -12,6 +12,24 @@ pub(crate) struct PublishMessage {
     pub(crate) retain: bool,
 }
 
+/// A message received from the broker
+#[derive(Debug)]
+pub(crate) struct IncomingMessage {
+    pub(crate) topic: String,
+    pub(crate) payload: Vec<u8>,
+    /// Receive time in milliseconds since the unix epoch
+    pub(crate) ts: u64,
+}
+
+/// Map a numeric QoS level, values above 2 are treated as 0
+pub(crate) fn qos_from_u8(qos: u8) -> QoS {
+    match qos {
+        2 => QoS::ExactlyOnce,
+        1 => QoS::AtLeastOnce,
+        _ => QoS::AtMostOnce,
+    }
+}
+
 pub(crate) enum ActorMessage {
     /// Publish
     Publish {
@@ -25,4 +43,11 @@ pub(crate) enum ActorMessage {
         topic: String,
         respond_to: oneshot::Sender<watch::Receiver<Arc<String>>>,
     },
+    /// Subscribe to a topic filter and receive every matching message
+    Stream {
+        filter: String,
+        respond_to: oneshot::Sender<mpsc::Receiver<Arc<IncomingMessage>>>,
+    },
+    /// Stop watching topics nobody listens to anymore
+    Release { topics: Vec<String> },
 }
@@ -5,6 +5,8 @@ pub(crate) mod message;
 pub(crate) use actor::mqtt_options_from_env;
 use actor::SubscriberActor;
 pub(crate) use handle::MqttHandle;
+use std::sync::{atomic::AtomicBool, Arc};
+
 use rumqttc::MqttOptions;
 use tokio::{
     sync::{mpsc, oneshot},
@@ -12,13 +14,24 @@ use tokio::{
 };
 use tracing::debug;
 
+use crate::{metrics::Metrics, transforms::Transforms};
+
 pub(crate) async fn run_subscriber_actor(
     channelsize: usize,
     mqttoptions: MqttOptions,
+    transforms: Transforms,
+    metrics: Metrics,
 ) -> (MqttHandle, oneshot::Sender<()>, JoinHandle<()>) {
     debug!("Setup mqtt with {channelsize} buffer size");
     let (sender, receiver) = mpsc::channel(channelsize);
-    let mut actor = SubscriberActor::new(receiver, mqttoptions);
+    let connected = Arc::new(AtomicBool::new(false));
+    let mut actor = SubscriberActor::new(
+        receiver,
+        mqttoptions,
+        transforms,
+        metrics,
+        connected.clone(),
+    );
     let (tx, mut rx) = oneshot::channel::<()>();
     let jh = tokio::spawn(async move {
         loop {
@@ -37,6 +50,6 @@ pub(crate) async fn run_subscriber_actor(
         }
         debug!("Leaving loop actor");
     });
-    let handle = MqttHandle::new(sender);
+    let handle = MqttHandle::new(sender, connected);
     (handle, tx, jh)
 }
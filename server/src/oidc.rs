@@ -0,0 +1,109 @@
+use std::{
+    env,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, OnceLock,
+    },
+    time::Duration,
+};
+
+use color_eyre::eyre::{Context, Result};
+use jsonwebtoken::{Algorithm, Header};
+use jwt_authorizer::{Authorizer, JwtAuthorizer, Validation};
+use tokio::task::JoinHandle;
+use tracing::{debug, info, warn};
+
+use crate::http::claims::Claims;
+
+/// Interval of the key checks
+const CHECK_INTERVAL: Duration = Duration::from_secs(60);
+/// Wait before discovery is tried again after it failed
+const RETRY_INTERVAL: Duration = Duration::from_secs(10);
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+/// One algorithm per key type, a key of any of them can validate tokens
+const KEY_ALGORITHMS: [Algorithm; 3] = [Algorithm::RS256, Algorithm::ES256, Algorithm::EdDSA];
+
+/// The JWT authorizer once OIDC discovery succeeded and whether it holds
+/// signing keys of the issuer
+#[derive(Clone, Default)]
+pub(crate) struct OidcStatus {
+    authorizer: Arc<OnceLock<Arc<Authorizer<Claims>>>>,
+    keys_loaded: Arc<AtomicBool>,
+}
+
+impl OidcStatus {
+    pub(crate) fn keys_loaded(&self) -> bool {
+        self.keys_loaded.load(Ordering::Relaxed)
+    }
+
+    pub(crate) fn authorizer(&self) -> Option<Arc<Authorizer<Claims>>> {
+        self.authorizer.get().cloned()
+    }
+}
+
+async fn build_authorizer(issuer: &str, client: &reqwest::Client) -> Result<Authorizer<Claims>> {
+    let validation = Validation::new()
+        .iss(&[issuer])
+        .aud(&["homecontrol"])
+        .leeway(5);
+    JwtAuthorizer::from_oidc(issuer)
+        .validation(validation)
+        .http_client(client.clone())
+        .build()
+        .await
+        .wrap_err("JWT authorization initialization failed")
+}
+
+/// Whether the key store of the authorizer has a key, it loads the keys of
+/// the issuer when it has none yet
+async fn has_keys(authorizer: &Authorizer<Claims>) -> bool {
+    for alg in KEY_ALGORITHMS {
+        let key = authorizer.key_source.get_key(Header::new(alg));
+        match tokio::time::timeout(REQUEST_TIMEOUT, key).await {
+            Ok(Ok(_)) => return true,
+            Ok(Err(e)) => debug!(?alg, "No OIDC key: {:?}", e),
+            Err(_) => {
+                warn!("Loading the OIDC keys timed out");
+                return false;
+            }
+        }
+    }
+    false
+}
+
+/// Build the authorizer for the issuer from `HCS_JWT_ISSUER` in the
+/// background, retrying until discovery succeeds, and check its keys.
+pub(crate) fn run_oidc() -> Result<(OidcStatus, JoinHandle<()>)> {
+    let issuer = env::var("HCS_JWT_ISSUER").wrap_err("Missing HCS_JWT_ISSUER variable")?;
+    let client = reqwest::Client::builder()
+        .timeout(REQUEST_TIMEOUT)
+        .build()
+        .wrap_err("Cannot create HTTP client")?;
+    let status = OidcStatus::default();
+    let task_status = status.clone();
+    let task = tokio::spawn(async move {
+        let authorizer = loop {
+            match build_authorizer(&issuer, &client).await {
+                Ok(authorizer) => break Arc::new(authorizer),
+                Err(e) => {
+                    warn!("OIDC discovery failed: {:?}", e);
+                    tokio::time::sleep(RETRY_INTERVAL).await;
+                }
+            }
+        };
+        info!(issuer, "OIDC discovery completed");
+        let _ = task_status.authorizer.set(authorizer.clone());
+        let mut interval = tokio::time::interval(CHECK_INTERVAL);
+        loop {
+            interval.tick().await;
+            let loaded = has_keys(&authorizer).await;
+            if loaded {
+                debug!("OIDC keys loaded");
+            } else {
+                warn!("Cannot load OIDC keys");
+            }
+            task_status.keys_loaded.store(loaded, Ordering::Relaxed);
+        }
+    });
+    Ok((status, task))
+}
@@ -0,0 +1,484 @@
+use std::{
+    collections::HashMap,
+    env,
+    net::IpAddr,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use axum::{
+    extract::{Request, State},
+    http::{header, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use color_eyre::eyre::{eyre, Context, Result};
+use jwt_authorizer::JwtClaims;
+use serde::Deserialize;
+use tracing::{debug, info};
+
+use crate::{
+    http::{claims::Claims, client::ClientInfo},
+    metrics::Metrics,
+};
+
+/// Most buckets kept, full ones behave like new ones and are dropped first,
+/// then the least recently used
+const MAX_BUCKETS: usize = 10_000;
+/// Buckets left after making room, so the next cleanup is far away
+const KEEP_BUCKETS: usize = MAX_BUCKETS * 9 / 10;
+
+/// Requests per second with bursts up to `burst`
+#[derive(Clone, Copy, Debug, Deserialize)]
+struct Limit {
+    rate: f64,
+    burst: f64,
+}
+
+impl Limit {
+    fn validate(&self, name: &str) -> Result<()> {
+        if !(self.rate > 0.0 && self.burst >= 1.0) {
+            return Err(eyre!(
+                "Limit {name} needs a rate above 0 and a burst of at least 1"
+            ));
+        }
+        Ok(())
+    }
+}
+
+#[derive(Clone, Debug, Deserialize)]
+struct TopicLimit {
+    filter: String,
+    #[serde(flatten)]
+    limit: Limit,
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct RateLimitConfig {
+    user: Option<Limit>,
+    ip: Option<Limit>,
+    #[serde(default)]
+    topics: Vec<TopicLimit>,
+}
+
+struct Bucket {
+    tokens: f64,
+    /// Last refill, also the last use
+    updated: Instant,
+    limit: Limit,
+}
+
+impl Bucket {
+    fn new(limit: Limit, now: Instant) -> Self {
+        Self {
+            tokens: limit.burst,
+            updated: now,
+            limit,
+        }
+    }
+
+    /// Tokens at `now`, without counting as a use
+    fn tokens_at(&self, now: Instant) -> f64 {
+        let elapsed = now.duration_since(self.updated).as_secs_f64();
+        (self.tokens + elapsed * self.limit.rate).min(self.limit.burst)
+    }
+
+    /// Tokens available now
+    fn refill(&mut self, now: Instant) -> f64 {
+        self.tokens = self.tokens_at(now);
+        self.updated = now;
+        self.tokens
+    }
+}
+
+/// Token buckets per user, client address and topic filter from
+/// `HCS_RATE_LIMITS_CONFIG`. A request passes if every bucket it falls into
+/// has a token left.
+#[derive(Clone)]
+pub(crate) struct RateLimits {
+    config: Arc<RateLimitConfig>,
+    buckets: Arc<Mutex<HashMap<String, Bucket>>>,
+    metrics: Metrics,
+}
+
+impl RateLimits {
+    /// Read the limits from the file in `HCS_RATE_LIMITS_CONFIG`, not set
+    /// means no limits.
+    pub(crate) fn from_env(metrics: Metrics) -> Result<Self> {
+        let config = match env::var("HCS_RATE_LIMITS_CONFIG") {
+            Ok(path) if !path.is_empty() => {
+                let text = std::fs::read_to_string(&path)
+                    .wrap_err_with(|| format!("Cannot read rate limits {path}"))?;
+                let config: RateLimitConfig = serde_json::from_str(&text)
+                    .wrap_err_with(|| format!("Cannot parse rate limits {path}"))?;
+                if let Some(limit) = &config.user {
+                    limit.validate("user")?;
+                }
+                if let Some(limit) = &config.ip {
+                    limit.validate("ip")?;
+                }
+                for topic in &config.topics {
+                    if !rumqttc::valid_filter(&topic.filter) {
+                        return Err(eyre!("Invalid topic filter {}", topic.filter));
+                    }
+                    topic.limit.validate(&topic.filter)?;
+                }
+                info!(topics = config.topics.len(), "Rate limits loaded");
+                config
+            }
+            _ => RateLimitConfig::default(),
+        };
+        Ok(Self {
+            config: Arc::new(config),
+            buckets: Arc::default(),
+            metrics,
+        })
+    }
+
+    /// Take a token from the buckets of `user`, `ip` and the filters
+    /// matching `topic`. Returns the time until a retry can pass if one of
+    /// them is empty, nothing is taken then.
+    pub(crate) fn check(
+        &self,
+        user: Option<&str>,
+        ip: IpAddr,
+        topic: Option<&str>,
+    ) -> Result<(), RateLimited> {
+        let mut applicable: Vec<(&'static str, String, Limit)> = Vec::new();
+        if let (Some(limit), Some(user)) = (self.config.user, user) {
+            applicable.push(("user", format!("user:{user}"), limit));
+        }
+        if let Some(limit) = self.config.ip {
+            applicable.push(("ip", format!("ip:{ip}"), limit));
+        }
+        self.topic_limits(topic.as_slice(), &mut applicable);
+        if applicable.is_empty() {
+            return Ok(());
+        }
+
+        self.take(&applicable, Instant::now())
+            .map_err(|(wait, scope)| {
+                // not a warning, a runaway client ends up here many times a second
+                debug!(scope, %ip, user, topic, "Rate limit exceeded");
+                self.metrics.rate_limited(scope);
+                RateLimited(wait)
+            })
+    }
+
+    /// Take a token from the buckets of the filters matching any of `topics`,
+    /// for commands that resolve to topics after [`limit_requests`] checked
+    /// the user and address. Each filter counts once per command.
+    pub(crate) fn check_topics(&self, topics: &[&str]) -> Result<(), RateLimited> {
+        let mut applicable = Vec::new();
+        self.topic_limits(topics, &mut applicable);
+        if applicable.is_empty() {
+            return Ok(());
+        }
+
+        self.take(&applicable, Instant::now())
+            .map_err(|(wait, scope)| {
+                debug!(scope, ?topics, "Rate limit exceeded");
+                self.metrics.rate_limited(scope);
+                RateLimited(wait)
+            })
+    }
+
+    fn topic_limits(&self, topics: &[&str], applicable: &mut Vec<(&'static str, String, Limit)>) {
+        for t in &self.config.topics {
+            if topics
+                .iter()
+                .any(|topic| rumqttc::matches(topic, &t.filter))
+            {
+                applicable.push(("topic", format!("topic:{}", t.filter), t.limit));
+            }
+        }
+    }
+
+    fn take(
+        &self,
+        applicable: &[(&'static str, String, Limit)],
+        now: Instant,
+    ) -> Result<(), (Duration, &'static str)> {
+        let mut buckets = self.buckets.lock().unwrap_or_else(|e| e.into_inner());
+        if applicable
+            .iter()
+            .any(|(_, key, _)| !buckets.contains_key(key))
+        {
+            make_room(&mut buckets, now);
+        }
+        let mut retry_after: Option<(Duration, &'static str)> = None;
+        for (scope, key, limit) in applicable {
+            let bucket = buckets
+                .entry(key.clone())
+                .or_insert_with(|| Bucket::new(*limit, now));
+            let tokens = bucket.refill(now);
+            if tokens < 1.0 {
+                let wait = Duration::from_secs_f64((1.0 - tokens) / limit.rate);
+                if retry_after.is_none_or(|(longest, _)| wait > longest) {
+                    retry_after = Some((wait, scope));
+                }
+            }
+        }
+        if let Some(retry_after) = retry_after {
+            return Err(retry_after);
+        }
+        for (_, key, _) in applicable {
+            if let Some(bucket) = buckets.get_mut(key) {
+                bucket.tokens -= 1.0;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Drop buckets once there are [`MAX_BUCKETS`], full ones first and then the
+/// least recently used down to [`KEEP_BUCKETS`]. Cleaning up only when the
+/// cap is reached keeps the cost per request constant on average.
+fn make_room(buckets: &mut HashMap<String, Bucket>, now: Instant) {
+    if buckets.len() < MAX_BUCKETS {
+        return;
+    }
+    buckets.retain(|_, bucket| bucket.tokens_at(now) < bucket.limit.burst);
+    if buckets.len() > KEEP_BUCKETS {
+        let mut used: Vec<(Instant, String)> = buckets
+            .iter()
+            .map(|(key, bucket)| (bucket.updated, key.clone()))
+            .collect();
+        let evict = buckets.len() - KEEP_BUCKETS;
+        used.select_nth_unstable(evict - 1);
+        for (_, key) in &used[..evict] {
+            buckets.remove(key);
+        }
+    }
+}
+
+/// Middleware for handlers that publish or arrange publishes. Applies the
+/// user and address limits, topic limits need the topic and are checked by
+/// the handlers that know it before acting.
+pub(crate) async fn limit_requests(
+    State(ratelimits): State<RateLimits>,
+    client: ClientInfo,
+    JwtClaims(user): JwtClaims<Claims>,
+    request: Request,
+    next: Next,
+) -> Result<Response, RateLimited> {
+    ratelimits.check(user.sub(), client.ip, None)?;
+    Ok(next.run(request).await)
+}
+
+/// Rejection of a request over its limit
+pub(crate) struct RateLimited(pub(crate) Duration);
+
+impl RateLimited {
+    /// Whole seconds until a retry can pass, as sent in `Retry-After`
+    pub(crate) fn retry_after(&self) -> u64 {
+        self.0.as_secs_f64().ceil().max(1.0) as u64
+    }
+}
+
+impl IntoResponse for RateLimited {
+    fn into_response(self) -> Response {
+        (
+            StatusCode::TOO_MANY_REQUESTS,
+            [(header::RETRY_AFTER, self.retry_after().to_string())],
+            "Rate limit exceeded",
+        )
+            .into_response()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::Ipv4Addr;
+
+    use super::*;
+
+    const IP: IpAddr = IpAddr::V4(Ipv4Addr::new(192, 0, 2, 1));
+
+    fn limit(rate: f64, burst: f64) -> Limit {
+        Limit { rate, burst }
+    }
+
+    fn limits(config: RateLimitConfig) -> RateLimits {
+        RateLimits {
+            config: Arc::new(config),
+            buckets: Arc::default(),
+            metrics: Metrics::from_env().unwrap(),
+        }
+    }
+
+    fn tokens(limits: &RateLimits, key: &str) -> f64 {
+        limits.buckets.lock().unwrap()[key].tokens
+    }
+
+    #[test]
+    fn refill_adds_rate_per_second_up_to_burst() {
+        let now = Instant::now();
+        let mut bucket = Bucket::new(limit(2.0, 4.0), now);
+        assert_eq!(bucket.refill(now), 4.0);
+        bucket.tokens = 0.0;
+        assert_eq!(bucket.refill(now + Duration::from_millis(500)), 1.0);
+        assert_eq!(bucket.refill(now + Duration::from_millis(1500)), 3.0);
+        assert_eq!(bucket.refill(now + Duration::from_secs(60)), 4.0);
+    }
+
+    #[test]
+    fn tokens_at_does_not_count_as_use() {
+        let now = Instant::now();
+        let mut bucket = Bucket::new(limit(1.0, 2.0), now);
+        bucket.tokens = 0.0;
+        assert_eq!(bucket.tokens_at(now + Duration::from_secs(1)), 1.0);
+        assert_eq!(bucket.updated, now);
+        assert_eq!(bucket.tokens, 0.0);
+    }
+
+    #[test]
+    fn no_limits_configured_passes() {
+        let limits = limits(RateLimitConfig::default());
+        for _ in 0..100 {
+            assert!(limits.check(Some("alice"), IP, Some("a/b")).is_ok());
+        }
+        assert!(limits.buckets.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn burst_then_rejected() {
+        let limits = limits(RateLimitConfig {
+            user: Some(limit(0.001, 3.0)),
+            ..Default::default()
+        });
+        for _ in 0..3 {
+            assert!(limits.check(Some("alice"), IP, None).is_ok());
+        }
+        assert!(limits.check(Some("alice"), IP, None).is_err());
+        // other users have their own bucket
+        assert!(limits.check(Some("bob"), IP, None).is_ok());
+        // requests without a user only fall under the other limits
+        assert!(limits.check(None, IP, None).is_ok());
+    }
+
+    #[test]
+    fn tokens_taken_only_if_every_bucket_passes() {
+        let limits = limits(RateLimitConfig {
+            user: Some(limit(0.001, 1.0)),
+            ip: Some(limit(0.001, 5.0)),
+            topics: vec![TopicLimit {
+                filter: "cmnd/#".to_string(),
+                limit: limit(0.001, 5.0),
+            }],
+        });
+        assert!(limits.check(Some("alice"), IP, Some("cmnd/a")).is_ok());
+        assert!((tokens(&limits, "ip:192.0.2.1") - 4.0).abs() < 0.01);
+        assert!((tokens(&limits, "topic:cmnd/#") - 4.0).abs() < 0.01);
+
+        // the user bucket is empty, the others keep their tokens
+        assert!(limits.check(Some("alice"), IP, Some("cmnd/a")).is_err());
+        assert!((tokens(&limits, "ip:192.0.2.1") - 4.0).abs() < 0.01);
+        assert!((tokens(&limits, "topic:cmnd/#") - 4.0).abs() < 0.01);
+    }
+
+    #[test]
+    fn topic_limit_applies_to_matching_topics_only() {
+        let limits = limits(RateLimitConfig {
+            topics: vec![TopicLimit {
+                filter: "cmnd/+/POWER".to_string(),
+                limit: limit(0.001, 1.0),
+            }],
+            ..Default::default()
+        });
+        assert!(limits
+            .check(Some("alice"), IP, Some("cmnd/a/POWER"))
+            .is_ok());
+        // shared by everyone sending to the filter
+        assert!(limits.check(Some("bob"), IP, Some("cmnd/b/POWER")).is_err());
+        assert!(limits.check(Some("bob"), IP, Some("cmnd/b/Dimmer")).is_ok());
+        assert!(limits.check(Some("bob"), IP, None).is_ok());
+    }
+
+    #[test]
+    fn command_topics_count_once_per_filter() {
+        let limits = limits(RateLimitConfig {
+            user: Some(limit(0.001, 1.0)),
+            topics: vec![TopicLimit {
+                filter: "cmnd/#".to_string(),
+                limit: limit(0.001, 2.0),
+            }],
+            ..Default::default()
+        });
+        assert!(limits
+            .check_topics(&["cmnd/a/POWER", "cmnd/b/POWER"])
+            .is_ok());
+        assert!((tokens(&limits, "topic:cmnd/#") - 1.0).abs() < 0.01);
+        assert!(limits.check_topics(&["stat/a/POWER"]).is_ok());
+        assert!(limits.check_topics(&["cmnd/c/POWER"]).is_ok());
+        assert!(limits.check_topics(&["cmnd/a/POWER"]).is_err());
+        // the user bucket is left to the middleware
+        assert!(!limits.buckets.lock().unwrap().contains_key("user:alice"));
+        assert!(limits.check(Some("alice"), IP, None).is_ok());
+    }
+
+    #[test]
+    fn retry_after_is_longest_wait() {
+        let limits = limits(RateLimitConfig {
+            user: Some(limit(1.0, 1.0)),
+            ip: Some(limit(0.1, 1.0)),
+            ..Default::default()
+        });
+        assert!(limits.check(Some("alice"), IP, None).is_ok());
+        let Err(RateLimited(wait)) = limits.check(Some("alice"), IP, None) else {
+            panic!("not limited");
+        };
+        assert!(wait > Duration::from_secs(9) && wait <= Duration::from_secs(10));
+    }
+
+    #[test]
+    fn retry_after_rounds_up_to_whole_seconds() {
+        assert_eq!(RateLimited(Duration::ZERO).retry_after(), 1);
+        assert_eq!(RateLimited(Duration::from_millis(300)).retry_after(), 1);
+        assert_eq!(RateLimited(Duration::from_secs(1)).retry_after(), 1);
+        assert_eq!(RateLimited(Duration::from_millis(1001)).retry_after(), 2);
+        assert_eq!(RateLimited(Duration::from_millis(9500)).retry_after(), 10);
+    }
+
+    #[test]
+    fn make_room_drops_full_then_least_recently_used() {
+        let start = Instant::now();
+        let mut buckets = HashMap::new();
+        for i in 0..MAX_BUCKETS {
+            let now = start + Duration::from_millis(i as u64);
+            let mut bucket = Bucket::new(limit(0.001, 2.0), now);
+            // every twentieth bucket is full, the others are in use
+            if i % 20 != 0 {
+                bucket.tokens = 0.0;
+            }
+            buckets.insert(format!("ip:{i}"), bucket);
+        }
+        let now = start + Duration::from_millis(MAX_BUCKETS as u64);
+        make_room(&mut buckets, now);
+        assert_eq!(buckets.len(), KEEP_BUCKETS);
+        // 500 full ones and then the 500 oldest in use
+        assert!(!buckets.contains_key("ip:9980"));
+        assert!(!buckets.contains_key("ip:1"));
+        assert!(!buckets.contains_key("ip:500"));
+        assert!(buckets.contains_key("ip:601"));
+        assert!(buckets.contains_key(&format!("ip:{}", MAX_BUCKETS - 1)));
+        assert!(buckets.values().all(|b| b.tokens == 0.0));
+
+        // below the cap nothing is dropped
+        make_room(&mut buckets, now);
+        assert_eq!(buckets.len(), KEEP_BUCKETS);
+    }
+
+    #[test]
+    fn bucket_count_stays_bounded() {
+        let limits = limits(RateLimitConfig {
+            ip: Some(limit(0.001, 1.0)),
+            ..Default::default()
+        });
+        for i in 0..(MAX_BUCKETS as u32 + 500) {
+            let ip = IpAddr::V4(Ipv4Addr::from(i));
+            assert!(limits.check(None, ip, None).is_ok());
+        }
+        assert!(limits.buckets.lock().unwrap().len() <= MAX_BUCKETS);
+    }
+}
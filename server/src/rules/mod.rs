@@ -0,0 +1,329 @@
+mod rule;
+
+use std::{
+    collections::{BTreeMap, HashMap, VecDeque},
+    env,
+    sync::Arc,
+    time::Duration,
+};
+
+use color_eyre::eyre::{eyre, Context, Result};
+use rand::distributions::{Alphanumeric, DistString};
+use serde::Serialize;
+use tokio::{
+    sync::{oneshot, watch, Mutex, RwLock},
+    task::JoinHandle,
+    time::Instant,
+};
+use tracing::{debug, info, warn};
+
+use rule::{Operator, RuleSource};
+pub(crate) use rule::{Rule, RuleDefinition};
+
+use crate::{
+    datadir::{load_json, now_millis, save_json},
+    mqtta::{message::ActorMessage, MqttHandle},
+    scenes::Scenes,
+};
+
+const RULES_FILE: &str = "rules.json";
+/// Number of fire log entries kept in memory
+const LOG_SIZE: usize = 1000;
+
+#[derive(Debug)]
+pub(crate) enum RuleError {
+    NotFound,
+    /// Rules from the configuration file cannot be changed through the API
+    ReadOnly,
+    Invalid(String),
+}
+
+/// One entry of the fire log
+#[derive(Clone, Debug, Serialize)]
+pub(crate) struct FireRecord {
+    ts: u64,
+    rule: String,
+    name: Option<String>,
+    value: String,
+    dry_run: bool,
+    result: String,
+}
+
+/// Rules from the configuration and the ones created through the API, each
+/// running in its own task
+#[derive(Clone)]
+pub(crate) struct RuleEngine {
+    mqtt: MqttHandle,
+    scenes: Scenes,
+    rules: Arc<RwLock<BTreeMap<String, Rule>>>,
+    tasks: Arc<Mutex<HashMap<String, JoinHandle<()>>>>,
+    log: Arc<RwLock<VecDeque<FireRecord>>>,
+}
+
+/// Load the rules from the file named by `HCS_RULES_CONFIG`.
+fn config_rules() -> Result<Vec<Rule>> {
+    let Ok(path) = env::var("HCS_RULES_CONFIG") else {
+        return Ok(Vec::new());
+    };
+    if path.is_empty() {
+        return Ok(Vec::new());
+    }
+    let content = std::fs::read_to_string(&path)
+        .wrap_err_with(|| format!("Cannot read rule configuration {path}"))?;
+    serde_json::from_str::<Vec<Rule>>(&content)
+        .wrap_err_with(|| format!("Invalid rule configuration {path}"))
+}
+
+/// Start the rules from the configuration and the data directory.
+pub(crate) async fn run_rules(mqtt: MqttHandle, scenes: Scenes) -> Result<RuleEngine> {
+    let mut rules = BTreeMap::new();
+    for mut rule in config_rules()? {
+        rule.source = RuleSource::Config;
+        rule.definition
+            .validate()
+            .and_then(|_| rule.definition.action.validate(&scenes))
+            .map_err(|e| eyre!("Invalid rule {}: {e}", rule.id))?;
+        if rules.contains_key(&rule.id) {
+            return Err(eyre!("Duplicate rule id {}", rule.id));
+        }
+        rules.insert(rule.id.clone(), rule);
+    }
+    let stored: Vec<Rule> = load_json(RULES_FILE)?;
+    for mut rule in stored {
+        rule.source = RuleSource::Api;
+        if rules.contains_key(&rule.id) {
+            warn!(id = rule.id, "Stored rule shadowed by configuration");
+            continue;
+        }
+        rules.insert(rule.id.clone(), rule);
+    }
+    info!(rules = rules.len(), "Rules loaded");
+
+    let engine = RuleEngine {
+        mqtt,
+        scenes,
+        rules: Arc::new(RwLock::new(rules)),
+        tasks: Default::default(),
+        log: Default::default(),
+    };
+    let rules: Vec<Rule> = engine.rules.read().await.values().cloned().collect();
+    for rule in rules {
+        engine.start(rule).await;
+    }
+    Ok(engine)
+}
+
+impl RuleEngine {
+    pub(crate) async fn list(&self) -> Vec<Rule> {
+        self.rules.read().await.values().cloned().collect()
+    }
+
+    pub(crate) async fn get(&self, id: &str) -> Option<Rule> {
+        self.rules.read().await.get(id).cloned()
+    }
+
+    /// Fire log, newest first, optionally limited to one rule
+    pub(crate) async fn log(&self, rule: Option<&str>, limit: usize) -> Vec<FireRecord> {
+        self.log
+            .read()
+            .await
+            .iter()
+            .rev()
+            .filter(|r| rule.is_none_or(|id| r.rule == id))
+            .take(limit)
+            .cloned()
+            .collect()
+    }
+
+    fn validate(&self, definition: &RuleDefinition) -> Result<(), RuleError> {
+        definition
+            .validate()
+            .and_then(|_| definition.action.validate(&self.scenes))
+            .map_err(RuleError::Invalid)
+    }
+
+    async fn save(&self, rules: &BTreeMap<String, Rule>) {
+        let list: Vec<&Rule> = rules
+            .values()
+            .filter(|r| matches!(r.source, RuleSource::Api))
+            .collect();
+        if let Err(e) = save_json(RULES_FILE, &list) {
+            warn!("Cannot save rules: {:?}", e);
+        }
+    }
+
+    pub(crate) async fn create(&self, definition: RuleDefinition) -> Result<Rule, RuleError> {
+        self.validate(&definition)?;
+        let rule = Rule {
+            id: Alphanumeric.sample_string(&mut rand::thread_rng(), 8),
+            source: RuleSource::Api,
+            definition,
+        };
+        {
+            let mut rules = self.rules.write().await;
+            rules.insert(rule.id.clone(), rule.clone());
+            self.save(&rules).await;
+        }
+        self.start(rule.clone()).await;
+        Ok(rule)
+    }
+
+    pub(crate) async fn update(
+        &self,
+        id: &str,
+        definition: RuleDefinition,
+    ) -> Result<Rule, RuleError> {
+        self.validate(&definition)?;
+        let rule = {
+            let mut rules = self.rules.write().await;
+            let rule = rules.get_mut(id).ok_or(RuleError::NotFound)?;
+            if matches!(rule.source, RuleSource::Config) {
+                return Err(RuleError::ReadOnly);
+            }
+            rule.definition = definition;
+            let rule = rule.clone();
+            self.save(&rules).await;
+            rule
+        };
+        self.start(rule.clone()).await;
+        Ok(rule)
+    }
+
+    pub(crate) async fn delete(&self, id: &str) -> Result<(), RuleError> {
+        {
+            let mut rules = self.rules.write().await;
+            let rule = rules.get(id).ok_or(RuleError::NotFound)?;
+            if matches!(rule.source, RuleSource::Config) {
+                return Err(RuleError::ReadOnly);
+            }
+            rules.remove(id);
+            self.save(&rules).await;
+        }
+        if let Some(task) = self.tasks.lock().await.remove(id) {
+            task.abort();
+        }
+        Ok(())
+    }
+
+    /// (Re)start the task of a rule, disabled rules only stop it.
+    async fn start(&self, rule: Rule) {
+        let mut tasks = self.tasks.lock().await;
+        if let Some(task) = tasks.remove(&rule.id) {
+            task.abort();
+        }
+        if !rule.definition.enabled {
+            return;
+        }
+        tasks.insert(rule.id.clone(), tokio::spawn(run_rule(self.clone(), rule)));
+    }
+
+    async fn fire(&self, rule: &Rule, value: String, last_fire: &mut Option<Instant>) {
+        let definition = &rule.definition;
+        if !definition.in_window() {
+            debug!(id = rule.id, "Rule outside of its time window");
+            return;
+        }
+        let debounce = Duration::from_secs(definition.debounce_seconds);
+        if last_fire.is_some_and(|t| t.elapsed() < debounce) {
+            debug!(id = rule.id, "Rule debounced");
+            return;
+        }
+        *last_fire = Some(Instant::now());
+        let result = if definition.dry_run {
+            String::from("Dry run")
+        } else {
+            definition.action.run(&self.mqtt, &self.scenes).await
+        };
+        info!(
+            id = rule.id,
+            dry_run = definition.dry_run,
+            result,
+            "Rule fired"
+        );
+        let mut log = self.log.write().await;
+        if log.len() >= LOG_SIZE {
+            log.pop_front();
+        }
+        log.push_back(FireRecord {
+            ts: now_millis(),
+            rule: rule.id.clone(),
+            name: definition.name.clone(),
+            value,
+            dry_run: definition.dry_run,
+            result,
+        });
+    }
+}
+
+/// Evaluate a rule on every update of its topic. The first value after the
+/// start only sets the baseline, the rule fires when the condition becomes
+/// true and, with `for_seconds`, stayed true that long.
+async fn run_rule(engine: RuleEngine, rule: Rule) {
+    // declared before the receiver so it is dropped after it, also when the
+    // task is aborted
+    let _release = engine
+        .mqtt
+        .release_guard(vec![rule.definition.topic.clone()]);
+    let (tx, rx) = oneshot::channel::<watch::Receiver<Arc<String>>>();
+    engine
+        .mqtt
+        .send(ActorMessage::Subscribe {
+            topic: rule.definition.topic.clone(),
+            respond_to: tx,
+        })
+        .await;
+    let Ok(mut w) = rx.await else {
+        warn!(id = rule.id, "Could not subscribe rule topic");
+        return;
+    };
+    debug!(id = rule.id, "Rule started");
+    let definition = &rule.definition;
+    let hold = Duration::from_secs(definition.for_seconds);
+    let mut matched: Option<bool> = None;
+    let mut pending: Option<(Instant, String)> = None;
+    let mut last_fire: Option<Instant> = None;
+    loop {
+        let deadline = pending.as_ref().map(|(at, _)| *at);
+        tokio::select! {
+            changed = w.changed() => {
+                if changed.is_err() {
+                    break;
+                }
+                let update = w.borrow_and_update().clone();
+                let Ok(update) = serde_json::from_str::<serde_json::Value>(&update) else {
+                    continue;
+                };
+                let Some(data) = update.get("data").and_then(|d| d.as_str()) else {
+                    continue;
+                };
+                // a path refers to the payload as received, not to a transformed value
+                let has_path = definition.condition.path.as_ref().is_some_and(|p| !p.is_empty());
+                let input = match update.get("raw").and_then(|r| r.as_str()) {
+                    Some(raw) if has_path => raw,
+                    _ => data,
+                };
+                let now = definition.condition.matches(input);
+                let previous = matched.replace(now);
+                let edge = match definition.condition.operator {
+                    Operator::Changed => previous.is_some(),
+                    _ => previous == Some(false) && now,
+                };
+                if !now {
+                    pending = None;
+                } else if edge && hold.is_zero() {
+                    engine.fire(&rule, data.to_string(), &mut last_fire).await;
+                } else if edge {
+                    pending = Some((Instant::now() + hold, data.to_string()));
+                } else if let Some((_, value)) = pending.as_mut() {
+                    *value = data.to_string();
+                }
+            }
+            _ = tokio::time::sleep_until(deadline.unwrap_or_else(Instant::now)), if deadline.is_some() => {
+                if let Some((_, value)) = pending.take() {
+                    engine.fire(&rule, value, &mut last_fire).await;
+                }
+            }
+        }
+    }
+    debug!(id = rule.id, "Rule stopped");
+}
@@ -0,0 +1,238 @@
+use chrono::{Local, NaiveTime};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    actions::Action,
+    jsonpath::{extract_number, lookup},
+};
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(tag = "op", rename_all = "snake_case")]
+pub(crate) enum Operator {
+    Eq {
+        value: String,
+    },
+    Ne {
+        value: String,
+    },
+    Gt {
+        value: f64,
+    },
+    Gte {
+        value: f64,
+    },
+    Lt {
+        value: f64,
+    },
+    Lte {
+        value: f64,
+    },
+    Contains {
+        value: String,
+    },
+    /// Every update matches
+    Changed,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub(crate) struct Condition {
+    /// Path into a JSON payload, e.g. `$.temperature`
+    pub(crate) path: Option<String>,
+    #[serde(flatten)]
+    pub(crate) operator: Operator,
+}
+
+/// Time of day the rule is active in, wraps around midnight if `from` is
+/// after `to`
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub(crate) struct TimeWindow {
+    pub(crate) from: String,
+    pub(crate) to: String,
+}
+
+fn default_enabled() -> bool {
+    true
+}
+
+/// A rule as accepted by the API, without id
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub(crate) struct RuleDefinition {
+    pub(crate) name: Option<String>,
+    #[serde(default = "default_enabled")]
+    pub(crate) enabled: bool,
+    /// Only log what the rule would do
+    #[serde(default)]
+    pub(crate) dry_run: bool,
+    pub(crate) topic: String,
+    pub(crate) condition: Condition,
+    /// The condition has to hold this long before the rule fires
+    #[serde(default)]
+    pub(crate) for_seconds: u64,
+    /// Minimum time between two fires
+    #[serde(default)]
+    pub(crate) debounce_seconds: u64,
+    pub(crate) window: Option<TimeWindow>,
+    pub(crate) action: Action,
+}
+
+#[derive(Clone, Copy, Debug, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum RuleSource {
+    Config,
+    Api,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub(crate) struct Rule {
+    pub(crate) id: String,
+    #[serde(default = "default_source")]
+    pub(crate) source: RuleSource,
+    #[serde(flatten)]
+    pub(crate) definition: RuleDefinition,
+}
+
+fn default_source() -> RuleSource {
+    RuleSource::Config
+}
+
+fn parse_time(time: &str) -> Result<NaiveTime, String> {
+    NaiveTime::parse_from_str(time, "%H:%M")
+        .or_else(|_| NaiveTime::parse_from_str(time, "%H:%M:%S"))
+        .map_err(|_| format!("Invalid time {time}, expected HH:MM"))
+}
+
+impl TimeWindow {
+    fn contains(&self, now: NaiveTime) -> bool {
+        let (Ok(from), Ok(to)) = (parse_time(&self.from), parse_time(&self.to)) else {
+            return false;
+        };
+        if from <= to {
+            from <= now && now < to
+        } else {
+            now >= from || now < to
+        }
+    }
+}
+
+impl Condition {
+    /// Whether an update payload satisfies the condition
+    pub(crate) fn matches(&self, payload: &str) -> bool {
+        let path = self.path.as_deref().filter(|p| !p.is_empty());
+        let text = || -> Option<String> {
+            match path {
+                None => Some(payload.to_string()),
+                Some(path) => {
+                    let parsed = serde_json::from_str::<serde_json::Value>(payload).ok()?;
+                    Some(match lookup(&parsed, path)? {
+                        serde_json::Value::String(s) => s.clone(),
+                        v => v.to_string(),
+                    })
+                }
+            }
+        };
+        let number = || extract_number(payload, path);
+        match &self.operator {
+            Operator::Eq { value } => text().is_some_and(|t| t == *value),
+            Operator::Ne { value } => text().is_some_and(|t| t != *value),
+            Operator::Contains { value } => text().is_some_and(|t| t.contains(value.as_str())),
+            Operator::Gt { value } => number().is_some_and(|n| n > *value),
+            Operator::Gte { value } => number().is_some_and(|n| n >= *value),
+            Operator::Lt { value } => number().is_some_and(|n| n < *value),
+            Operator::Lte { value } => number().is_some_and(|n| n <= *value),
+            Operator::Changed => true,
+        }
+    }
+}
+
+impl RuleDefinition {
+    pub(crate) fn validate(&self) -> Result<(), String> {
+        if self.topic.is_empty() || !rumqttc::valid_topic(&self.topic) {
+            return Err(format!("Invalid topic {}", self.topic));
+        }
+        if let Some(window) = &self.window {
+            parse_time(&window.from)?;
+            parse_time(&window.to)?;
+        }
+        Ok(())
+    }
+
+    pub(crate) fn in_window(&self) -> bool {
+        self.window
+            .as_ref()
+            .is_none_or(|w| w.contains(Local::now().time()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn condition(path: Option<&str>, operator: Operator) -> Condition {
+        Condition {
+            path: path.map(str::to_string),
+            operator,
+        }
+    }
+
+    fn window(from: &str, to: &str) -> TimeWindow {
+        TimeWindow {
+            from: from.to_string(),
+            to: to.to_string(),
+        }
+    }
+
+    fn time(h: u32, m: u32) -> NaiveTime {
+        NaiveTime::from_hms_opt(h, m, 0).unwrap()
+    }
+
+    #[test]
+    fn text_conditions() {
+        let on = || "ON".to_string();
+        assert!(condition(None, Operator::Eq { value: on() }).matches("ON"));
+        assert!(!condition(None, Operator::Eq { value: on() }).matches("OFF"));
+        assert!(condition(None, Operator::Ne { value: on() }).matches("OFF"));
+        let path = Some("$.state");
+        assert!(condition(path, Operator::Eq { value: on() }).matches(r#"{"state":"ON"}"#));
+        // non string values compare with their JSON text
+        let value = "true".to_string();
+        assert!(condition(path, Operator::Eq { value }).matches(r#"{"state":true}"#));
+        // a missing path or invalid JSON matches neither eq nor ne
+        assert!(!condition(path, Operator::Ne { value: on() }).matches(r#"{"other":1}"#));
+        assert!(!condition(path, Operator::Ne { value: on() }).matches("ON"));
+        let value = "arm".to_string();
+        assert!(condition(None, Operator::Contains { value }).matches("disarmed"));
+        assert!(condition(None, Operator::Changed).matches(""));
+    }
+
+    #[test]
+    fn numeric_conditions() {
+        let payload = r#"{"temperature":21.5}"#;
+        let path = Some("temperature");
+        assert!(condition(path, Operator::Gt { value: 21.0 }).matches(payload));
+        assert!(!condition(path, Operator::Gt { value: 21.5 }).matches(payload));
+        assert!(condition(path, Operator::Gte { value: 21.5 }).matches(payload));
+        assert!(condition(path, Operator::Lt { value: 22.0 }).matches(payload));
+        assert!(condition(path, Operator::Lte { value: 21.5 }).matches(payload));
+        assert!(!condition(path, Operator::Lte { value: 21.0 }).matches(payload));
+        assert!(condition(None, Operator::Gt { value: 0.5 }).matches("on"));
+        assert!(!condition(None, Operator::Lt { value: 100.0 }).matches("open"));
+        assert!(condition(Some(""), Operator::Lt { value: 10.0 }).matches("5"));
+    }
+
+    #[test]
+    fn window_contains() {
+        let day = window("08:00", "20:00");
+        assert!(day.contains(time(8, 0)));
+        assert!(day.contains(time(19, 59)));
+        assert!(!day.contains(time(20, 0)));
+        assert!(!day.contains(time(7, 59)));
+        let night = window("22:00", "06:30:00");
+        assert!(night.contains(time(23, 0)));
+        assert!(night.contains(time(0, 0)));
+        assert!(night.contains(time(6, 29)));
+        assert!(!night.contains(time(6, 30)));
+        assert!(!night.contains(time(12, 0)));
+        assert!(!window("08:00", "08:00").contains(time(8, 0)));
+        assert!(!window("soon", "20:00").contains(time(12, 0)));
+    }
+}
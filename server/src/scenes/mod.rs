@@ -0,0 +1,156 @@
+use std::{collections::HashSet, env, sync::Arc, time::Duration};
+
+use color_eyre::eyre::{eyre, Context, Result};
+use serde::{Deserialize, Serialize};
+use tracing::{debug, info};
+
+use crate::mqtta::{
+    message::{qos_from_u8, PublishMessage},
+    MqttHandle,
+};
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub(crate) struct SceneStep {
+    topic: String,
+    value: String,
+    #[serde(default)]
+    qos: u8,
+    #[serde(default)]
+    retain: bool,
+    /// Wait before publishing this step
+    #[serde(default)]
+    delay_ms: u64,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub(crate) struct Scene {
+    id: String,
+    name: String,
+    steps: Vec<SceneStep>,
+}
+
+impl Scene {
+    /// Topics the steps publish to
+    pub(crate) fn topics(&self) -> Vec<&str> {
+        self.steps.iter().map(|s| s.topic.as_str()).collect()
+    }
+
+    /// Sum of the step delays
+    pub(crate) fn delay(&self) -> Duration {
+        Duration::from_millis(
+            self.steps
+                .iter()
+                .fold(0u64, |sum, s| sum.saturating_add(s.delay_ms)),
+        )
+    }
+}
+
+/// Result of a single step of a scene activation
+#[derive(Debug, Serialize)]
+pub(crate) struct StepResult {
+    index: usize,
+    topic: String,
+    result: String,
+}
+
+impl StepResult {
+    pub(crate) fn ok(&self) -> bool {
+        self.result == "OK"
+    }
+}
+
+/// Scenes as defined in the configuration
+#[derive(Clone, Default)]
+pub(crate) struct Scenes {
+    scenes: Arc<Vec<Scene>>,
+}
+
+impl Scenes {
+    /// Load the scenes from the file named by `HCS_SCENES_CONFIG`.
+    pub(crate) fn from_env() -> Result<Self> {
+        let Ok(path) = env::var("HCS_SCENES_CONFIG") else {
+            return Ok(Self::default());
+        };
+        if path.is_empty() {
+            return Ok(Self::default());
+        }
+        let content = std::fs::read_to_string(&path)
+            .wrap_err_with(|| format!("Cannot read scene configuration {path}"))?;
+        let scenes = serde_json::from_str::<Vec<Scene>>(&content)
+            .wrap_err_with(|| format!("Invalid scene configuration {path}"))?;
+        let mut ids = HashSet::new();
+        for scene in &scenes {
+            if !ids.insert(scene.id.as_str()) {
+                return Err(eyre!("Duplicate scene id {}", scene.id));
+            }
+        }
+        info!(scenes = scenes.len(), "Scenes loaded");
+        Ok(Self {
+            scenes: Arc::new(scenes),
+        })
+    }
+
+    pub(crate) fn list(&self) -> &[Scene] {
+        &self.scenes
+    }
+
+    pub(crate) fn get(&self, id: &str) -> Option<&Scene> {
+        self.scenes.iter().find(|s| s.id == id)
+    }
+
+    /// Publish all steps of a scene in order. Failed steps do not stop the
+    /// remaining steps.
+    pub(crate) async fn activate(&self, mqtt: &MqttHandle, id: &str) -> Option<Vec<StepResult>> {
+        let scene = self.get(id)?;
+        debug!(scene = scene.id, "Activating scene");
+        let mut results = Vec::with_capacity(scene.steps.len());
+        for (index, step) in scene.steps.iter().enumerate() {
+            if step.delay_ms > 0 {
+                tokio::time::sleep(Duration::from_millis(step.delay_ms)).await;
+            }
+            let payload = PublishMessage::builder()
+                .topic(step.topic.clone())
+                .value(step.value.clone().into_bytes())
+                .qos(qos_from_u8(step.qos))
+                .retain(step.retain)
+                .build();
+            results.push(StepResult {
+                index,
+                topic: step.topic.clone(),
+                result: mqtt.publish(payload).await,
+            });
+        }
+        Some(results)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn step(delay_ms: u64) -> SceneStep {
+        SceneStep {
+            topic: String::from("light/set"),
+            value: String::from("ON"),
+            qos: 0,
+            retain: false,
+            delay_ms,
+        }
+    }
+
+    #[test]
+    fn delay_saturates() {
+        let scene = Scene {
+            id: String::from("movie-night"),
+            name: String::from("Movie night"),
+            steps: vec![step(500), step(1500)],
+        };
+        assert_eq!(scene.delay(), Duration::from_secs(2));
+
+        let scene = Scene {
+            steps: vec![step(u64::MAX), step(1)],
+            ..scene
+        };
+        assert_eq!(scene.delay(), Duration::from_millis(u64::MAX));
+    }
+}
@@ -0,0 +1,228 @@
+mod schedule;
+mod sun;
+
+use std::{collections::BTreeMap, env, sync::Arc, time::Duration};
+
+use chrono::{DateTime, Local};
+use color_eyre::eyre::{eyre, Context, Result};
+use rand::distributions::{Alphanumeric, DistString};
+use serde::Serialize;
+use tokio::{
+    sync::{Notify, RwLock},
+    task::JoinHandle,
+};
+use tracing::{debug, info, warn};
+
+pub(crate) use schedule::{Schedule, ScheduleDefinition};
+use sun::Location;
+
+use crate::{
+    datadir::{load_json, save_json},
+    mqtta::MqttHandle,
+    scenes::Scenes,
+};
+
+const SCHEDULES_FILE: &str = "schedules.json";
+/// Upper bound for sleeping, so clock changes are noticed
+const MAX_SLEEP: Duration = Duration::from_secs(60);
+
+/// A schedule with the time it fires next
+#[derive(Serialize)]
+pub(crate) struct ScheduleEntry {
+    #[serde(flatten)]
+    schedule: Schedule,
+    next_fire: Option<DateTime<Local>>,
+}
+
+/// Schedules persisted in the data directory
+#[derive(Clone, Default)]
+pub(crate) struct Scheduler {
+    schedules: Arc<RwLock<BTreeMap<String, Schedule>>>,
+    location: Option<Location>,
+    scenes: Scenes,
+    changed: Arc<Notify>,
+}
+
+fn coordinate(name: &str, limit: f64) -> Result<Option<f64>> {
+    let Ok(value) = env::var(name) else {
+        return Ok(None);
+    };
+    if value.is_empty() {
+        return Ok(None);
+    }
+    let value = value
+        .parse::<f64>()
+        .wrap_err_with(|| format!("Cannot parse {name}"))?;
+    if value.abs() > limit {
+        return Err(eyre!("{name} out of range"));
+    }
+    Ok(Some(value))
+}
+
+/// Location from `HCS_LATITUDE` and `HCS_LONGITUDE`
+fn location_from_env() -> Result<Option<Location>> {
+    match (
+        coordinate("HCS_LATITUDE", 90.0)?,
+        coordinate("HCS_LONGITUDE", 180.0)?,
+    ) {
+        (Some(latitude), Some(longitude)) => Ok(Some(Location {
+            latitude,
+            longitude,
+        })),
+        (None, None) => Ok(None),
+        _ => Err(eyre!("HCS_LATITUDE and HCS_LONGITUDE must be set together")),
+    }
+}
+
+impl Scheduler {
+    pub(crate) fn from_env(scenes: Scenes) -> Result<Self> {
+        let location = location_from_env()?;
+        let schedules: Vec<Schedule> = load_json(SCHEDULES_FILE)?;
+        info!(schedules = schedules.len(), "Schedules loaded");
+        Ok(Self {
+            schedules: Arc::new(RwLock::new(
+                schedules.into_iter().map(|s| (s.id.clone(), s)).collect(),
+            )),
+            location,
+            scenes,
+            changed: Default::default(),
+        })
+    }
+
+    fn entry(&self, schedule: &Schedule, now: DateTime<Local>) -> ScheduleEntry {
+        let next_fire = if schedule.definition.enabled {
+            schedule.definition.trigger.next_after(now, self.location)
+        } else {
+            None
+        };
+        ScheduleEntry {
+            schedule: schedule.clone(),
+            next_fire,
+        }
+    }
+
+    pub(crate) async fn list(&self) -> Vec<ScheduleEntry> {
+        let now = Local::now();
+        self.schedules
+            .read()
+            .await
+            .values()
+            .map(|s| self.entry(s, now))
+            .collect()
+    }
+
+    pub(crate) async fn get(&self, id: &str) -> Option<ScheduleEntry> {
+        let schedules = self.schedules.read().await;
+        schedules.get(id).map(|s| self.entry(s, Local::now()))
+    }
+
+    fn validate(&self, definition: &ScheduleDefinition) -> Result<(), String> {
+        definition.trigger.validate(self.location)?;
+        definition.action.validate(&self.scenes)
+    }
+
+    async fn save(&self, schedules: &BTreeMap<String, Schedule>) {
+        let list: Vec<&Schedule> = schedules.values().collect();
+        if let Err(e) = save_json(SCHEDULES_FILE, &list) {
+            warn!("Cannot save schedules: {:?}", e);
+        }
+        self.changed.notify_one();
+    }
+
+    pub(crate) async fn create(
+        &self,
+        definition: ScheduleDefinition,
+    ) -> Result<ScheduleEntry, String> {
+        self.validate(&definition)?;
+        let schedule = Schedule {
+            id: Alphanumeric.sample_string(&mut rand::thread_rng(), 8),
+            definition,
+        };
+        let mut schedules = self.schedules.write().await;
+        schedules.insert(schedule.id.clone(), schedule.clone());
+        self.save(&schedules).await;
+        Ok(self.entry(&schedule, Local::now()))
+    }
+
+    /// Replace a schedule, `Ok(None)` if it does not exist
+    pub(crate) async fn update(
+        &self,
+        id: &str,
+        definition: ScheduleDefinition,
+    ) -> Result<Option<ScheduleEntry>, String> {
+        self.validate(&definition)?;
+        let mut schedules = self.schedules.write().await;
+        let Some(schedule) = schedules.get_mut(id) else {
+            return Ok(None);
+        };
+        schedule.definition = definition;
+        let entry = self.entry(schedule, Local::now());
+        self.save(&schedules).await;
+        Ok(Some(entry))
+    }
+
+    pub(crate) async fn delete(&self, id: &str) -> bool {
+        let mut schedules = self.schedules.write().await;
+        let removed = schedules.remove(id).is_some();
+        if removed {
+            self.save(&schedules).await;
+        }
+        removed
+    }
+
+    /// Start the actions of all schedules that were due in `(since, now]`
+    /// and return the time of the next fire.
+    async fn fire_due(
+        &self,
+        mqtt: &MqttHandle,
+        since: DateTime<Local>,
+        now: DateTime<Local>,
+    ) -> Option<DateTime<Local>> {
+        let schedules: Vec<Schedule> = self.schedules.read().await.values().cloned().collect();
+        for schedule in schedules.iter().filter(|s| s.definition.enabled) {
+            let due = schedule
+                .definition
+                .trigger
+                .next_after(since, self.location)
+                .is_some_and(|t| t <= now);
+            if due {
+                // spawned, a scene with delays must not hold up other schedules
+                let (mqtt, scenes) = (mqtt.clone(), self.scenes.clone());
+                let (id, action) = (schedule.id.clone(), schedule.definition.action.clone());
+                tokio::spawn(async move {
+                    let result = action.run(&mqtt, &scenes).await;
+                    info!(id, result, "Schedule fired");
+                });
+            }
+        }
+        schedules
+            .iter()
+            .filter(|s| s.definition.enabled)
+            .filter_map(|s| s.definition.trigger.next_after(now, self.location))
+            .min()
+    }
+}
+
+/// Run the schedules until the server stops.
+pub(crate) fn run_scheduler(mqtt: MqttHandle, scheduler: Scheduler) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        debug!("Scheduler started");
+        let mut since = Local::now();
+        loop {
+            let now = Local::now();
+            let next = scheduler.fire_due(&mqtt, since, now).await;
+            since = now;
+            let sleep = match next {
+                Some(n) => (n - Local::now())
+                    .to_std()
+                    .unwrap_or(Duration::ZERO)
+                    .min(MAX_SLEEP),
+                None => MAX_SLEEP,
+            };
+            tokio::select! {
+                _ = tokio::time::sleep(sleep) => {}
+                _ = scheduler.changed.notified() => {}
+            }
+        }
+    })
+}
@@ -0,0 +1,186 @@
+use std::str::FromStr;
+
+use chrono::{DateTime, Duration, Local, Utc};
+use serde::{Deserialize, Serialize};
+
+use super::sun::{sun_time, Location, SunEvent};
+use crate::actions::Action;
+
+/// Days to look ahead for the next sunrise or sunset
+const SUN_LOOKAHEAD_DAYS: i64 = 7;
+/// Largest offset of a sun trigger in either direction, one day
+const MAX_SUN_OFFSET_MINUTES: i64 = 1440;
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub(crate) enum Trigger {
+    /// Cron expression with seconds, e.g. `0 30 7 * * Mon-Fri`. Expressions
+    /// with five fields are run at second 0.
+    Cron { expression: String },
+    Sun {
+        event: SunEvent,
+        #[serde(default)]
+        offset_minutes: i64,
+    },
+}
+
+fn default_enabled() -> bool {
+    true
+}
+
+/// A schedule as accepted by the API, without id
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub(crate) struct ScheduleDefinition {
+    pub(crate) name: Option<String>,
+    #[serde(default = "default_enabled")]
+    pub(crate) enabled: bool,
+    pub(crate) trigger: Trigger,
+    pub(crate) action: Action,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub(crate) struct Schedule {
+    pub(crate) id: String,
+    #[serde(flatten)]
+    pub(crate) definition: ScheduleDefinition,
+}
+
+fn cron_schedule(expression: &str) -> Result<cron::Schedule, String> {
+    let expression = expression.trim();
+    let expression = if expression.split_whitespace().count() == 5 {
+        format!("0 {expression}")
+    } else {
+        expression.to_string()
+    };
+    cron::Schedule::from_str(&expression).map_err(|e| format!("Invalid cron expression: {e}"))
+}
+
+impl Trigger {
+    pub(crate) fn validate(&self, location: Option<Location>) -> Result<(), String> {
+        match self {
+            Trigger::Cron { expression } => cron_schedule(expression).map(|_| ()),
+            Trigger::Sun { .. } if location.is_none() => {
+                Err("Sun triggers need HCS_LATITUDE and HCS_LONGITUDE".to_string())
+            }
+            Trigger::Sun { offset_minutes, .. }
+                if !(-MAX_SUN_OFFSET_MINUTES..=MAX_SUN_OFFSET_MINUTES).contains(offset_minutes) =>
+            {
+                Err(format!(
+                    "offset_minutes must be between -{MAX_SUN_OFFSET_MINUTES} and {MAX_SUN_OFFSET_MINUTES}"
+                ))
+            }
+            Trigger::Sun { .. } => Ok(()),
+        }
+    }
+
+    /// First fire time strictly after `after`, `None` if there is none or it
+    /// is out of range.
+    pub(crate) fn next_after(
+        &self,
+        after: DateTime<Local>,
+        location: Option<Location>,
+    ) -> Option<DateTime<Local>> {
+        match self {
+            Trigger::Cron { expression } => cron_schedule(expression).ok()?.after(&after).next(),
+            Trigger::Sun {
+                event,
+                offset_minutes,
+            } => {
+                let location = location?;
+                let offset = Duration::try_minutes(*offset_minutes)?;
+                let start = after.with_timezone(&Utc).date_naive() - Duration::days(1);
+                (0..=SUN_LOOKAHEAD_DAYS)
+                    .filter_map(|day| sun_time(start + Duration::days(day), location, *event))
+                    .filter_map(|t| t.with_timezone(&Local).checked_add_signed(offset))
+                    .find(|t| *t > after)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{NaiveDate, TimeZone, Timelike};
+
+    use super::*;
+
+    const LONDON: Location = Location {
+        latitude: 51.5074,
+        longitude: -0.1278,
+    };
+
+    fn local(y: i32, m: u32, d: u32, h: u32, min: u32) -> DateTime<Local> {
+        Local
+            .from_local_datetime(
+                &NaiveDate::from_ymd_opt(y, m, d)
+                    .unwrap()
+                    .and_hms_opt(h, min, 0)
+                    .unwrap(),
+            )
+            .earliest()
+            .unwrap()
+    }
+
+    fn cron(expression: &str) -> Trigger {
+        Trigger::Cron {
+            expression: expression.to_string(),
+        }
+    }
+
+    fn sunrise(offset_minutes: i64) -> Trigger {
+        Trigger::Sun {
+            event: SunEvent::Sunrise,
+            offset_minutes,
+        }
+    }
+
+    #[test]
+    fn cron_next_after() {
+        let after = local(2024, 3, 4, 7, 30);
+        let next = cron("0 30 7 * * *").next_after(after, None).unwrap();
+        assert_eq!(next, local(2024, 3, 5, 7, 30));
+        // five fields fire at second 0
+        let next = cron("45 7 * * *").next_after(after, None).unwrap();
+        assert_eq!(next, local(2024, 3, 4, 7, 45));
+        assert_eq!(next.second(), 0);
+        assert!(cron("not cron").next_after(after, None).is_none());
+    }
+
+    #[test]
+    fn sun_next_after() {
+        let after = local(2024, 6, 10, 12, 0);
+        assert!(sunrise(0).next_after(after, None).is_none());
+        let next = sunrise(0).next_after(after, Some(LONDON)).unwrap();
+        assert!(next > after);
+        assert!(next - after < Duration::days(1));
+        let later = sunrise(30).next_after(after, Some(LONDON)).unwrap();
+        assert_eq!(later - next, Duration::minutes(30));
+        // an offset moving today's sunrise before `after` fires tomorrow
+        let early = sunrise(-60)
+            .next_after(next - Duration::minutes(30), Some(LONDON))
+            .unwrap();
+        let expected = next + Duration::days(1) - Duration::minutes(60);
+        assert!((early - expected).abs() < Duration::minutes(5));
+    }
+
+    #[test]
+    fn sun_offset_out_of_range() {
+        assert!(sunrise(1440).validate(Some(LONDON)).is_ok());
+        assert!(sunrise(-1440).validate(Some(LONDON)).is_ok());
+        assert!(sunrise(1441).validate(Some(LONDON)).is_err());
+        assert!(sunrise(i64::MIN).validate(Some(LONDON)).is_err());
+        let after = local(2024, 6, 10, 12, 0);
+        assert!(sunrise(i64::MAX).next_after(after, Some(LONDON)).is_none());
+        assert!(sunrise(i64::MIN / 60_000)
+            .next_after(after, Some(LONDON))
+            .is_none());
+    }
+
+    #[test]
+    fn validate_needs_location() {
+        assert!(sunrise(0).validate(None).is_err());
+        assert!(sunrise(0).validate(Some(LONDON)).is_ok());
+        assert!(cron("0 0 * * *").validate(None).is_ok());
+        assert!(cron("every day").validate(None).is_err());
+    }
+}
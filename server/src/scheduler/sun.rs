@@ -0,0 +1,116 @@
+use chrono::{DateTime, NaiveDate, Utc};
+use serde::{Deserialize, Serialize};
+
+const UNIX_EPOCH_JULIAN_DAY: f64 = 2440587.5;
+const J2000: f64 = 2451545.0;
+
+#[derive(Clone, Copy, Debug, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum SunEvent {
+    Sunrise,
+    Sunset,
+}
+
+/// Geographic position used for the astronomical calculations
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct Location {
+    pub(crate) latitude: f64,
+    /// Degrees east of Greenwich
+    pub(crate) longitude: f64,
+}
+
+/// Time of sunrise or sunset at the given date, computed with the sunrise
+/// equation. Returns `None` during polar day or polar night.
+pub(crate) fn sun_time(
+    date: NaiveDate,
+    location: Location,
+    event: SunEvent,
+) -> Option<DateTime<Utc>> {
+    let midnight = date.and_hms_opt(0, 0, 0)?.and_utc().timestamp() as f64;
+    let julian_day = midnight / 86400.0 + UNIX_EPOCH_JULIAN_DAY;
+    let n = (julian_day - J2000 + 0.0008).ceil();
+
+    let mean_solar_time = n - location.longitude / 360.0;
+    let mean_anomaly = (357.5291 + 0.98560028 * mean_solar_time).rem_euclid(360.0);
+    let m = mean_anomaly.to_radians();
+    let center = 1.9148 * m.sin() + 0.0200 * (2.0 * m).sin() + 0.0003 * (3.0 * m).sin();
+    let ecliptic_longitude = (mean_anomaly + center + 180.0 + 102.9372).rem_euclid(360.0);
+    let lambda = ecliptic_longitude.to_radians();
+    let transit = J2000 + mean_solar_time + 0.0053 * m.sin() - 0.0069 * (2.0 * lambda).sin();
+
+    let declination = (lambda.sin() * 23.4397_f64.to_radians().sin()).asin();
+    let latitude = location.latitude.to_radians();
+    let cos_hour_angle = ((-0.833_f64).to_radians().sin() - latitude.sin() * declination.sin())
+        / (latitude.cos() * declination.cos());
+    if !(-1.0..=1.0).contains(&cos_hour_angle) {
+        return None;
+    }
+    let hour_angle = cos_hour_angle.acos().to_degrees();
+
+    let julian = match event {
+        SunEvent::Sunrise => transit - hour_angle / 360.0,
+        SunEvent::Sunset => transit + hour_angle / 360.0,
+    };
+    let seconds = (julian - UNIX_EPOCH_JULIAN_DAY) * 86400.0;
+    DateTime::from_timestamp(seconds.round() as i64, 0)
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{NaiveDateTime, TimeDelta};
+
+    use super::*;
+
+    const LONDON: Location = Location {
+        latitude: 51.5074,
+        longitude: -0.1278,
+    };
+    const TROMSO: Location = Location {
+        latitude: 69.6492,
+        longitude: 18.9553,
+    };
+
+    fn date(y: i32, m: u32, d: u32) -> NaiveDate {
+        NaiveDate::from_ymd_opt(y, m, d).unwrap()
+    }
+
+    fn assert_near(actual: Option<DateTime<Utc>>, expected: &str) {
+        let expected = NaiveDateTime::parse_from_str(expected, "%Y-%m-%d %H:%M")
+            .unwrap()
+            .and_utc();
+        let delta = (actual.unwrap() - expected).abs();
+        assert!(
+            delta < TimeDelta::minutes(3),
+            "{actual:?} is not near {expected}"
+        );
+    }
+
+    #[test]
+    fn london_solstices() {
+        let summer = date(2024, 6, 21);
+        assert_near(
+            sun_time(summer, LONDON, SunEvent::Sunrise),
+            "2024-06-21 03:43",
+        );
+        assert_near(
+            sun_time(summer, LONDON, SunEvent::Sunset),
+            "2024-06-21 20:21",
+        );
+        let winter = date(2024, 12, 21);
+        assert_near(
+            sun_time(winter, LONDON, SunEvent::Sunrise),
+            "2024-12-21 08:04",
+        );
+        assert_near(
+            sun_time(winter, LONDON, SunEvent::Sunset),
+            "2024-12-21 15:53",
+        );
+    }
+
+    #[test]
+    fn polar_day_and_night() {
+        assert!(sun_time(date(2024, 6, 21), TROMSO, SunEvent::Sunrise).is_none());
+        assert!(sun_time(date(2024, 12, 21), TROMSO, SunEvent::Sunset).is_none());
+        assert!(sun_time(date(2024, 3, 20), TROMSO, SunEvent::Sunrise).is_some());
+    }
+}
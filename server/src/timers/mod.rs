@@ -0,0 +1,221 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    sync::Arc,
+    time::Duration,
+};
+
+use color_eyre::eyre::Result;
+use rand::distributions::{Alphanumeric, DistString};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use tokio::{
+    sync::{broadcast, Mutex, RwLock},
+    task::JoinHandle,
+};
+use tracing::{debug, info, warn};
+
+use crate::{
+    actions::Action,
+    datadir::{load_json, now_millis, save_json},
+    mqtta::MqttHandle,
+    scenes::Scenes,
+};
+
+const TIMERS_FILE: &str = "timers.json";
+/// Longest delay accepted for a timer
+const MAX_DELAY_SECONDS: u64 = 7 * 24 * 60 * 60;
+const COUNTDOWN_INTERVAL: Duration = Duration::from_secs(1);
+
+/// A timer as accepted by the API
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub(crate) struct TimerRequest {
+    name: Option<String>,
+    delay_seconds: u64,
+    action: Action,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub(crate) struct Timer {
+    id: String,
+    name: Option<String>,
+    /// Creation time in milliseconds since the unix epoch
+    created: u64,
+    /// Time the timer fires in milliseconds since the unix epoch
+    due: u64,
+    action: Action,
+}
+
+/// A timer with the time left until it fires
+#[derive(Serialize)]
+pub(crate) struct TimerEntry {
+    #[serde(flatten)]
+    timer: Timer,
+    remaining_ms: u64,
+}
+
+impl Timer {
+    fn entry(&self) -> TimerEntry {
+        TimerEntry {
+            timer: self.clone(),
+            remaining_ms: self.due.saturating_sub(now_millis()),
+        }
+    }
+}
+
+/// One shot timers persisted in the data directory. Events are sent as JSON
+/// to WebSocket clients that asked for them.
+#[derive(Clone)]
+pub(crate) struct Timers {
+    mqtt: MqttHandle,
+    scenes: Scenes,
+    timers: Arc<RwLock<BTreeMap<String, Timer>>>,
+    tasks: Arc<Mutex<HashMap<String, JoinHandle<()>>>>,
+    events: broadcast::Sender<Arc<String>>,
+}
+
+/// Restore the persisted timers, timers that became due while the server was
+/// down fire right away.
+pub(crate) async fn run_timers(
+    mqtt: MqttHandle,
+    scenes: Scenes,
+) -> Result<(Timers, JoinHandle<()>)> {
+    let stored: Vec<Timer> = load_json(TIMERS_FILE)?;
+    info!(timers = stored.len(), "Timers loaded");
+    let (events, _) = broadcast::channel(64);
+    let timers = Timers {
+        mqtt,
+        scenes,
+        timers: Arc::new(RwLock::new(
+            stored.iter().map(|t| (t.id.clone(), t.clone())).collect(),
+        )),
+        tasks: Default::default(),
+        events,
+    };
+    for timer in stored {
+        timers.start(timer).await;
+    }
+
+    let countdown = timers.clone();
+    let task = tokio::spawn(async move {
+        let mut interval = tokio::time::interval(COUNTDOWN_INTERVAL);
+        loop {
+            interval.tick().await;
+            if countdown.events.receiver_count() == 0 {
+                continue;
+            }
+            for timer in countdown.timers.read().await.values() {
+                countdown.notify("countdown", timer, None);
+            }
+        }
+    });
+    Ok((timers, task))
+}
+
+impl TimerRequest {
+    pub(crate) fn action(&self) -> &Action {
+        &self.action
+    }
+}
+
+impl Timers {
+    pub(crate) async fn list(&self) -> Vec<TimerEntry> {
+        self.timers
+            .read()
+            .await
+            .values()
+            .map(Timer::entry)
+            .collect()
+    }
+
+    pub(crate) async fn get(&self, id: &str) -> Option<TimerEntry> {
+        self.timers.read().await.get(id).map(Timer::entry)
+    }
+
+    /// Timer events as JSON
+    pub(crate) fn subscribe(&self) -> broadcast::Receiver<Arc<String>> {
+        self.events.subscribe()
+    }
+
+    fn notify(&self, event: &str, timer: &Timer, result: Option<&str>) {
+        let mut message = json!({
+            "type": "timer",
+            "event": event,
+            "timer": timer.entry(),
+        });
+        if let Some(result) = result {
+            message["result"] = json!(result);
+        }
+        // no receivers is not an error
+        let _ = self.events.send(Arc::new(message.to_string()));
+    }
+
+    async fn save(&self, timers: &BTreeMap<String, Timer>) {
+        let list: Vec<&Timer> = timers.values().collect();
+        if let Err(e) = save_json(TIMERS_FILE, &list) {
+            warn!("Cannot save timers: {:?}", e);
+        }
+    }
+
+    pub(crate) async fn create(&self, request: TimerRequest) -> Result<TimerEntry, String> {
+        if request.delay_seconds == 0 || request.delay_seconds > MAX_DELAY_SECONDS {
+            return Err(format!(
+                "delay_seconds must be between 1 and {MAX_DELAY_SECONDS}"
+            ));
+        }
+        request.action.validate(&self.scenes)?;
+        let created = now_millis();
+        let timer = Timer {
+            id: Alphanumeric.sample_string(&mut rand::thread_rng(), 8),
+            name: request.name,
+            created,
+            due: created + request.delay_seconds * 1000,
+            action: request.action,
+        };
+        {
+            let mut timers = self.timers.write().await;
+            timers.insert(timer.id.clone(), timer.clone());
+            self.save(&timers).await;
+        }
+        self.start(timer.clone()).await;
+        self.notify("created", &timer, None);
+        Ok(timer.entry())
+    }
+
+    pub(crate) async fn cancel(&self, id: &str) -> bool {
+        let Some(timer) = self.remove(id).await else {
+            return false;
+        };
+        if let Some(task) = self.tasks.lock().await.remove(id) {
+            task.abort();
+        }
+        debug!(id, "Timer cancelled");
+        self.notify("cancelled", &timer, None);
+        true
+    }
+
+    async fn remove(&self, id: &str) -> Option<Timer> {
+        let mut timers = self.timers.write().await;
+        let timer = timers.remove(id)?;
+        self.save(&timers).await;
+        Some(timer)
+    }
+
+    async fn start(&self, timer: Timer) {
+        let mut tasks = self.tasks.lock().await;
+        let timers = self.clone();
+        let id = timer.id.clone();
+        let task = tokio::spawn(async move {
+            let delay = timer.due.saturating_sub(now_millis());
+            tokio::time::sleep(Duration::from_millis(delay)).await;
+            // a concurrent cancel already removed the timer
+            if timers.remove(&timer.id).await.is_none() {
+                return;
+            }
+            timers.tasks.lock().await.remove(&timer.id);
+            let result = timer.action.run(&timers.mqtt, &timers.scenes).await;
+            info!(id = timer.id, result, "Timer fired");
+            timers.notify("fired", &timer, Some(&result));
+        });
+        tasks.insert(id, task);
+    }
+}
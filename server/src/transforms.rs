@@ -0,0 +1,331 @@
+use std::{collections::BTreeMap, env, sync::Arc};
+
+use color_eyre::eyre::{eyre, Context, Result};
+use serde::Deserialize;
+use serde_json::Value;
+use tracing::info;
+
+use crate::jsonpath::lookup;
+
+/// Units that can be converted into each other, as dimension, factor and
+/// offset to the base unit of the dimension.
+const UNITS: &[(&str, &str, f64, f64)] = &[
+    ("C", "temperature", 1.0, 0.0),
+    ("F", "temperature", 5.0 / 9.0, -160.0 / 9.0),
+    ("K", "temperature", 1.0, -273.15),
+    ("W", "power", 1.0, 0.0),
+    ("kW", "power", 1000.0, 0.0),
+    ("Wh", "energy", 1.0, 0.0),
+    ("kWh", "energy", 1000.0, 0.0),
+    ("Pa", "pressure", 1.0, 0.0),
+    ("hPa", "pressure", 100.0, 0.0),
+    ("mbar", "pressure", 100.0, 0.0),
+    ("kPa", "pressure", 1000.0, 0.0),
+    ("bar", "pressure", 100000.0, 0.0),
+    ("mm", "length", 0.001, 0.0),
+    ("cm", "length", 0.01, 0.0),
+    ("m", "length", 1.0, 0.0),
+    ("km", "length", 1000.0, 0.0),
+];
+
+fn unit(name: &str) -> Option<(&'static str, f64, f64)> {
+    UNITS
+        .iter()
+        .find(|(unit, ..)| *unit == name)
+        .map(|(_, dimension, factor, offset)| (*dimension, *factor, *offset))
+}
+
+#[derive(Clone, Debug, Deserialize)]
+struct UnitConversion {
+    from: String,
+    to: String,
+}
+
+impl UnitConversion {
+    fn validate(&self) -> Result<()> {
+        let (from, _, _) = unit(&self.from).ok_or_else(|| eyre!("Unknown unit {}", self.from))?;
+        let (to, _, _) = unit(&self.to).ok_or_else(|| eyre!("Unknown unit {}", self.to))?;
+        if from != to {
+            return Err(eyre!("Cannot convert {} to {}", self.from, self.to));
+        }
+        Ok(())
+    }
+
+    fn convert(value: f64, from: &str, to: &str) -> f64 {
+        match (unit(from), unit(to)) {
+            (Some((_, from_factor, from_offset)), Some((_, to_factor, to_offset))) => {
+                (value * from_factor + from_offset - to_offset) / to_factor
+            }
+            _ => value,
+        }
+    }
+}
+
+/// Conversion of the payloads of the topics matching a filter. The steps run
+/// in the order JSON path, value map, scale and offset, unit conversion and
+/// rounding.
+#[derive(Clone, Debug, Deserialize)]
+struct Transform {
+    filter: String,
+    /// Filter of the command topics whose published values are converted
+    /// back, e.g. `cmnd/+/setpoint` for a state topic `stat/+/setpoint`
+    command: Option<String>,
+    path: Option<String>,
+    /// Raw value to displayed value, e.g. `"0x01": "open"`
+    map: Option<BTreeMap<String, String>>,
+    scale: Option<f64>,
+    offset: Option<f64>,
+    unit: Option<UnitConversion>,
+    /// Number of decimals
+    round: Option<u32>,
+}
+
+/// Parse a number, integers with `0x` prefix are read as hex.
+fn parse_value(text: &str) -> Option<f64> {
+    let text = text.trim();
+    match text.strip_prefix("0x").or_else(|| text.strip_prefix("0X")) {
+        Some(hex) => i64::from_str_radix(hex, 16).ok().map(|n| n as f64),
+        None => text.parse::<f64>().ok(),
+    }
+}
+
+/// Decimals kept without `round`, drops float noise such as
+/// `214.99999999999997`
+const MAX_DECIMALS: i32 = 9;
+
+fn format_value(value: f64, decimals: Option<u32>) -> String {
+    match decimals {
+        Some(d) => format!("{:.*}", d as usize, value),
+        None => {
+            let factor = 10f64.powi(MAX_DECIMALS);
+            // `+ 0.0` turns -0 into 0
+            ((value * factor).round() / factor + 0.0).to_string()
+        }
+    }
+}
+
+impl Transform {
+    fn validate(&self) -> Result<()> {
+        if !rumqttc::valid_filter(&self.filter) {
+            return Err(eyre!("Invalid topic filter {}", self.filter));
+        }
+        if let Some(command) = self.command.as_ref().filter(|c| !rumqttc::valid_filter(c)) {
+            return Err(eyre!("Invalid command topic filter {command}"));
+        }
+        if self.scale == Some(0.0) {
+            return Err(eyre!("Scale of {} cannot be 0", self.filter));
+        }
+        if let Some(unit) = &self.unit {
+            unit.validate()?;
+        }
+        Ok(())
+    }
+
+    fn is_numeric(&self) -> bool {
+        self.scale.is_some() || self.offset.is_some() || self.unit.is_some() || self.round.is_some()
+    }
+
+    fn forward(&self, payload: &str) -> String {
+        let text = match self.path.as_deref().filter(|p| !p.is_empty()) {
+            None => payload.to_string(),
+            Some(path) => {
+                let Ok(parsed) = serde_json::from_str::<Value>(payload) else {
+                    return payload.to_string();
+                };
+                match lookup(&parsed, path) {
+                    Some(Value::String(s)) => s.clone(),
+                    Some(v) => v.to_string(),
+                    None => return payload.to_string(),
+                }
+            }
+        };
+        if let Some(mapped) = self.map.as_ref().and_then(|m| m.get(text.trim())) {
+            return mapped.clone();
+        }
+        if !self.is_numeric() {
+            return text;
+        }
+        let Some(mut value) = parse_value(&text) else {
+            return text;
+        };
+        value = value * self.scale.unwrap_or(1.0) + self.offset.unwrap_or(0.0);
+        if let Some(unit) = &self.unit {
+            value = UnitConversion::convert(value, &unit.from, &unit.to);
+        }
+        format_value(value, self.round)
+    }
+
+    /// Undo map, unit conversion, offset and scale. JSON paths and rounding
+    /// cannot be reversed and are left out.
+    fn reverse(&self, value: &str) -> String {
+        if let Some((raw, _)) = self
+            .map
+            .as_ref()
+            .and_then(|m| m.iter().find(|(_, shown)| shown.as_str() == value.trim()))
+        {
+            return raw.clone();
+        }
+        if !self.is_numeric() {
+            return value.to_string();
+        }
+        let Some(mut number) = parse_value(value) else {
+            return value.to_string();
+        };
+        if let Some(unit) = &self.unit {
+            number = UnitConversion::convert(number, &unit.to, &unit.from);
+        }
+        number = (number - self.offset.unwrap_or(0.0)) / self.scale.unwrap_or(1.0);
+        format_value(number, None)
+    }
+}
+
+/// Transforms from the configuration, the first one matching a topic is used.
+#[derive(Clone, Default)]
+pub(crate) struct Transforms {
+    transforms: Arc<Vec<Transform>>,
+}
+
+impl Transforms {
+    /// Load the transforms from the file named by `HCS_TRANSFORMS_CONFIG`.
+    pub(crate) fn from_env() -> Result<Self> {
+        let Ok(path) = env::var("HCS_TRANSFORMS_CONFIG") else {
+            return Ok(Self::default());
+        };
+        if path.is_empty() {
+            return Ok(Self::default());
+        }
+        let content = std::fs::read_to_string(&path)
+            .wrap_err_with(|| format!("Cannot read transform configuration {path}"))?;
+        let transforms = serde_json::from_str::<Vec<Transform>>(&content)
+            .wrap_err_with(|| format!("Invalid transform configuration {path}"))?;
+        for transform in &transforms {
+            transform.validate()?;
+        }
+        info!(transforms = transforms.len(), "Transforms loaded");
+        Ok(Self {
+            transforms: Arc::new(transforms),
+        })
+    }
+
+    fn find(&self, topic: &str) -> Option<&Transform> {
+        self.transforms
+            .iter()
+            .find(|t| rumqttc::matches(topic, &t.filter))
+    }
+
+    /// Value of a received payload as shown to clients, `None` if no
+    /// transform matches the topic
+    pub(crate) fn apply(&self, topic: &str, payload: &str) -> Option<String> {
+        self.find(topic).map(|transform| transform.forward(payload))
+    }
+
+    /// Raw payload for a value published to a topic matching the `command`
+    /// filter of a transform, other publishes are sent unchanged
+    pub(crate) fn reverse(&self, topic: &str, value: Vec<u8>) -> Vec<u8> {
+        let Some(transform) = self.transforms.iter().find(|t| {
+            t.command
+                .as_ref()
+                .is_some_and(|c| rumqttc::matches(topic, c))
+        }) else {
+            return value;
+        };
+        match String::from_utf8(value) {
+            Ok(text) => transform.reverse(&text).into_bytes(),
+            Err(e) => e.into_bytes(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    fn transform(definition: Value) -> Transform {
+        let transform: Transform = serde_json::from_value(definition).unwrap();
+        transform.validate().unwrap();
+        transform
+    }
+
+    fn transforms(definitions: Value) -> Transforms {
+        Transforms {
+            transforms: Arc::new(serde_json::from_value(definitions).unwrap()),
+        }
+    }
+
+    #[test]
+    fn forward_steps() {
+        let t = transform(json!({"filter": "#", "path": "$.raw", "scale": 0.1, "round": 1}));
+        assert_eq!(t.forward(r#"{"raw":215}"#), "21.5");
+        assert_eq!(t.forward(r#"{"raw":"0x10"}"#), "1.6");
+        // not a number, a missing path or not JSON pass through
+        assert_eq!(t.forward(r#"{"raw":"n/a"}"#), "n/a");
+        assert_eq!(t.forward(r#"{"other":1}"#), r#"{"other":1}"#);
+        assert_eq!(t.forward("215"), "215");
+
+        let t = transform(json!({"filter": "#", "unit": {"from": "F", "to": "C"}}));
+        assert_eq!(t.forward("212"), "100");
+        assert_eq!(t.forward("32"), "0");
+
+        let t = transform(json!({"filter": "#", "map": {"0x01": "open", "0x00": "closed"}}));
+        assert_eq!(t.forward(" 0x01 "), "open");
+        assert_eq!(t.forward("0x02"), "0x02");
+    }
+
+    #[test]
+    fn reverse_steps() {
+        let t = transform(json!({"filter": "#", "scale": 0.1}));
+        assert_eq!(t.reverse("21.5"), "215");
+        assert_eq!(t.reverse("-0"), "0");
+        assert_eq!(t.reverse("warm"), "warm");
+
+        let t = transform(
+            json!({"filter": "#", "scale": 2, "offset": 1, "unit": {"from": "C", "to": "F"}}),
+        );
+        assert_eq!(t.forward("10"), "69.8");
+        assert_eq!(t.reverse("69.8"), "10");
+
+        let t = transform(json!({"filter": "#", "map": {"0x01": "open"}}));
+        assert_eq!(t.reverse("open"), "0x01");
+        assert_eq!(t.reverse("closed"), "closed");
+    }
+
+    #[test]
+    fn invalid_transforms() {
+        let invalid = |definition: Value| {
+            serde_json::from_value::<Transform>(definition)
+                .unwrap()
+                .validate()
+                .is_err()
+        };
+        assert!(invalid(json!({"filter": "a/#/b"})));
+        assert!(invalid(json!({"filter": "#", "command": "a/#/b"})));
+        assert!(invalid(json!({"filter": "#", "scale": 0})));
+        assert!(invalid(
+            json!({"filter": "#", "unit": {"from": "C", "to": "kW"}})
+        ));
+        assert!(invalid(
+            json!({"filter": "#", "unit": {"from": "C", "to": "X"}})
+        ));
+    }
+
+    #[test]
+    fn first_matching_topic() {
+        let transforms = transforms(json!([
+            {"filter": "stat/+/setpoint", "command": "cmnd/+/setpoint", "scale": 0.1},
+            {"filter": "stat/#", "scale": 10},
+        ]));
+        assert_eq!(
+            transforms.apply("stat/hall/setpoint", "215").unwrap(),
+            "21.5"
+        );
+        assert_eq!(transforms.apply("stat/hall/power", "2").unwrap(), "20");
+        assert!(transforms.apply("tele/hall/power", "2").is_none());
+        // only command topics are reversed
+        let reverse = |topic| transforms.reverse(topic, b"21.5".to_vec());
+        assert_eq!(reverse("cmnd/hall/setpoint"), b"215");
+        assert_eq!(reverse("stat/hall/setpoint"), b"21.5");
+        assert_eq!(transforms.reverse("cmnd/hall/setpoint", vec![0xff]), [0xff]);
+    }
+}
@@ -0,0 +1,191 @@
+mod state;
+
+use std::{collections::HashMap, env, sync::Arc, time::Duration};
+
+use rand::distributions::{Alphanumeric, DistString};
+use rumqttc::QoS;
+use serde_json::Value;
+use tokio::{
+    sync::{mpsc, oneshot, Mutex, RwLock},
+    task::JoinHandle,
+};
+use tracing::{debug, info, warn};
+
+use state::ZigbeeState;
+pub(crate) use state::{BridgeInfo, ZigbeeDevice};
+
+use crate::mqtta::{
+    message::{ActorMessage, IncomingMessage, PublishMessage},
+    MqttHandle,
+};
+
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Error of a request to the bridge
+#[derive(Debug)]
+pub(crate) enum ZigbeeError {
+    Disabled,
+    Publish,
+    Timeout,
+    Bridge(String),
+}
+
+type PendingRequests = Arc<Mutex<HashMap<String, oneshot::Sender<Value>>>>;
+
+/// Devices paired with Zigbee2MQTT and access to the bridge requests
+#[derive(Clone, Default)]
+pub(crate) struct ZigbeeBridge {
+    base_topic: Option<String>,
+    mqtt: Option<MqttHandle>,
+    state: Arc<RwLock<ZigbeeState>>,
+    pending: PendingRequests,
+}
+
+impl ZigbeeBridge {
+    pub(crate) async fn devices(&self) -> Vec<ZigbeeDevice> {
+        self.state.read().await.devices.values().cloned().collect()
+    }
+
+    pub(crate) async fn bridge(&self) -> BridgeInfo {
+        self.state.read().await.bridge_info()
+    }
+
+    pub(crate) fn enabled(&self) -> bool {
+        self.base_topic.is_some()
+    }
+
+    /// Topic of a bridge request, `None` if the integration is disabled
+    pub(crate) fn request_topic(&self, path: &str) -> Option<String> {
+        self.base_topic
+            .as_ref()
+            .map(|base_topic| format!("{base_topic}/bridge/request/{path}"))
+    }
+
+    /// Publish to `bridge/request/<path>` and wait for the matching
+    /// `bridge/response/<path>` message.
+    pub(crate) async fn request(&self, path: &str, mut body: Value) -> Result<Value, ZigbeeError> {
+        let (Some(topic), Some(mqtt)) = (self.request_topic(path), &self.mqtt) else {
+            return Err(ZigbeeError::Disabled);
+        };
+        let transaction = Alphanumeric.sample_string(&mut rand::thread_rng(), 8);
+        if let Value::Object(map) = &mut body {
+            map.insert("transaction".to_string(), Value::from(transaction.clone()));
+        }
+
+        let (response_tx, response_rx) = oneshot::channel::<Value>();
+        self.pending
+            .lock()
+            .await
+            .insert(transaction.clone(), response_tx);
+
+        let payload = PublishMessage::builder()
+            .topic(topic)
+            .value(body.to_string().into_bytes())
+            .qos(QoS::AtLeastOnce)
+            .retain(false)
+            .build();
+        if mqtt.publish(payload).await != "OK" {
+            self.pending.lock().await.remove(&transaction);
+            return Err(ZigbeeError::Publish);
+        }
+
+        let response = tokio::time::timeout(REQUEST_TIMEOUT, response_rx).await;
+        self.pending.lock().await.remove(&transaction);
+        match response {
+            Ok(Ok(response)) => {
+                if response.get("status").and_then(Value::as_str) == Some("ok") {
+                    Ok(response.get("data").cloned().unwrap_or(Value::Null))
+                } else {
+                    Err(ZigbeeError::Bridge(
+                        response
+                            .get("error")
+                            .and_then(Value::as_str)
+                            .unwrap_or("Unknown error")
+                            .to_string(),
+                    ))
+                }
+            }
+            _ => Err(ZigbeeError::Timeout),
+        }
+    }
+
+    async fn apply(&self, base_topic: &str, message: &IncomingMessage) {
+        let Some(subtopic) = message
+            .topic
+            .strip_prefix(base_topic)
+            .and_then(|t| t.strip_prefix('/'))
+        else {
+            return;
+        };
+
+        if let Some(response) = subtopic.strip_prefix("bridge/response/") {
+            let Ok(payload) = serde_json::from_slice::<Value>(&message.payload) else {
+                warn!(response, "Invalid bridge response");
+                return;
+            };
+            let transaction = payload
+                .get("transaction")
+                .and_then(Value::as_str)
+                .map(str::to_string);
+            if let Some(transaction) = transaction {
+                if let Some(tx) = self.pending.lock().await.remove(&transaction) {
+                    let _ = tx.send(payload);
+                }
+            }
+            return;
+        }
+
+        let mut state = self.state.write().await;
+        match subtopic {
+            "bridge/devices" => state.set_devices(&message.payload),
+            "bridge/state" => state.set_bridge_state(&message.payload),
+            "bridge/event" => state.add_event(&message.payload, message.ts),
+            t if t.starts_with("bridge/") => {}
+            t => {
+                if let Some(name) = t.strip_suffix("/availability") {
+                    state.set_availability(name, &message.payload);
+                } else if !t.ends_with("/set") && !t.ends_with("/get") {
+                    state.set_device_state(t, &message.payload, message.ts);
+                }
+            }
+        }
+    }
+}
+
+/// Follow the topics below `HCS_Z2M_BASE_TOPIC`. The integration is disabled
+/// if the variable is not set.
+pub(crate) fn run_zigbee_bridge(mqtt: MqttHandle) -> (ZigbeeBridge, Option<JoinHandle<()>>) {
+    let Some(base_topic) = env::var("HCS_Z2M_BASE_TOPIC")
+        .ok()
+        .map(|t| t.trim_end_matches('/').to_string())
+        .filter(|t| !t.is_empty())
+    else {
+        return (ZigbeeBridge::default(), None);
+    };
+
+    info!(base_topic, "Zigbee2MQTT integration enabled");
+
+    let bridge = ZigbeeBridge {
+        base_topic: Some(base_topic.clone()),
+        mqtt: Some(mqtt.clone()),
+        ..Default::default()
+    };
+    let loopbridge = bridge.clone();
+    let task = tokio::spawn(async move {
+        let (tx, rx) = oneshot::channel::<mpsc::Receiver<Arc<IncomingMessage>>>();
+        mqtt.send(ActorMessage::Stream {
+            filter: format!("{base_topic}/#"),
+            respond_to: tx,
+        })
+        .await;
+        let Ok(mut messages) = rx.await else {
+            warn!(base_topic, "Could not subscribe to Zigbee2MQTT topics");
+            return;
+        };
+        while let Some(message) = messages.recv().await {
+            loopbridge.apply(&base_topic, &message).await;
+        }
+        debug!("Zigbee2MQTT integration stopped");
+    });
+    (bridge, Some(task))
+}
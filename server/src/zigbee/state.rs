@@ -0,0 +1,246 @@
+use std::collections::{BTreeMap, VecDeque};
+
+use serde::Serialize;
+use serde_json::Value;
+use tracing::{debug, warn};
+
+const MAX_EVENTS: usize = 50;
+
+/// A device paired with the Zigbee2MQTT bridge
+#[derive(Clone, Debug, Serialize)]
+pub(crate) struct ZigbeeDevice {
+    pub(crate) ieee_address: String,
+    pub(crate) friendly_name: String,
+    #[serde(rename = "type")]
+    pub(crate) device_type: Option<String>,
+    pub(crate) model: Option<String>,
+    pub(crate) vendor: Option<String>,
+    pub(crate) description: Option<String>,
+    pub(crate) supported: bool,
+    pub(crate) interview_completed: bool,
+    /// Features of the device as announced by the bridge
+    pub(crate) exposes: Value,
+    pub(crate) availability: Option<String>,
+    pub(crate) linkquality: Option<u64>,
+    /// Time of the last state message in milliseconds since the unix epoch
+    pub(crate) last_seen: Option<u64>,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub(crate) struct BridgeEvent {
+    ts: u64,
+    event: Value,
+}
+
+#[derive(Serialize)]
+pub(crate) struct BridgeInfo {
+    state: Option<String>,
+    devices: usize,
+    events: Vec<BridgeEvent>,
+}
+
+#[derive(Default)]
+pub(super) struct ZigbeeState {
+    bridge_state: Option<String>,
+    /// Devices by IEEE address
+    pub(super) devices: BTreeMap<String, ZigbeeDevice>,
+    events: VecDeque<BridgeEvent>,
+}
+
+/// Read `online` from either a plain payload or `{"state":"online"}`
+fn state_text(payload: &[u8]) -> Option<String> {
+    match serde_json::from_slice::<Value>(payload) {
+        Ok(Value::Object(map)) => map.get("state").and_then(Value::as_str).map(str::to_string),
+        _ => String::from_utf8(payload.to_vec()).ok(),
+    }
+}
+
+fn text(value: &Value, key: &str) -> Option<String> {
+    value.get(key).and_then(Value::as_str).map(str::to_string)
+}
+
+impl ZigbeeState {
+    pub(super) fn bridge_info(&self) -> BridgeInfo {
+        BridgeInfo {
+            state: self.bridge_state.clone(),
+            devices: self.devices.len(),
+            events: self.events.iter().cloned().collect(),
+        }
+    }
+
+    fn by_name(&mut self, friendly_name: &str) -> Option<&mut ZigbeeDevice> {
+        self.devices
+            .values_mut()
+            .find(|d| d.friendly_name == friendly_name)
+    }
+
+    /// Replace the device list from a `bridge/devices` message, keeping the
+    /// availability and link quality that were already known.
+    pub(super) fn set_devices(&mut self, payload: &[u8]) {
+        let Ok(Value::Array(list)) = serde_json::from_slice::<Value>(payload) else {
+            warn!("Invalid bridge/devices payload");
+            return;
+        };
+        let mut devices = BTreeMap::new();
+        for entry in list {
+            let Some(ieee_address) = text(&entry, "ieee_address") else {
+                continue;
+            };
+            if text(&entry, "type").as_deref() == Some("Coordinator") {
+                continue;
+            }
+            let previous = self.devices.remove(&ieee_address);
+            let definition = entry.get("definition").cloned().unwrap_or(Value::Null);
+            let device = ZigbeeDevice {
+                friendly_name: text(&entry, "friendly_name")
+                    .unwrap_or_else(|| ieee_address.clone()),
+                device_type: text(&entry, "type"),
+                model: text(&definition, "model"),
+                vendor: text(&definition, "vendor"),
+                description: text(&definition, "description"),
+                supported: entry
+                    .get("supported")
+                    .and_then(Value::as_bool)
+                    .unwrap_or(false),
+                interview_completed: entry
+                    .get("interview_completed")
+                    .and_then(Value::as_bool)
+                    .unwrap_or(false),
+                exposes: definition
+                    .get("exposes")
+                    .cloned()
+                    .unwrap_or(Value::Array(Vec::new())),
+                availability: previous.as_ref().and_then(|p| p.availability.clone()),
+                linkquality: previous.as_ref().and_then(|p| p.linkquality),
+                last_seen: previous.as_ref().and_then(|p| p.last_seen),
+                ieee_address: ieee_address.clone(),
+            };
+            devices.insert(ieee_address, device);
+        }
+        debug!(devices = devices.len(), "Zigbee device list updated");
+        self.devices = devices;
+    }
+
+    pub(super) fn set_bridge_state(&mut self, payload: &[u8]) {
+        self.bridge_state = state_text(payload);
+    }
+
+    pub(super) fn add_event(&mut self, payload: &[u8], ts: u64) {
+        let Ok(event) = serde_json::from_slice::<Value>(payload) else {
+            return;
+        };
+        if text(&event, "type").as_deref() == Some("device_leave") {
+            if let Some(ieee) = event.get("data").and_then(|d| text(d, "ieee_address")) {
+                self.devices.remove(&ieee);
+            }
+        }
+        if self.events.len() >= MAX_EVENTS {
+            self.events.pop_front();
+        }
+        self.events.push_back(BridgeEvent { ts, event });
+    }
+
+    pub(super) fn set_availability(&mut self, friendly_name: &str, payload: &[u8]) {
+        let availability = state_text(payload);
+        if let Some(device) = self.by_name(friendly_name) {
+            device.availability = availability;
+        }
+    }
+
+    pub(super) fn set_device_state(&mut self, friendly_name: &str, payload: &[u8], ts: u64) {
+        let Some(device) = self.by_name(friendly_name) else {
+            return;
+        };
+        device.last_seen = Some(ts);
+        if let Ok(state) = serde_json::from_slice::<Value>(payload) {
+            if let Some(lqi) = state.get("linkquality").and_then(Value::as_u64) {
+                device.linkquality = Some(lqi);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    fn devices(list: Value) -> Vec<u8> {
+        list.to_string().into_bytes()
+    }
+
+    fn state() -> ZigbeeState {
+        let mut state = ZigbeeState::default();
+        state.set_devices(&devices(json!([
+            {"ieee_address": "0x00", "type": "Coordinator"},
+            {
+                "ieee_address": "0x01",
+                "friendly_name": "lamp",
+                "type": "Router",
+                "supported": true,
+                "interview_completed": true,
+                "definition": {"model": "LED1545G12", "vendor": "IKEA", "exposes": [{"type": "light"}]},
+            },
+            {"ieee_address": "0x02"},
+            {"friendly_name": "no address"},
+        ])));
+        state
+    }
+
+    #[test]
+    fn device_list() {
+        let state = state();
+        assert_eq!(state.devices.keys().collect::<Vec<_>>(), ["0x01", "0x02"]);
+        let lamp = &state.devices["0x01"];
+        assert_eq!(lamp.friendly_name, "lamp");
+        assert_eq!(lamp.vendor.as_deref(), Some("IKEA"));
+        assert!(lamp.supported && lamp.interview_completed);
+        assert_eq!(lamp.exposes, json!([{"type": "light"}]));
+        let unknown = &state.devices["0x02"];
+        assert_eq!(unknown.friendly_name, "0x02");
+        assert!(!unknown.supported);
+        assert_eq!(unknown.exposes, json!([]));
+    }
+
+    #[test]
+    fn device_list_keeps_availability() {
+        let mut state = state();
+        state.set_availability("lamp", br#"{"state":"online"}"#);
+        state.set_device_state("lamp", br#"{"linkquality":87,"state":"ON"}"#, 5);
+        state.set_device_state("lamp", br#"{"state":"OFF"}"#, 6);
+        state.set_device_state("unknown", br#"{"linkquality":1}"#, 7);
+        state.set_devices(&devices(
+            json!([{"ieee_address": "0x01", "friendly_name": "lamp"}]),
+        ));
+        let lamp = &state.devices["0x01"];
+        assert_eq!(lamp.availability.as_deref(), Some("online"));
+        assert_eq!(lamp.linkquality, Some(87));
+        assert_eq!(lamp.last_seen, Some(6));
+        assert_eq!(state.devices.len(), 1);
+        // an invalid list leaves the devices as they are
+        state.set_devices(b"{}");
+        assert_eq!(state.devices.len(), 1);
+    }
+
+    #[test]
+    fn bridge_state_and_events() {
+        let mut state = state();
+        state.set_bridge_state(b"online");
+        assert_eq!(state.bridge_state.as_deref(), Some("online"));
+        state.set_bridge_state(br#"{"state":"offline"}"#);
+        assert_eq!(state.bridge_state.as_deref(), Some("offline"));
+
+        let leave = json!({"type": "device_leave", "data": {"ieee_address": "0x02"}});
+        state.add_event(leave.to_string().as_bytes(), 1);
+        assert!(!state.devices.contains_key("0x02"));
+        state.add_event(b"not json", 2);
+        for ts in 0..MAX_EVENTS as u64 {
+            state.add_event(br#"{"type":"device_announce"}"#, 10 + ts);
+        }
+        let info = state.bridge_info();
+        assert_eq!(info.devices, 1);
+        assert_eq!(info.events.len(), MAX_EVENTS);
+        assert_eq!(info.events[0].ts, 10);
+    }
+}